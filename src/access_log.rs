@@ -0,0 +1,495 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use signal_hook::consts::SIGUSR1;
+use signal_hook::iterator::Signals;
+
+use crate::log;
+
+/// This is the access log every response is recorded in, if there is one.
+static CURRENT: RwLock<Option<AccessLog>> = RwLock::new(None);
+
+/// This is the abbreviated name of every month, as used by the Common Log Format.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// This enum holds the formats a response can be recorded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The Common Log Format, e.g. `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1"
+    /// 200 2326`
+    Common,
+    /// The Common Log Format, followed by the quoted `Referer` and `User-Agent`
+    Combined,
+    /// A JSON object per line, which also holds the host and how long the response took
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Format, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "common" | "clf" => Ok(Format::Common),
+            "combined" => Ok(Format::Combined),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "{:?} is no access log format, use common, combined or json",
+                format
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Common => "common",
+            Format::Combined => "combined",
+            Format::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// This enum holds where the access log is written to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// Responses are not recorded
+    Off,
+    /// Responses are printed to stdout, written as `-`
+    Stdout,
+    /// Responses are appended to a file, which is reopened on SIGUSR1
+    File(PathBuf),
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(destination: &str) -> Result<Destination, String> {
+        match destination {
+            "" => Err("the access log needs a path, - for stdout or off".to_string()),
+            "off" => Ok(Destination::Off),
+            "-" => Ok(Destination::Stdout),
+            path => Ok(Destination::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// This struct holds everything recorded about a response
+#[derive(Debug)]
+pub struct Entry<'a> {
+    /// The address of the client
+    pub remote: Option<IpAddr>,
+    /// When the request was received
+    pub time: SystemTime,
+    /// The host the request was sent to
+    pub host: Option<&'a str>,
+    /// The request line, split into the method, the target and the protocol. It is `None` for
+    /// requests that could not be parsed.
+    pub request: Option<(&'a str, &'a str, &'a str)>,
+    /// The status code of the response
+    pub status: u16,
+    /// The amount of body bytes sent
+    pub bytes: u64,
+    /// The `Referer` of the request
+    pub referer: Option<&'a str>,
+    /// The `User-Agent` of the request
+    pub user_agent: Option<&'a str>,
+    /// How long it took to answer the request
+    pub duration: Duration,
+}
+
+impl Format {
+    /// This function formats an entry as a line of the access log
+    ///
+    /// # Parameters
+    ///
+    /// - `entry`: This is the response that is recorded
+    ///
+    /// # Returns
+    ///
+    /// Returns the line, without the line break
+    pub fn format(&self, entry: &Entry) -> String {
+        let remote = entry
+            .remote
+            .map_or_else(|| "-".to_string(), |remote| remote.to_string());
+        match self {
+            Format::Common | Format::Combined => {
+                let request = match entry.request {
+                    Some((method, target, protocol)) => {
+                        format!("{} {} {}", quoted(method), quoted(target), quoted(protocol))
+                    }
+                    None => "-".to_string(),
+                };
+                let bytes = match entry.bytes {
+                    0 => "-".to_string(),
+                    bytes => bytes.to_string(),
+                };
+                let mut line = format!(
+                    "{} - - [{}] \"{}\" {} {}",
+                    remote,
+                    common_time(entry.time),
+                    request,
+                    entry.status,
+                    bytes
+                );
+                if *self == Format::Combined {
+                    let field = |value: Option<&str>| value.map_or("-".to_string(), quoted);
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        field(entry.referer),
+                        field(entry.user_agent)
+                    ));
+                }
+                line
+            }
+            Format::Json => {
+                let (method, path, protocol) = match entry.request {
+                    Some((method, target, protocol)) => {
+                        (Some(method), Some(target), Some(protocol))
+                    }
+                    None => (None, None, None),
+                };
+                format!(
+                    "{{\"time\":\"{}\",\"remote\":{},\"host\":{},\"method\":{},\"path\":{},\
+                     \"protocol\":{},\"status\":{},\"bytes\":{},\"referer\":{},\
+                     \"user_agent\":{},\"duration_ms\":{:.3}}}",
+                    iso_time(entry.time),
+                    json(entry.remote.map(|_| remote.as_str())),
+                    json(entry.host),
+                    json(method),
+                    json(path),
+                    json(protocol),
+                    entry.status,
+                    entry.bytes,
+                    json(entry.referer),
+                    json(entry.user_agent),
+                    entry.duration.as_secs_f64() * 1000.0
+                )
+            }
+        }
+    }
+}
+
+/// This function escapes a value sent by the client for a quoted field of the Common Log
+/// Format, the way Apache does, so it cannot end the field or forge a line
+fn quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// This function writes a value as a JSON string, or `null` if there is none
+fn json(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "null".to_string();
+    };
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// This function splits a point in time into its date and time of day in UTC
+///
+/// # Returns
+///
+/// Returns the year, the month from `1` to `12`, the day, and the hours, minutes and seconds
+fn civil_time(time: SystemTime) -> (i64, usize, u64, u64, u64, u64) {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // This converts the days since 1970-01-01 to a date, counted in eras of 400 years
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u64;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as usize;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+    )
+}
+
+/// This function formats a point in time like `10/Oct/2000:13:55:36 +0000`
+fn common_time(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil_time(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month - 1],
+        year,
+        hours,
+        minutes,
+        seconds
+    )
+}
+
+/// This function formats a point in time like `2000-10-10T13:55:36Z`
+fn iso_time(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil_time(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hours, minutes, seconds
+    )
+}
+
+/// This is where the lines of an access log are written to
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File { path: PathBuf, file: Mutex<File> },
+}
+
+/// This struct holds an opened access log
+#[derive(Debug)]
+pub struct AccessLog {
+    format: Format,
+    output: Output,
+}
+
+impl AccessLog {
+    /// This opens an access log
+    ///
+    /// # Parameters
+    ///
+    /// - `destination`: This is where the responses are recorded
+    /// - `format`: This is the format of every line
+    ///
+    /// # Returns
+    ///
+    /// Returns the `AccessLog`, or `None` if responses are not recorded
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the file cannot be opened for appending
+    pub fn open(destination: &Destination, format: Format) -> io::Result<Option<AccessLog>> {
+        let output = match destination {
+            Destination::Off => return Ok(None),
+            Destination::Stdout => Output::Stdout,
+            Destination::File(path) => Output::File {
+                path: path.clone(),
+                file: Mutex::new(append(path)?),
+            },
+        };
+        Ok(Some(AccessLog { format, output }))
+    }
+
+    /// This function records a response
+    pub fn write(&self, entry: &Entry) {
+        let mut line = self.format.format(entry);
+        line.push('\n');
+        let written = match &self.output {
+            Output::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Output::File { file, .. } => match file.lock() {
+                Ok(mut file) => file.write_all(line.as_bytes()),
+                Err(poisoned) => poisoned.into_inner().write_all(line.as_bytes()),
+            },
+        };
+        if let Err(e) = written {
+            log::warning!("Failed to write the access log: {}", e);
+        }
+    }
+
+    /// This function opens the file of the access log again, so a file that was moved away by
+    /// log rotation is replaced by a new one
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the file cannot be opened, in which case the old one is kept
+    pub fn reopen(&self) -> io::Result<()> {
+        if let Output::File { path, file } = &self.output {
+            let reopened = append(path)?;
+            match file.lock() {
+                Ok(mut file) => *file = reopened,
+                Err(poisoned) => *poisoned.into_inner() = reopened,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// This function opens a file for appending, creating it if it does not exist
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// This function replaces the access log every response is recorded in
+pub fn set(access_log: Option<AccessLog>) {
+    match CURRENT.write() {
+        Ok(mut current) => *current = access_log,
+        Err(poisoned) => *poisoned.into_inner() = access_log,
+    }
+}
+
+/// This function records a response in the access log, if there is one
+pub fn record(entry: &Entry) {
+    let current = match CURRENT.read() {
+        Ok(current) => current,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(access_log) = current.as_ref() {
+        access_log.write(entry);
+    }
+}
+
+/// This function reopens the file of the access log whenever the process receives SIGUSR1, which
+/// is how log rotation tells the server that it moved the file away
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the signal handler cannot be registered
+pub fn reopen_on_signal() -> io::Result<()> {
+    let mut signals = Signals::new([SIGUSR1])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            let current = match CURRENT.read() {
+                Ok(current) => current,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if let Some(access_log) = current.as_ref() {
+                match access_log.reopen() {
+                    Ok(()) => log::info!("Reopened the access log"),
+                    Err(e) => log::error!("Failed to reopen the access log: {}", e),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry<'static> {
+        Entry {
+            remote: Some(IpAddr::from([127, 0, 0, 1])),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(971_186_136),
+            host: Some("example.test"),
+            request: Some(("GET", "/a \"b\"?c", "HTTP/1.1")),
+            status: 200,
+            bytes: 2326,
+            referer: None,
+            user_agent: Some("curl/8.0\n"),
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn test_formats_entries() {
+        assert_eq!(
+            Format::Common.format(&entry()),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a \\\"b\\\"?c HTTP/1.1\" 200 2326"
+        );
+        assert_eq!(
+            Format::Combined.format(&entry()),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /a \\\"b\\\"?c HTTP/1.1\" 200 2326 \
+             \"-\" \"curl/8.0\\x0a\""
+        );
+        assert_eq!(
+            Format::Json.format(&entry()),
+            "{\"time\":\"2000-10-10T13:55:36Z\",\"remote\":\"127.0.0.1\",\
+             \"host\":\"example.test\",\"method\":\"GET\",\"path\":\"/a \\\"b\\\"?c\",\
+             \"protocol\":\"HTTP/1.1\",\"status\":200,\"bytes\":2326,\"referer\":null,\
+             \"user_agent\":\"curl/8.0\\n\",\"duration_ms\":1.500}"
+        );
+
+        let unparsed = Entry {
+            request: None,
+            bytes: 0,
+            ..entry()
+        };
+        assert!(Format::Common.format(&unparsed).ends_with("] \"-\" 200 -"));
+    }
+
+    #[test]
+    fn test_converts_dates() {
+        let date = |seconds| iso_time(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(date(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(date(4_102_444_800), "2100-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_parses_settings() {
+        assert_eq!("CLF".parse::<Format>(), Ok(Format::Common));
+        assert_eq!("json".parse::<Format>(), Ok(Format::Json));
+        assert!("xml".parse::<Format>().is_err());
+        assert_eq!("-".parse::<Destination>(), Ok(Destination::Stdout));
+        assert_eq!("off".parse::<Destination>(), Ok(Destination::Off));
+        assert_eq!(
+            "access.log".parse::<Destination>(),
+            Ok(Destination::File(PathBuf::from("access.log")))
+        );
+    }
+
+    #[test]
+    fn test_reopens_a_rotated_file() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-access-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("access.log");
+        let access_log = AccessLog::open(&Destination::File(path.clone()), Format::Common)
+            .unwrap()
+            .unwrap();
+
+        access_log.write(&entry());
+        std::fs::rename(&path, directory.join("access.log.1")).unwrap();
+        access_log.write(&entry());
+        access_log.reopen().unwrap();
+        access_log.write(&entry());
+
+        let lines = |name| std::fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(lines("access.log.1").lines().count(), 2);
+        assert_eq!(lines("access.log").lines().count(), 1);
+        assert!(AccessLog::open(&Destination::Off, Format::Json)
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
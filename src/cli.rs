@@ -0,0 +1,989 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use toml::Spanned;
+
+use crate::access_log::{self, AccessLog, Destination};
+use crate::config::{Config, ConfigError, LimitsBlock, SiteOverrides, DEFAULT_CONFIG_FILE};
+use crate::http::{RequestLimits, Service, SiteConfig, VirtualHost, VirtualHosts};
+use crate::log::{LogFilter, LogFormat};
+use crate::metrics::{self, DEFAULT_METRICS_PATH};
+use crate::tcp;
+use crate::tls::{self, TlsSettings};
+
+/// This is the address listened on when nothing else is configured.
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+/// This is the port used for addresses without one when nothing else is configured.
+const DEFAULT_PORT: u16 = 7878;
+/// This is the port used for HTTPS addresses without one when nothing else is configured.
+const DEFAULT_TLS_PORT: u16 = 8443;
+/// This is the port used for metrics addresses without one.
+const DEFAULT_METRICS_PORT: u16 = 9464;
+
+/// A small HTTP server for static files.
+///
+/// Every option can also be set through the environment variable shown next to it, or a `.env`
+/// file, and in the configuration file. Options on the command line win over the environment,
+/// which wins over the configuration file.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "anes-http", version, about)]
+pub struct Cli {
+    /// Address to listen on, as `host` or `host:port`. Repeat it, or separate the addresses with
+    /// commas, to listen on several addresses.
+    #[arg(
+        short,
+        long = "address",
+        value_name = "ADDRESS",
+        env = "TCP_ADDRESS",
+        value_delimiter = ','
+    )]
+    pub addresses: Vec<String>,
+    /// Port used for addresses without one [default: 7878]
+    #[arg(short, long, env = "PORT")]
+    pub port: Option<u16>,
+    /// Address to listen on with HTTPS, as `host` or `host:port`. Repeat it, or separate the
+    /// addresses with commas, to listen on several addresses.
+    #[arg(
+        long = "tls-address",
+        value_name = "ADDRESS",
+        env = "TLS_ADDRESS",
+        value_delimiter = ','
+    )]
+    pub tls_addresses: Vec<String>,
+    /// Port used for HTTPS addresses without one [default: 8443]
+    #[arg(long, value_name = "PORT", env = "TLS_PORT")]
+    pub tls_port: Option<u16>,
+    /// PEM file with the certificate chain of the default server
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "TLS_CERTIFICATE",
+        requires = "private_key"
+    )]
+    pub certificate: Option<PathBuf>,
+    /// PEM file with the private key of the certificate
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "TLS_PRIVATE_KEY",
+        requires = "certificate"
+    )]
+    pub private_key: Option<PathBuf>,
+    /// Address to serve only the metrics on, as `host` or `host:port` [default port: 9464]. Repeat
+    /// it, or separate the addresses with commas, to listen on several addresses.
+    #[arg(
+        long = "metrics-address",
+        value_name = "ADDRESS",
+        env = "METRICS_ADDRESS",
+        value_delimiter = ','
+    )]
+    pub metrics_addresses: Vec<String>,
+    /// Path the metrics are served at in the Prometheus text format. Without a metrics address,
+    /// every plain HTTP listener serves them at this path. [default: /metrics on metrics
+    /// addresses only]
+    #[arg(long, value_name = "PATH", env = "METRICS_PATH", value_parser = parse_metrics_path)]
+    pub metrics_path: Option<String>,
+    /// Generate `.br` and `.gz` files next to every compressible file served, on start and on
+    /// every reload, to send instead of compressing while sending. `--precompress=false` turns
+    /// it off. [default: false]
+    #[arg(
+        long,
+        value_name = "BOOL",
+        env = "PRECOMPRESS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub precompress: Option<bool>,
+    /// Redirect every request to the plain addresses to the first HTTPS address
+    #[arg(long, env = "REDIRECT_TO_HTTPS")]
+    pub redirect_to_https: bool,
+    /// Directory the files of the default server are served from [default: public]
+    #[arg(
+        short = 'r',
+        long = "root",
+        value_name = "DIRECTORY",
+        env = "DOCUMENT_ROOT"
+    )]
+    pub document_root: Option<PathBuf>,
+    /// Directory of the error pages of the default server, named after their status code like
+    /// `404.html` [default: the document root]
+    #[arg(long, value_name = "DIRECTORY", env = "ERROR_PAGES")]
+    pub error_pages: Option<PathBuf>,
+    /// Index file names of the default server, tried in order when a directory is requested and
+    /// separated by commas [default: index.*]
+    #[arg(
+        long = "index",
+        value_name = "NAMES",
+        env = "INDEX_FILES",
+        value_delimiter = ','
+    )]
+    pub index_files: Vec<String>,
+    /// Level messages are printed up to: error, warn, info, debug or trace, optionally followed
+    /// by levels for single modules like `info,anes_http::tcp=debug` [default: info]
+    #[arg(short, long, value_name = "FILTER", env = "LOG_LEVEL")]
+    pub log_level: Option<LogFilter>,
+    /// Format messages are printed to stderr in: compact or json [default: compact]
+    #[arg(long, value_name = "FORMAT", env = "LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+    /// Amount of worker threads serving requests, which idle keep-alive connections do not occupy
+    /// [default: the amount of CPU cores]
+    #[arg(short, long, env = "WORKERS", value_parser = parse_workers)]
+    pub workers: Option<usize>,
+    /// Configuration file [default: anes-http.toml, if it exists]
+    #[arg(short, long, value_name = "FILE", env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+    /// Check the configuration, including its directories, and exit
+    #[arg(long)]
+    pub check_config: bool,
+    /// Reload the configuration and certificates when they change, checking every SECONDS. They
+    /// are always reloaded on SIGHUP.
+    #[arg(long, value_name = "SECONDS", env = "WATCH_INTERVAL", value_parser = parse_interval)]
+    pub watch: Option<Duration>,
+    /// Seconds open connections get to finish their requests when the server is stopped by
+    /// SIGINT or SIGTERM [default: 30]
+    #[arg(long, value_name = "SECONDS", env = "SHUTDOWN_TIMEOUT", value_parser = parse_seconds)]
+    pub shutdown_timeout: Option<Duration>,
+    /// Bytes the header section of a request may take, request line included [default: 16384]
+    #[arg(long, value_name = "BYTES", env = "MAX_HEADER_SIZE", value_parser = parse_limit)]
+    pub max_header_size: Option<usize>,
+    /// Bytes a single header line may take [default: 8192]
+    #[arg(long, value_name = "BYTES", env = "MAX_HEADER_LINE_SIZE", value_parser = parse_limit)]
+    pub max_header_line_size: Option<usize>,
+    /// Amount of header fields a request may have [default: 100]
+    #[arg(long, value_name = "COUNT", env = "MAX_HEADER_COUNT", value_parser = parse_limit)]
+    pub max_header_count: Option<usize>,
+    /// Bytes the request target may take [default: 8192]
+    #[arg(long, value_name = "BYTES", env = "MAX_TARGET_LENGTH", value_parser = parse_limit)]
+    pub max_target_length: Option<usize>,
+    /// Bytes the body of a request may take [default: 1048576]
+    #[arg(long, value_name = "BYTES", env = "MAX_BODY_SIZE", value_parser = parse_limit)]
+    pub max_body_size: Option<usize>,
+    /// Seconds the header section of a request, or a TLS handshake, may take to arrive once it
+    /// started, answered with 408 when it runs out [default: 10]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "HEADER_TIMEOUT",
+        value_parser = parse_timeout
+    )]
+    pub header_timeout: Option<Duration>,
+    /// Seconds a keep-alive connection may sit idle between two requests [default: 5]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "KEEP_ALIVE_TIMEOUT",
+        value_parser = parse_timeout
+    )]
+    pub keep_alive_timeout: Option<Duration>,
+    /// Amount of requests served over one connection before it is closed [default: 100]
+    #[arg(
+        long,
+        value_name = "COUNT",
+        env = "MAX_KEEP_ALIVE_REQUESTS",
+        value_parser = parse_limit
+    )]
+    pub max_keep_alive_requests: Option<usize>,
+    /// File every response is recorded in, `-` for stdout or `off`. The file is reopened on
+    /// SIGUSR1. [default: -]
+    #[arg(long, value_name = "FILE", env = "ACCESS_LOG")]
+    pub access_log: Option<Destination>,
+    /// Format of the access log: common, combined or json [default: common]
+    #[arg(long, value_name = "FORMAT", env = "ACCESS_LOG_FORMAT")]
+    pub access_log_format: Option<access_log::Format>,
+}
+
+/// This describes why the settings cannot be used
+#[derive(Debug)]
+pub enum SettingsError {
+    /// The configuration file cannot be read or is not valid
+    Config(ConfigError),
+    /// A setting has a value that cannot be used
+    Invalid(String),
+    /// The directories of the site cannot be used
+    Site(crate::http::SiteError),
+    /// The certificate or the protocol settings cannot be used
+    Tls(tls::TlsError),
+}
+
+impl From<ConfigError> for SettingsError {
+    fn from(error: ConfigError) -> SettingsError {
+        SettingsError::Config(error)
+    }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Config(error) => write!(f, "{}", error),
+            SettingsError::Invalid(message) => write!(f, "{}", message),
+            SettingsError::Site(error) => write!(f, "{}", error),
+            SettingsError::Tls(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// This struct holds the settings the server is started with, after the command line, the
+/// environment and the configuration file were combined
+#[derive(Debug)]
+pub struct Settings {
+    /// The listeners, each with the address it binds to and how it answers connections
+    pub listeners: Vec<Listener>,
+    /// The amount of worker threads serving requests
+    pub workers: usize,
+    /// The amount of retries when binding a listener fails
+    pub bind_retries: u8,
+    /// The filter deciding which messages are printed
+    pub log_level: LogFilter,
+    /// The format messages are printed in
+    pub log_format: LogFormat,
+    /// The sites the files are served from, picked by the host of each request
+    pub hosts: Arc<VirtualHosts>,
+    /// The configuration file and the certificates the settings were read from
+    pub files: Vec<PathBuf>,
+    /// How often the files are checked for changes, if they are watched
+    pub watch: Option<Duration>,
+    /// How long open connections get to finish when the server stops
+    pub shutdown_timeout: Duration,
+    /// The access log every response is recorded in, if there is one
+    pub access_log: Option<AccessLog>,
+    /// The path the listeners serving files also serve the metrics at. It is `None` if the
+    /// metrics are not served or there are listeners that only serve them.
+    pub metrics_path: Option<String>,
+    /// Whether the precompressed siblings of the served files are generated
+    pub precompress: bool,
+    /// The limits every request and connection have to stay within
+    pub limits: RequestLimits,
+}
+
+/// This struct holds a listener of the server
+#[derive(Debug)]
+pub struct Listener {
+    /// The address, with a port
+    pub address: String,
+    /// How the connections of the listener are answered
+    pub service: Service,
+}
+
+/// This is the kind of a listener, before the configuration of the HTTPS listeners is built
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Http,
+    Https,
+    RedirectToHttps,
+    Metrics,
+}
+
+impl Settings {
+    /// This combines the command line, which already holds the values of the environment, with
+    /// the configuration file and the defaults
+    ///
+    /// # Parameters
+    ///
+    /// - `cli`: This is the parsed command line
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Settings` the server is started with
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SettingsError` if the configuration file is invalid or a setting cannot be used
+    pub fn resolve(cli: Cli) -> Result<Settings, SettingsError> {
+        let config = match &cli.config {
+            Some(path) => Some(Config::read(path)?),
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Some(Config::read(Path::new(DEFAULT_CONFIG_FILE))?)
+            }
+            None => None,
+        };
+        let file = config.as_ref().map(|config| &config.file);
+        let limits = request_limits(&cli, file.and_then(|file| file.limits.as_ref()));
+
+        let log_level = match (cli.log_level, file.and_then(|file| file.log_level.as_ref())) {
+            (Some(level), _) => level,
+            (None, Some(level)) => level.get_ref().parse().map_err(SettingsError::Invalid)?,
+            (None, None) => LogFilter::default(),
+        };
+        let log_format = match (
+            cli.log_format,
+            file.and_then(|file| file.log_format.as_ref()),
+        ) {
+            (Some(format), _) => format,
+            (None, Some(format)) => format.get_ref().parse().map_err(SettingsError::Invalid)?,
+            (None, None) => LogFormat::default(),
+        };
+        let workers = cli
+            .workers
+            .or(file.and_then(|file| file.workers.as_ref().map(|workers| *workers.get_ref())))
+            .unwrap_or_else(tcp::default_worker_count);
+        let bind_retries = file
+            .and_then(|file| file.bind_retries)
+            .unwrap_or(tcp::RECONNECT_TRIES);
+        let shutdown_timeout = cli
+            .shutdown_timeout
+            .or(file.and_then(|file| file.shutdown_timeout.map(Duration::from_secs)))
+            .unwrap_or(tcp::SHUTDOWN_TIMEOUT);
+
+        let port = cli
+            .port
+            .or(file.and_then(|file| file.port))
+            .unwrap_or(DEFAULT_PORT);
+        let tls_port = cli
+            .tls_port
+            .or(file.and_then(|file| file.tls_port))
+            .unwrap_or(DEFAULT_TLS_PORT);
+        let plain = if cli.redirect_to_https {
+            Kind::RedirectToHttps
+        } else {
+            Kind::Http
+        };
+        let listen: Vec<(String, Option<u16>, Kind)> = match file {
+            _ if !cli.addresses.is_empty() || !cli.tls_addresses.is_empty() => cli
+                .addresses
+                .iter()
+                .map(|address| (address.clone(), None, plain))
+                .chain(
+                    cli.tls_addresses
+                        .iter()
+                        .map(|address| (address.clone(), None, Kind::Https)),
+                )
+                .collect(),
+            Some(file) if file.listen.iter().any(|listen| !listen.metrics) => file
+                .listen
+                .iter()
+                .filter(|listen| !listen.metrics)
+                .map(|listen| {
+                    let kind = match (listen.tls, listen.redirect_to_https) {
+                        (true, _) => Kind::Https,
+                        (false, true) => Kind::RedirectToHttps,
+                        (false, false) => plain,
+                    };
+                    (listen.address.get_ref().clone(), listen.port, kind)
+                })
+                .collect(),
+            _ => vec![(DEFAULT_ADDRESS.to_string(), None, plain)],
+        };
+        let metrics_listen: Vec<(String, Option<u16>, Kind)> = match file {
+            _ if !cli.metrics_addresses.is_empty() => cli
+                .metrics_addresses
+                .iter()
+                .map(|address| (address.clone(), None, Kind::Metrics))
+                .collect(),
+            Some(file) => file
+                .listen
+                .iter()
+                .filter(|listen| listen.metrics)
+                .map(|listen| (listen.address.get_ref().clone(), listen.port, Kind::Metrics))
+                .collect(),
+            None => Vec::new(),
+        };
+        let listen: Vec<_> = listen.into_iter().chain(metrics_listen).collect();
+        if listen
+            .iter()
+            .any(|(address, _, _)| address.trim().is_empty())
+        {
+            return Err(SettingsError::Invalid(
+                "an address to listen on is empty".to_string(),
+            ));
+        }
+        let addresses: Vec<(String, Kind)> = listen
+            .iter()
+            .map(|(address, own_port, kind)| {
+                let default_port = match kind {
+                    Kind::Https => tls_port,
+                    Kind::Metrics => DEFAULT_METRICS_PORT,
+                    _ => port,
+                };
+                (
+                    with_port(address.trim(), own_port.unwrap_or(default_port)),
+                    *kind,
+                )
+            })
+            .collect();
+
+        let overrides = SiteOverrides {
+            document_root: cli.document_root,
+            error_pages: cli.error_pages,
+            index_files: non_empty(cli.index_files),
+            certificate: cli.certificate,
+            private_key: cli.private_key,
+        };
+        let configured = match &config {
+            Some(config) => config.virtual_hosts(&overrides)?,
+            None => None,
+        };
+        let hosts = match configured {
+            Some(hosts) => hosts,
+            None => {
+                let site = SiteConfig::new(
+                    overrides
+                        .document_root
+                        .as_deref()
+                        .unwrap_or(Path::new(crate::http::DEFAULT_DOCUMENT_ROOT)),
+                    overrides.error_pages.as_deref(),
+                    overrides.index_files.unwrap_or_default(),
+                )
+                .map_err(SettingsError::Site)?;
+                let certificate = match (&overrides.certificate, &overrides.private_key) {
+                    (Some(certificate), Some(private_key)) => Some(
+                        tls::load_certificate(certificate, private_key)
+                            .map_err(SettingsError::Tls)?,
+                    ),
+                    _ => None,
+                };
+                VirtualHosts::new(
+                    vec![VirtualHost {
+                        names: Vec::new(),
+                        site,
+                        certificate,
+                    }],
+                    0,
+                )
+            }
+        };
+        let hosts = Arc::new(hosts);
+
+        let mut files: Vec<PathBuf> = overrides
+            .certificate
+            .iter()
+            .chain(&overrides.private_key)
+            .cloned()
+            .collect();
+        if let Some(config) = &config {
+            files.push(config.path().to_path_buf());
+            files.extend(config.certificate_files());
+        }
+
+        let metrics_path = cli.metrics_path.or(file.and_then(|file| {
+            file.metrics_path
+                .as_ref()
+                .map(|path| path.get_ref().clone())
+        }));
+        let has_metrics_listener = addresses.iter().any(|(_, kind)| *kind == Kind::Metrics);
+        let metrics_listener_path = metrics_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_METRICS_PATH.to_string());
+        let metrics_path = metrics_path.filter(|_| !has_metrics_listener);
+
+        let listeners = listeners(
+            addresses,
+            &hosts,
+            &metrics_listener_path,
+            || match &config {
+                Some(config) => Ok(config.tls_settings()?),
+                None => Ok(TlsSettings::default()),
+            },
+        )?;
+
+        let destination = cli
+            .access_log
+            .or(config.as_ref().and_then(Config::access_log))
+            .unwrap_or(Destination::Stdout);
+        let format = match (
+            cli.access_log_format,
+            file.and_then(|file| file.access_log_format.as_ref()),
+        ) {
+            (Some(format), _) => format,
+            (None, Some(format)) => format.get_ref().parse().map_err(SettingsError::Invalid)?,
+            (None, None) => access_log::Format::Common,
+        };
+        let access_log = AccessLog::open(&destination, format).map_err(|e| {
+            let path = match &destination {
+                Destination::File(path) => path.as_path(),
+                _ => Path::new("-"),
+            };
+            SettingsError::Invalid(format!("the access log {:?} cannot be opened: {}", path, e))
+        })?;
+
+        Ok(Settings {
+            listeners,
+            workers,
+            bind_retries,
+            log_level,
+            log_format,
+            hosts,
+            files,
+            watch: cli.watch,
+            shutdown_timeout,
+            access_log,
+            metrics_path,
+            precompress: cli
+                .precompress
+                .or(file.and_then(|file| file.precompress))
+                .unwrap_or(false),
+            limits,
+        })
+    }
+}
+
+/// This function combines the limits of the command line with those of the configuration file,
+/// falling back to the defaults
+///
+/// # Parameters
+///
+/// - `cli`: This is the parsed command line
+/// - `file`: This is the `[limits]` table of the configuration file, if it has one
+///
+/// # Returns
+///
+/// Returns the `RequestLimits` every request and connection have to stay within
+fn request_limits(cli: &Cli, file: Option<&LimitsBlock>) -> RequestLimits {
+    let defaults = RequestLimits::default();
+    let from_file = |limit: fn(&LimitsBlock) -> &Option<Spanned<usize>>| {
+        file.and_then(|file| limit(file).as_ref().map(|limit| *limit.get_ref()))
+    };
+    RequestLimits {
+        max_header_size: cli
+            .max_header_size
+            .or(from_file(|file| &file.max_header_size))
+            .unwrap_or(defaults.max_header_size),
+        max_header_line_size: cli
+            .max_header_line_size
+            .or(from_file(|file| &file.max_header_line_size))
+            .unwrap_or(defaults.max_header_line_size),
+        max_header_count: cli
+            .max_header_count
+            .or(from_file(|file| &file.max_header_count))
+            .unwrap_or(defaults.max_header_count),
+        max_target_length: cli
+            .max_target_length
+            .or(from_file(|file| &file.max_target_length))
+            .unwrap_or(defaults.max_target_length),
+        max_body_size: cli
+            .max_body_size
+            .or(from_file(|file| &file.max_body_size))
+            .unwrap_or(defaults.max_body_size),
+        header_timeout: cli
+            .header_timeout
+            .or(file.and_then(|file| {
+                file.header_timeout
+                    .as_ref()
+                    .map(|seconds| Duration::from_secs(*seconds.get_ref()))
+            }))
+            .unwrap_or(defaults.header_timeout),
+        keep_alive_timeout: cli
+            .keep_alive_timeout
+            .or(file.and_then(|file| {
+                file.keep_alive_timeout
+                    .as_ref()
+                    .map(|seconds| Duration::from_secs(*seconds.get_ref()))
+            }))
+            .unwrap_or(defaults.keep_alive_timeout),
+        max_keep_alive_requests: cli
+            .max_keep_alive_requests
+            .or(from_file(|file| &file.max_keep_alive_requests))
+            .unwrap_or(defaults.max_keep_alive_requests),
+    }
+}
+
+/// This function builds the listeners, with the configuration of the HTTPS listeners if there
+/// are any
+///
+/// # Parameters
+///
+/// - `addresses`: This are the addresses with their kind
+/// - `hosts`: This are the sites, whose certificates are presented by the HTTPS listeners
+/// - `metrics_path`: This is the path the metrics listeners serve the metrics at
+/// - `tls_settings`: This returns the protocol settings of the HTTPS listeners
+///
+/// # Errors
+///
+/// Returns a `SettingsError` if there is an HTTPS listener but no certificate, the plain
+/// listeners redirect but there is no HTTPS listener, or the protocol settings cannot be used
+fn listeners(
+    addresses: Vec<(String, Kind)>,
+    hosts: &Arc<VirtualHosts>,
+    metrics_path: &str,
+    tls_settings: impl FnOnce() -> Result<TlsSettings, SettingsError>,
+) -> Result<Vec<Listener>, SettingsError> {
+    let https = addresses
+        .iter()
+        .find(|(_, kind)| *kind == Kind::Https)
+        .map(|(address, _)| address);
+    let https_port = https.and_then(|address| address.rsplit(':').next()?.parse::<u16>().ok());
+    let needs_https = addresses
+        .iter()
+        .any(|(_, kind)| *kind == Kind::RedirectToHttps);
+    if needs_https && https_port.is_none() {
+        return Err(SettingsError::Invalid(
+            "redirecting to HTTPS needs an HTTPS listener".to_string(),
+        ));
+    }
+    let tls_config = match https {
+        Some(_) if !hosts.has_certificate() => {
+            return Err(SettingsError::Invalid(
+                "listening with HTTPS needs a certificate".to_string(),
+            ))
+        }
+        Some(_) => Some(
+            tls::server_config(&tls_settings()?, Arc::clone(hosts)).map_err(SettingsError::Tls)?,
+        ),
+        None => None,
+    };
+
+    Ok(addresses
+        .into_iter()
+        .map(|(address, kind)| {
+            let service = match (kind, &tls_config, https_port) {
+                (Kind::Https, Some(config), _) => Service::Https(Arc::clone(config)),
+                (Kind::RedirectToHttps, _, Some(port)) => Service::RedirectToHttps(port),
+                (Kind::Metrics, _, _) => Service::Metrics(metrics_path.to_string()),
+                _ => Service::Http,
+            };
+            Listener { address, service }
+        })
+        .collect())
+}
+
+/// This function turns an empty list, which is what clap leaves for options that were not given,
+/// into `None`
+fn non_empty(values: Vec<String>) -> Option<Vec<String>> {
+    (!values.is_empty()).then_some(values)
+}
+
+/// This function adds the port to an address that does not have one yet
+///
+/// # Parameters
+///
+/// - `address`: This is a host name or ip address, optionally with a port
+/// - `port`: This is the port added if the address has none
+///
+/// # Returns
+///
+/// Returns the address in the `host:port` form `TcpListener::bind()` expects
+fn with_port(address: &str, port: u16) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return address.to_string();
+    }
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        Ok(IpAddr::V4(ip)) => format!("{}:{}", ip, port),
+        // A host name with a port
+        Err(_) if address.contains(':') => address.to_string(),
+        Err(_) => format!("{}:{}", address, port),
+    }
+}
+
+/// This function parses the interval the files are checked for changes in, which has to be at
+/// least one second
+fn parse_interval(seconds: &str) -> Result<Duration, String> {
+    match seconds.parse::<u64>() {
+        Ok(0) => Err("the interval has to be at least one second".to_string()),
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// This function parses a timeout in whole seconds, which has to be at least one second
+fn parse_timeout(seconds: &str) -> Result<Duration, String> {
+    match seconds.parse::<u64>() {
+        Ok(0) => Err("the timeout has to be at least one second".to_string()),
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// This function parses a limit of a request or connection, which has to be at least one
+fn parse_limit(limit: &str) -> Result<usize, String> {
+    match limit.parse::<usize>() {
+        Ok(0) => Err("the limit has to be at least 1".to_string()),
+        Ok(limit) => Ok(limit),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// This function parses a duration in whole seconds, which may be zero
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    seconds
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|e| e.to_string())
+}
+
+/// This function parses the path the metrics are served at, which has to start with a slash
+fn parse_metrics_path(path: &str) -> Result<String, String> {
+    metrics::check_path(path).map(|()| path.to_string())
+}
+
+/// This function parses the amount of workers, which has to be at least one
+fn parse_workers(workers: &str) -> Result<usize, String> {
+    match workers.parse::<usize>() {
+        Ok(0) => Err("at least one worker is needed".to_string()),
+        Ok(workers) => Ok(workers),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("anes-http").chain(args.iter().copied())).unwrap()
+    }
+
+    fn addresses(settings: &Settings) -> Vec<&str> {
+        settings
+            .listeners
+            .iter()
+            .map(|listener| listener.address.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_adds_ports_to_addresses() {
+        assert_eq!(with_port("127.0.0.1", 80), "127.0.0.1:80");
+        assert_eq!(with_port("127.0.0.1:8080", 80), "127.0.0.1:8080");
+        assert_eq!(with_port("::1", 80), "[::1]:80");
+        assert_eq!(with_port("[::1]:8080", 80), "[::1]:8080");
+        assert_eq!(with_port("localhost", 80), "localhost:80");
+        assert_eq!(with_port("localhost:8080", 80), "localhost:8080");
+    }
+
+    #[test]
+    fn test_command_line_wins_over_the_config_file() {
+        let directory = std::env::temp_dir().join(format!("anes-http-cli-{}", std::process::id()));
+        fs::create_dir_all(directory.join("www")).unwrap();
+        let config = directory.join("anes-http.toml");
+        fs::write(
+            &config,
+            "port = 8080\nlog_level = \"debug\"\nlog_format = \"json\"\nworkers = 3\nbind_retries = 1\nshutdown_timeout = 5\n\n\
+             [[listen]]\naddress = \"0.0.0.0\"\n\n[[listen]]\naddress = \"::1\"\nport = 9000\n\n\
+             [[server]]\nroot = \"www\"\n",
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+
+        let settings = Settings::resolve(parse(&["--config", config])).unwrap();
+        assert_eq!(addresses(&settings), vec!["0.0.0.0:8080", "[::1]:9000"]);
+        assert_eq!(settings.workers, 3);
+        assert_eq!(settings.bind_retries, 1);
+        assert_eq!(settings.shutdown_timeout, Duration::from_secs(5));
+        assert_eq!(settings.log_level.to_string(), "debug");
+        assert_eq!(settings.log_format, LogFormat::Json);
+        assert!(settings.hosts.default_site().document_root.ends_with("www"));
+
+        let settings = Settings::resolve(parse(&[
+            "--config",
+            config,
+            "-a",
+            "127.0.0.1,localhost",
+            "-p",
+            "81",
+            "-w",
+            "2",
+            "--shutdown-timeout",
+            "0",
+            "-l",
+            "warn,anes_http::http=debug",
+            "--root",
+            "public",
+        ]))
+        .unwrap();
+        assert_eq!(addresses(&settings), vec!["127.0.0.1:81", "localhost:81"]);
+        assert_eq!(settings.workers, 2);
+        assert_eq!(settings.shutdown_timeout, Duration::ZERO);
+        assert_eq!(settings.log_level.to_string(), "warn,anes_http::http=debug");
+        assert!(settings
+            .hosts
+            .default_site()
+            .document_root
+            .ends_with("public"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_invalid_settings() {
+        assert!(Cli::try_parse_from(["anes-http", "--workers", "0"]).is_err());
+        assert!(Cli::try_parse_from(["anes-http", "--log-level", "loud"]).is_err());
+        assert!(Cli::try_parse_from(["anes-http", "--port", "70000"]).is_err());
+        assert!(Cli::try_parse_from(["anes-http", "--watch", "0"]).is_err());
+        assert!(Cli::try_parse_from(["anes-http", "--access-log-format", "xml"]).is_err());
+        let error =
+            Settings::resolve(parse(&["--access-log", "/does/not/exist/access.log"])).unwrap_err();
+        assert!(error.to_string().contains("access log"));
+
+        let error = Settings::resolve(parse(&["--config", "does-not-exist.toml"])).unwrap_err();
+        assert!(matches!(error, SettingsError::Config(_)));
+
+        let config =
+            std::env::temp_dir().join(format!("anes-http-cli-invalid-{}.toml", std::process::id()));
+        fs::write(&config, "document_rot = \"public\"\n").unwrap();
+        let error = Settings::resolve(parse(&["--config", config.to_str().unwrap()])).unwrap_err();
+        assert!(error.to_string().contains("document_rot"));
+        fs::remove_file(config).unwrap();
+    }
+
+    #[test]
+    fn test_resolves_https_listeners() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-cli-tls-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (certificate, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+        fs::write(&certificate, generated.cert.pem()).unwrap();
+        fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+        let config = directory.join("anes-http.toml");
+        fs::write(&config, "").unwrap();
+        let config = config.to_str().unwrap();
+
+        let error = Settings::resolve(parse(&["-c", config, "--tls-address", "::1"])).unwrap_err();
+        assert!(error.to_string().contains("certificate"));
+        let error = Settings::resolve(parse(&["-c", config, "--redirect-to-https"])).unwrap_err();
+        assert!(error.to_string().contains("HTTPS listener"));
+
+        let settings = Settings::resolve(parse(&[
+            "-c",
+            config,
+            "-a",
+            "127.0.0.1",
+            "--tls-address",
+            "::1",
+            "--redirect-to-https",
+            "--certificate",
+            certificate.to_str().unwrap(),
+            "--private-key",
+            key.to_str().unwrap(),
+        ]))
+        .unwrap();
+        assert_eq!(addresses(&settings), vec!["127.0.0.1:7878", "[::1]:8443"]);
+        assert!(matches!(
+            settings.listeners[0].service,
+            Service::RedirectToHttps(8443)
+        ));
+        assert!(matches!(settings.listeners[1].service, Service::Https(_)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_resolves_the_metrics_listeners() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-cli-metrics-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let config = directory.join("anes-http.toml");
+        fs::write(
+            &config,
+            "metrics_path = \"/stats\"\n\n[[listen]]\naddress = \"127.0.0.1\"\nmetrics = true\n",
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+
+        let settings = Settings::resolve(parse(&["-c", config])).unwrap();
+        assert_eq!(
+            addresses(&settings),
+            vec!["127.0.0.1:7878", "127.0.0.1:9464"]
+        );
+        assert!(matches!(settings.listeners[0].service, Service::Http));
+        assert!(
+            matches!(&settings.listeners[1].service, Service::Metrics(path) if path == "/stats")
+        );
+        assert_eq!(settings.metrics_path, None);
+
+        let settings = Settings::resolve(parse(&[
+            "-c",
+            config,
+            "--metrics-address",
+            "[::1]:9000",
+            "--metrics-path",
+            "/m",
+        ]))
+        .unwrap();
+        assert_eq!(addresses(&settings), vec!["127.0.0.1:7878", "[::1]:9000"]);
+        assert!(matches!(&settings.listeners[1].service, Service::Metrics(path) if path == "/m"));
+
+        fs::write(
+            directory.join("anes-http.toml"),
+            "metrics_path = \"/stats\"\n",
+        )
+        .unwrap();
+        let settings = Settings::resolve(parse(&["-c", config])).unwrap();
+        assert_eq!(addresses(&settings), vec!["127.0.0.1:7878"]);
+        assert_eq!(settings.metrics_path.as_deref(), Some("/stats"));
+        assert!(Cli::try_parse_from(["anes-http", "--metrics-path", "stats"]).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_resolves_precompress() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-cli-precompress-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let config = directory.join("anes-http.toml");
+        fs::write(&config, "precompress = true\n").unwrap();
+        let config = config.to_str().unwrap();
+
+        assert!(!Settings::resolve(parse(&[])).unwrap().precompress);
+        assert!(
+            Settings::resolve(parse(&["--precompress"]))
+                .unwrap()
+                .precompress
+        );
+        assert!(
+            Settings::resolve(parse(&["-c", config]))
+                .unwrap()
+                .precompress
+        );
+        let settings = Settings::resolve(parse(&["-c", config, "--precompress=false"])).unwrap();
+        assert!(!settings.precompress);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_resolves_the_request_limits() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-cli-limits-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let config = directory.join("anes-http.toml");
+        fs::write(
+            &config,
+            "[limits]\nmax_body_size = 64\nkeep_alive_timeout = 2\nmax_keep_alive_requests = 3\n",
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+
+        assert_eq!(
+            Settings::resolve(parse(&[])).unwrap().limits,
+            RequestLimits::default()
+        );
+        let settings = Settings::resolve(parse(&["-c", config, "--max-body-size", "128"])).unwrap();
+        assert_eq!(settings.limits.max_body_size, 128);
+        assert_eq!(settings.limits.keep_alive_timeout, Duration::from_secs(2));
+        assert_eq!(settings.limits.max_keep_alive_requests, 3);
+        assert_eq!(
+            settings.limits.max_header_size,
+            RequestLimits::default().max_header_size
+        );
+
+        for args in [
+            ["--max-header-size", "0"],
+            ["--keep-alive-timeout", "0"],
+            ["--max-keep-alive-requests", "many"],
+        ] {
+            assert!(Cli::try_parse_from(std::iter::once("anes-http").chain(args)).is_err());
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        let error = Cli::try_parse_from(["anes-http", "--version"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::DisplayVersion);
+
+        let help = Cli::try_parse_from(["anes-http", "--help"])
+            .unwrap_err()
+            .to_string();
+        assert!(help.contains("--address"));
+        assert!(help.contains("DOCUMENT_ROOT"));
+    }
+}
@@ -0,0 +1,860 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::sign::CertifiedKey;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::access_log::{self, Destination};
+use crate::http::{self, HostPattern, Location, SiteConfig, SiteError, VirtualHost, VirtualHosts};
+use crate::metrics;
+use crate::tls::{self, TlsError, TlsSettings};
+
+/// This is the configuration file read if it exists and no other one is given.
+pub const DEFAULT_CONFIG_FILE: &str = "anes-http.toml";
+
+/// This describes a problem of the configuration file, with the line it was found on
+#[derive(Debug)]
+pub struct ConfigError {
+    /// The path of the configuration file
+    pub path: PathBuf,
+    /// The line of the problem, counted from one, if it can be pinned down
+    pub line: Option<usize>,
+    /// The description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// This is the top level of the configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// The amount of worker threads
+    pub workers: Option<Spanned<usize>>,
+    /// The filter deciding which messages are printed
+    pub log_level: Option<Spanned<String>>,
+    /// The format messages are printed in
+    pub log_format: Option<Spanned<String>>,
+    /// The amount of retries when binding a listener fails
+    pub bind_retries: Option<u8>,
+    /// The seconds open connections get to finish when the server stops
+    pub shutdown_timeout: Option<u64>,
+    /// Where responses are recorded: a file, `-` for stdout or `off`
+    pub access_log: Option<Spanned<String>>,
+    /// The format responses are recorded in
+    pub access_log_format: Option<Spanned<String>>,
+    /// The path the metrics are served at
+    pub metrics_path: Option<Spanned<String>>,
+    /// Whether the precompressed siblings of the served files are generated
+    pub precompress: Option<bool>,
+    /// The port of every plain listener without one
+    pub port: Option<u16>,
+    /// The port of every HTTPS listener without one
+    pub tls_port: Option<u16>,
+    /// The protocol settings of the HTTPS listeners
+    pub tls: Option<TlsBlock>,
+    /// The limits of every request and connection
+    pub limits: Option<LimitsBlock>,
+    /// The addresses listened on
+    #[serde(default)]
+    pub listen: Vec<ListenBlock>,
+    /// The sites served
+    #[serde(default)]
+    pub server: Vec<Spanned<ServerBlock>>,
+}
+
+/// This is a `[[listen]]` block
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenBlock {
+    /// The host name or ip address, optionally with a port
+    pub address: Spanned<String>,
+    /// The port, if the address has none
+    pub port: Option<u16>,
+    /// Whether the listener speaks HTTPS
+    #[serde(default)]
+    pub tls: bool,
+    /// Whether the plain listener redirects every request to the first HTTPS listener
+    #[serde(default)]
+    pub redirect_to_https: bool,
+    /// Whether the listener only serves the metrics
+    #[serde(default)]
+    pub metrics: bool,
+}
+
+/// This is the `[tls]` table
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsBlock {
+    versions: Option<Vec<Spanned<String>>>,
+    cipher_suites: Option<Vec<Spanned<String>>>,
+    alpn: Option<Vec<Spanned<String>>>,
+}
+
+/// This is the `[limits]` table
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsBlock {
+    /// The size of the whole header section, in bytes
+    pub max_header_size: Option<Spanned<usize>>,
+    /// The length of a single header line, in bytes
+    pub max_header_line_size: Option<Spanned<usize>>,
+    /// The amount of header fields
+    pub max_header_count: Option<Spanned<usize>>,
+    /// The length of the request target, in bytes
+    pub max_target_length: Option<Spanned<usize>>,
+    /// The size of a request body, in bytes
+    pub max_body_size: Option<Spanned<usize>>,
+    /// The seconds the header section of a request or a TLS handshake may take to arrive
+    pub header_timeout: Option<Spanned<u64>>,
+    /// The seconds an idle keep-alive connection is kept open
+    pub keep_alive_timeout: Option<Spanned<u64>>,
+    /// The amount of requests served over one connection
+    pub max_keep_alive_requests: Option<Spanned<usize>>,
+}
+
+/// This is a `[[server]]` block
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerBlock {
+    #[serde(default)]
+    names: Vec<Spanned<String>>,
+    #[serde(default)]
+    default: bool,
+    certificate: Option<Spanned<PathBuf>>,
+    private_key: Option<Spanned<PathBuf>>,
+    root: Option<Spanned<PathBuf>>,
+    error_pages: Option<Spanned<PathBuf>>,
+    index_files: Option<Spanned<Vec<String>>>,
+    compression: Option<bool>,
+    headers: Option<Spanned<BTreeMap<String, String>>>,
+    cache_control: Option<Spanned<String>>,
+    #[serde(default)]
+    location: Vec<Spanned<LocationBlock>>,
+}
+
+/// This is a `[[server.location]]` block
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocationBlock {
+    path: Spanned<String>,
+    root: Option<Spanned<PathBuf>>,
+    index_files: Option<Spanned<Vec<String>>>,
+    compression: Option<bool>,
+    headers: Option<Spanned<BTreeMap<String, String>>>,
+    cache_control: Option<Spanned<String>>,
+}
+
+/// This are the settings given on the command line or in the environment that win over those of
+/// the server blocks
+#[derive(Debug, Default)]
+pub struct SiteOverrides {
+    pub document_root: Option<PathBuf>,
+    pub error_pages: Option<PathBuf>,
+    pub index_files: Option<Vec<String>>,
+    pub certificate: Option<PathBuf>,
+    pub private_key: Option<PathBuf>,
+}
+
+/// This is a parsed configuration file, which still knows its source so problems found later can
+/// be reported with their line
+#[derive(Debug)]
+pub struct Config {
+    path: PathBuf,
+    source: String,
+    /// The settings of the file
+    pub file: FileConfig,
+}
+
+impl Config {
+    /// This reads and parses a configuration file
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: This is the path of the file
+    ///
+    /// # Returns
+    ///
+    /// Returns the parsed `Config`
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if the file cannot be read, is no valid TOML or has settings of the
+    /// wrong type or unknown settings
+    pub fn read(path: &Path) -> Result<Config, ConfigError> {
+        let source = fs::read_to_string(path).map_err(|e| ConfigError {
+            path: path.to_path_buf(),
+            line: None,
+            message: e.to_string(),
+        })?;
+        Config::parse(path, source)
+    }
+
+    /// This parses the content of a configuration file
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: This is the path of the file, which relative paths are resolved against
+    /// - `source`: This is the content of the file
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if the content is no valid TOML or has settings of the wrong type
+    /// or unknown settings
+    pub fn parse(path: &Path, source: String) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            path: path.to_path_buf(),
+            source,
+            file: FileConfig::default(),
+        };
+        config.file = toml::from_str(&config.source).map_err(|e| {
+            let message = e.message().trim_end().to_string();
+            match e.span() {
+                Some(span) => config.error(&span, message),
+                None => ConfigError {
+                    path: config.path.clone(),
+                    line: None,
+                    message,
+                },
+            }
+        })?;
+
+        if let Some(workers) = &config.file.workers {
+            if *workers.get_ref() == 0 {
+                return Err(config.error(&workers.span(), "at least one worker is needed"));
+            }
+        }
+        if let Some(level) = &config.file.log_level {
+            if let Err(e) = level.get_ref().parse::<crate::log::LogFilter>() {
+                return Err(config.error(&level.span(), e));
+            }
+        }
+        if let Some(format) = &config.file.log_format {
+            if let Err(e) = format.get_ref().parse::<crate::log::LogFormat>() {
+                return Err(config.error(&format.span(), e));
+            }
+        }
+        if let Some(destination) = &config.file.access_log {
+            if let Err(e) = destination.get_ref().parse::<Destination>() {
+                return Err(config.error(&destination.span(), e));
+            }
+        }
+        if let Some(format) = &config.file.access_log_format {
+            if let Err(e) = format.get_ref().parse::<access_log::Format>() {
+                return Err(config.error(&format.span(), e));
+            }
+        }
+        if let Some(path) = &config.file.metrics_path {
+            if let Err(e) = metrics::check_path(path.get_ref()) {
+                return Err(config.error(&path.span(), e));
+            }
+        }
+        if let Some(limits) = &config.file.limits {
+            let sizes = [
+                ("max_header_size", &limits.max_header_size),
+                ("max_header_line_size", &limits.max_header_line_size),
+                ("max_header_count", &limits.max_header_count),
+                ("max_target_length", &limits.max_target_length),
+                ("max_body_size", &limits.max_body_size),
+                ("max_keep_alive_requests", &limits.max_keep_alive_requests),
+            ];
+            for (name, limit) in sizes {
+                if let Some(limit) = limit.as_ref().filter(|limit| *limit.get_ref() == 0) {
+                    return Err(
+                        config.error(&limit.span(), format!("{} has to be at least 1", name))
+                    );
+                }
+            }
+            let timeouts = [
+                ("header_timeout", &limits.header_timeout),
+                ("keep_alive_timeout", &limits.keep_alive_timeout),
+            ];
+            for (name, timeout) in timeouts {
+                if let Some(timeout) = timeout.as_ref().filter(|timeout| *timeout.get_ref() == 0) {
+                    return Err(config.error(
+                        &timeout.span(),
+                        format!("{} has to be at least one second", name),
+                    ));
+                }
+            }
+        }
+        for listen in &config.file.listen {
+            if listen.tls && listen.redirect_to_https {
+                return Err(config.error(
+                    &listen.address.span(),
+                    "an HTTPS listener cannot redirect to HTTPS",
+                ));
+            }
+            if listen.metrics && (listen.tls || listen.redirect_to_https) {
+                return Err(config.error(
+                    &listen.address.span(),
+                    "a metrics listener can neither use nor redirect to HTTPS",
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// This builds the sites of every server block, together with the names they answer to
+    ///
+    /// # Parameters
+    ///
+    /// - `overrides`: This are the settings of the command line and the environment, which win
+    ///   over those of the default server
+    ///
+    /// # Returns
+    ///
+    /// Returns the `VirtualHosts`, whose default server is the block marked with `default = true`
+    /// or else the first one, or `None` if there is no server block
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` pointing at the setting that cannot be used
+    pub fn virtual_hosts(
+        &self,
+        overrides: &SiteOverrides,
+    ) -> Result<Option<VirtualHosts>, ConfigError> {
+        let servers = &self.file.server;
+        let mut defaults = servers
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.get_ref().default);
+        let default = defaults.next().map_or(0, |(index, _)| index);
+        if let Some((_, second)) = defaults.next() {
+            return Err(self.error(&second.span(), "only one server block can be the default"));
+        }
+
+        let mut hosts: Vec<VirtualHost> = Vec::new();
+        for (index, block) in servers.iter().enumerate() {
+            let no_overrides = SiteOverrides::default();
+            let overrides = if index == default {
+                overrides
+            } else if block.get_ref().names.is_empty() {
+                return Err(self.error(
+                    &block.span(),
+                    "only the default server block can be without names",
+                ));
+            } else {
+                &no_overrides
+            };
+            let site = self.site(block, overrides)?;
+            let certificate = self.certificate(block, overrides)?;
+
+            let mut names = Vec::new();
+            for name in &block.get_ref().names {
+                let pattern =
+                    HostPattern::parse(name.get_ref()).map_err(|e| self.error(&name.span(), e))?;
+                if names.contains(&pattern)
+                    || hosts.iter().any(|host| host.names.contains(&pattern))
+                {
+                    return Err(self.error(
+                        &name.span(),
+                        format!("the server name {:?} is used twice", name.get_ref()),
+                    ));
+                }
+                names.push(pattern);
+            }
+            hosts.push(VirtualHost {
+                names,
+                site,
+                certificate,
+            });
+        }
+
+        Ok((!hosts.is_empty()).then(|| VirtualHosts::new(hosts, default)))
+    }
+
+    /// This lists the certificate and private key files of every server block, resolved relative
+    /// to the configuration file
+    pub fn certificate_files(&self) -> Vec<PathBuf> {
+        self.file
+            .server
+            .iter()
+            .flat_map(|block| {
+                let server = block.get_ref();
+                [&server.certificate, &server.private_key]
+            })
+            .flatten()
+            .map(|path| self.relative(path.get_ref()))
+            .collect()
+    }
+
+    /// This returns where responses are recorded, with a file resolved relative to the
+    /// configuration file
+    pub fn access_log(&self) -> Option<Destination> {
+        let destination = self.file.access_log.as_ref()?.get_ref().parse().ok()?;
+        Some(match destination {
+            Destination::File(path) => Destination::File(self.relative(&path)),
+            destination => destination,
+        })
+    }
+
+    /// This builds the protocol settings of the HTTPS listeners from the `[tls]` table
+    ///
+    /// # Returns
+    ///
+    /// Returns the `TlsSettings`, with the defaults of rustls for everything not configured
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` pointing at the version, cipher suite or protocol that is unknown
+    pub fn tls_settings(&self) -> Result<TlsSettings, ConfigError> {
+        let mut settings = TlsSettings::default();
+        let Some(block) = &self.file.tls else {
+            return Ok(settings);
+        };
+        if let Some(versions) = &block.versions {
+            settings.versions = self.each(versions, tls::parse_version)?;
+        }
+        if let Some(cipher_suites) = &block.cipher_suites {
+            settings.cipher_suites = self.each(cipher_suites, tls::parse_cipher_suite)?;
+        }
+        if let Some(protocols) = &block.alpn {
+            settings.protocols = self.each(protocols, tls::parse_protocol)?;
+        }
+        Ok(settings)
+    }
+
+    /// This parses every value of a list, pointing at the first one that cannot be parsed
+    fn each<T>(
+        &self,
+        values: &[Spanned<String>],
+        parse: fn(&str) -> Result<T, TlsError>,
+    ) -> Result<Vec<T>, ConfigError> {
+        values
+            .iter()
+            .map(|value| parse(value.get_ref()).map_err(|e| self.error(&value.span(), e)))
+            .collect()
+    }
+
+    /// This loads the certificate of a server block, if it has one
+    fn certificate(
+        &self,
+        block: &Spanned<ServerBlock>,
+        overrides: &SiteOverrides,
+    ) -> Result<Option<Arc<CertifiedKey>>, ConfigError> {
+        let server = block.get_ref();
+        let paths = match (&overrides.certificate, &overrides.private_key) {
+            (Some(certificate), Some(private_key)) => {
+                Some((certificate.clone(), private_key.clone(), None))
+            }
+            _ => match (&server.certificate, &server.private_key) {
+                (Some(certificate), Some(private_key)) => Some((
+                    self.relative(certificate.get_ref()),
+                    self.relative(private_key.get_ref()),
+                    Some((certificate.span(), private_key.span())),
+                )),
+                (Some(certificate), None) => {
+                    return Err(self.error(&certificate.span(), "the private_key is missing"))
+                }
+                (None, Some(private_key)) => {
+                    return Err(self.error(&private_key.span(), "the certificate is missing"))
+                }
+                (None, None) => None,
+            },
+        };
+        let Some((certificate, private_key, spans)) = paths else {
+            return Ok(None);
+        };
+        tls::load_certificate(&certificate, &private_key)
+            .map(Some)
+            .map_err(|e| match (&e, spans) {
+                (TlsError::Pem { path, .. }, Some((certificate_span, _)))
+                    if *path == certificate =>
+                {
+                    self.error(&certificate_span, e)
+                }
+                (_, Some((_, private_key_span))) => self.error(&private_key_span, e),
+                // Settings of the command line have no line in the file
+                (_, None) => ConfigError {
+                    path: self.path.clone(),
+                    line: None,
+                    message: e.to_string(),
+                },
+            })
+    }
+
+    /// This builds the site of a server block, resolving its directories relative to the
+    /// configuration file
+    ///
+    /// # Parameters
+    ///
+    /// - `block`: This is the server block
+    /// - `overrides`: This are the settings of the command line and the environment, which win
+    ///   over those of the block
+    ///
+    /// # Returns
+    ///
+    /// Returns the `SiteConfig` of the block
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` pointing at the setting that cannot be used
+    pub fn site(
+        &self,
+        block: &Spanned<ServerBlock>,
+        overrides: &SiteOverrides,
+    ) -> Result<SiteConfig, ConfigError> {
+        let server = block.get_ref();
+
+        let (document_root, root_span) = match (&overrides.document_root, &server.root) {
+            (Some(root), _) => (root.clone(), None),
+            (None, Some(root)) => (self.relative(root.get_ref()), Some(root.span())),
+            // Like a given root, the default one is next to the configuration file
+            (None, None) => (
+                self.relative(Path::new(http::DEFAULT_DOCUMENT_ROOT)),
+                Some(block.span()),
+            ),
+        };
+        let (error_pages, error_pages_span) = match (&overrides.error_pages, &server.error_pages) {
+            (Some(pages), _) => (Some(pages.clone()), None),
+            (None, Some(pages)) => (Some(self.relative(pages.get_ref())), Some(pages.span())),
+            (None, None) => (None, None),
+        };
+        let (index_files, index_span) = match (&overrides.index_files, &server.index_files) {
+            (Some(names), _) => (names.clone(), None),
+            (None, Some(names)) => (names.get_ref().clone(), Some(names.span())),
+            (None, None) => (Vec::new(), None),
+        };
+
+        let mut site = SiteConfig::new(&document_root, error_pages.as_deref(), index_files)
+            .map_err(|e| {
+                let span = match &e {
+                    SiteError::Missing { setting, .. }
+                    | SiteError::NotADirectory { setting, .. }
+                        if *setting == "error page directory" =>
+                    {
+                        error_pages_span
+                    }
+                    SiteError::InvalidIndexFile(_) => index_span,
+                    _ => root_span,
+                };
+                // Settings of the command line have no line in the file
+                match span {
+                    Some(span) => self.error(&span, e),
+                    None => ConfigError {
+                        path: self.path.clone(),
+                        line: None,
+                        message: e.to_string(),
+                    },
+                }
+            })?;
+        site.compression = server.compression.unwrap_or(true);
+        site.headers = self.headers(&server.headers)?;
+        site.cache_control = self.cache_control(&server.cache_control)?;
+
+        for block in &server.location {
+            let location = block.get_ref();
+            let root = location
+                .root
+                .as_ref()
+                .map(|root| self.relative(root.get_ref()));
+            let mut built =
+                Location::new(location.path.get_ref(), root.as_deref()).map_err(|e| {
+                    let span = match (&e, &location.root) {
+                        (SiteError::InvalidLocation(_), _) | (_, None) => location.path.span(),
+                        (_, Some(root)) => root.span(),
+                    };
+                    self.error(&span, e)
+                })?;
+            if let Some(index_files) = &location.index_files {
+                built
+                    .set_index_files(index_files.get_ref().clone())
+                    .map_err(|e| self.error(&index_files.span(), e))?;
+            }
+            built.compression = location.compression;
+            built.headers = self.headers(&location.headers)?;
+            built.cache_control = self.cache_control(&location.cache_control)?;
+
+            if site
+                .locations
+                .iter()
+                .any(|other| other.prefix == built.prefix)
+            {
+                return Err(self.error(
+                    &location.path.span(),
+                    format!("the location {:?} is defined twice", built.prefix),
+                ));
+            }
+            site.locations.push(built);
+        }
+        Ok(site)
+    }
+
+    /// This returns the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// This returns the directory relative paths of the file are resolved from
+    pub fn directory(&self) -> PathBuf {
+        self.path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    /// This function resolves a path of the file relative to its directory
+    fn relative(&self, path: &Path) -> PathBuf {
+        self.directory().join(path)
+    }
+
+    /// This checks the header fields of a block
+    fn headers(
+        &self,
+        headers: &Option<Spanned<BTreeMap<String, String>>>,
+    ) -> Result<Vec<(String, String)>, ConfigError> {
+        let mut checked = Vec::new();
+        let Some(headers) = headers else {
+            return Ok(checked);
+        };
+        for (name, value) in headers.get_ref() {
+            http::check_header(name, value).map_err(|e| self.error(&headers.span(), e))?;
+            checked.push((name.clone(), value.clone()));
+        }
+        Ok(checked)
+    }
+
+    /// This checks the `Cache-Control` of a block
+    fn cache_control(
+        &self,
+        cache_control: &Option<Spanned<String>>,
+    ) -> Result<Option<String>, ConfigError> {
+        match cache_control {
+            Some(value) => {
+                http::check_header("Cache-Control", value.get_ref())
+                    .map_err(|e| self.error(&value.span(), e))?;
+                Ok(Some(value.get_ref().clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// This builds an error pointing at the line a part of the file starts on
+    pub fn error(&self, span: &Range<usize>, message: impl ToString) -> ConfigError {
+        let start = span.start.min(self.source.len());
+        ConfigError {
+            path: self.path.clone(),
+            line: Some(self.source[..start].matches('\n').count() + 1),
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Config, ConfigError> {
+        Config::parse(Path::new("anes-http.toml"), source.to_string())
+    }
+
+    #[test]
+    fn test_parses_a_full_configuration() {
+        let config = parse(
+            r#"
+workers = 2
+log_level = "debug"
+bind_retries = 1
+
+[[listen]]
+address = "127.0.0.1"
+port = 8080
+
+[[server]]
+root = "public"
+index_files = ["index.html"]
+cache_control = "no-cache"
+headers = { "X-Content-Type-Options" = "nosniff" }
+
+[[server.location]]
+path = "/source/"
+root = "src"
+compression = false
+cache_control = "max-age=3600"
+"#,
+        )
+        .unwrap();
+        assert_eq!(*config.file.workers.as_ref().unwrap().get_ref(), 2);
+        assert_eq!(config.file.listen[0].port, Some(8080));
+
+        let site = config
+            .site(&config.file.server[0], &SiteOverrides::default())
+            .unwrap();
+        assert_eq!(site.index_files, vec!["index.html"]);
+        assert_eq!(site.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(
+            site.headers,
+            vec![("X-Content-Type-Options".to_string(), "nosniff".to_string())]
+        );
+        assert_eq!(site.locations[0].prefix, "/source/");
+        assert!(site.locations[0].root.as_ref().unwrap().ends_with("src"));
+        assert_eq!(site.locations[0].compression, Some(false));
+    }
+
+    #[test]
+    fn test_errors_carry_their_line() {
+        let error = parse("workers = 2\nworkers = 3\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+
+        let error = parse("workers = 2\n\n[[listen]]\nadress = \"::1\"\n").unwrap_err();
+        assert_eq!(error.line, Some(4));
+        assert!(error.message.contains("adress"), "{}", error.message);
+
+        let error = parse("log_level = \"loud\"\n").unwrap_err();
+        assert_eq!(error.to_string().split(':').nth(1), Some("1"));
+        let error = parse("workers = 1\naccess_log_format = \"xml\"\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+
+        let config = parse(
+            "[[server]]\nroot = \"public\"\n\n[[server.location]]\npath = \"/a/\"\nroot = \"missing\"\n",
+        )
+        .unwrap();
+        let error = config
+            .site(&config.file.server[0], &SiteOverrides::default())
+            .unwrap_err();
+        assert_eq!(error.line, Some(6));
+        assert!(error.message.contains("location root"));
+
+        let config = parse("[[server]]\nheaders = { \"Bad Name\" = \"x\" }\n").unwrap();
+        let error = config
+            .site(&config.file.server[0], &SiteOverrides::default())
+            .unwrap_err();
+        assert_eq!(error.line, Some(2));
+    }
+
+    #[test]
+    fn test_builds_virtual_hosts() {
+        let config = parse(
+            "[[server]]\nnames = [\"a.test\"]\nroot = \"src\"\n\n\
+             [[server]]\nnames = [\"*.b.test\"]\ndefault = true\n",
+        )
+        .unwrap();
+        let overrides = SiteOverrides {
+            index_files: Some(vec!["index.html".to_string()]),
+            ..SiteOverrides::default()
+        };
+
+        let hosts = config.virtual_hosts(&overrides).unwrap().unwrap();
+        assert!(hosts.select(Some("a.test")).document_root.ends_with("src"));
+        assert_eq!(hosts.select(Some("a.test")).index_files, vec!["index.*"]);
+        assert!(hosts.select(None).document_root.ends_with("public"));
+        assert_eq!(hosts.select(None).index_files, vec!["index.html"]);
+        assert!(parse("")
+            .unwrap()
+            .virtual_hosts(&overrides)
+            .unwrap()
+            .is_none());
+
+        for (source, line) in [
+            ("[[server]]\n\n[[server]]\nroot = \"src\"\n", 3),
+            (
+                "[[server]]\ndefault = true\n\n[[server]]\ndefault = true\n",
+                4,
+            ),
+            ("[[server]]\nnames = [\"a.test\", \"a.*\"]\n", 2),
+            (
+                "[[server]]\nnames = [\"a.test\"]\n\n[[server]]\nnames = [\"A.test\"]\n",
+                5,
+            ),
+        ] {
+            let error = parse(source)
+                .unwrap()
+                .virtual_hosts(&SiteOverrides::default())
+                .unwrap_err();
+            assert_eq!(error.line, Some(line), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_default_root_is_relative_to_the_file() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-config-root-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source = "workers = 1\n\n[[server]]\nnames = [\"a.test\"]\n".to_string();
+        let config = Config::parse(&directory.join("anes-http.toml"), source).unwrap();
+
+        let error = config
+            .site(&config.file.server[0], &SiteOverrides::default())
+            .unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("document root"), "{}", error);
+
+        fs::create_dir(directory.join("public")).unwrap();
+        let site = config
+            .site(&config.file.server[0], &SiteOverrides::default())
+            .unwrap();
+        assert_eq!(
+            site.document_root,
+            directory.join("public").canonicalize().unwrap()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_parses_tls_settings() {
+        let config = parse(
+            "[tls]\nversions = [\"1.3\"]\ncipher_suites = [\"TLS13_AES_256_GCM_SHA384\"]\n\
+             alpn = [\"http/1.1\", \"http/1.0\"]\n",
+        )
+        .unwrap();
+        let settings = config.tls_settings().unwrap();
+        assert_eq!(settings.versions, vec![&rustls::version::TLS13]);
+        assert_eq!(settings.cipher_suites.len(), 1);
+        assert_eq!(settings.protocols, vec!["http/1.1", "http/1.0"]);
+
+        for (source, line) in [
+            ("[tls]\nversions = [\"1.3\",\n  \"1.0\"]\n", 3),
+            ("[tls]\nalpn = [\"h2\"]\n", 2),
+        ] {
+            let error = parse(source).unwrap().tls_settings().unwrap_err();
+            assert_eq!(error.line, Some(line), "{}", error);
+        }
+
+        let error = parse("[[listen]]\naddress = \"::\"\ntls = true\nredirect_to_https = true\n")
+            .unwrap_err();
+        assert_eq!(error.line, Some(2));
+        let error =
+            parse("[[listen]]\naddress = \"::\"\nmetrics = true\ntls = true\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+        let error = parse("[limits]\nmax_header_count = 10\nmax_body_size = 0\n").unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("max_body_size"), "{}", error);
+        let error = parse("[limits]\nkeep_alive_timeout = 0\n").unwrap_err();
+        assert_eq!(error.line, Some(2));
+
+        let error = parse("metrics_path = \"metrics\"\n").unwrap_err();
+        assert!(error.message.contains("start with /"), "{}", error.message);
+        let config = parse("[[server]]\nroot = \"public\"\ncertificate = \"cert.pem\"\n").unwrap();
+        let error = config.virtual_hosts(&SiteOverrides::default()).unwrap_err();
+        assert_eq!(error.line, Some(3));
+        assert!(error.message.contains("private_key"));
+    }
+
+    #[test]
+    fn test_overrides_win_over_the_server_block() {
+        let config = parse("[[server]]\nroot = \"missing\"\nindex_files = [\"a.html\"]\n").unwrap();
+        let overrides = SiteOverrides {
+            document_root: Some(PathBuf::from("public")),
+            index_files: Some(vec!["index.*".to_string()]),
+            ..SiteOverrides::default()
+        };
+
+        let site = config.site(&config.file.server[0], &overrides).unwrap();
+        assert!(site.document_root.ends_with("public"));
+        assert_eq!(site.index_files, vec!["index.*"]);
+    }
+}
@@ -1,144 +1,793 @@
-use std::io::Read;
-use std::net::TcpStream;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use mime_guess::Mime;
+use rustls::ServerConfig;
+use tracing::field;
 
+mod chunked;
+mod conditional;
+mod encoding;
+mod file_path;
+mod headers;
 mod http_codes;
 mod http_object;
+mod limits;
+mod method;
+mod parser;
+mod precompressed;
+mod range;
+mod reader;
+mod response;
+mod site;
+mod socket;
+mod virtual_host;
 
-/// This is the internal request gate, which writes everything but the 400 Bad Request HTTP
-/// Response to the client.
-fn internal_request_gate(stream: &TcpStream) -> Result<(), String> {
-    println!("New connection from: {}", stream.peer_addr().unwrap());
-    let mut buffer = [0; 1024];
-    let mut mutable_stream = stream.try_clone().unwrap();
-
-    match mutable_stream.read(&mut buffer) {
-        Ok(0) => Err("No data received from the client.".to_string()),
-        Ok(_) => {
-            println!("Received data: \n{}", String::from_utf8_lossy(&buffer));
-            let request = request_tokenizer(&String::from_utf8_lossy(&buffer));
-            if !request.is_http() {
-                println!("This is not an http request");
-                return Err("This is not an http request".to_string());
+pub use limits::RequestLimits;
+pub use site::{check_header, Location, SiteConfig, SiteError, DEFAULT_DOCUMENT_ROOT};
+pub use virtual_host::{HostPattern, VirtualHost, VirtualHosts};
+use crate::access_log::{self, Entry};
+use crate::tcp::{Connection, Idle, Turn};
+use crate::{log, metrics, tls};
+use conditional::{Precondition, Validators};
+use encoding::Encoding;
+use file_path::PathError;
+use http_object::HttpObject;
+use method::{Method, ALLOWED_METHODS};
+use parser::ParseError;
+use reader::{ReadError, RequestReader};
+use response::{Response, Status};
+use site::Route;
+use socket::Socket;
+
+/// This numbers the requests, so the messages of one request can be told apart from the others.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// This is everything that can go wrong while handling a single request
+#[derive(Debug)]
+enum RequestError {
+    /// The request could not be parsed and gets answered according to the `ParseError`
+    Parse(ParseError),
+    /// The client closed the connection, so there is no one left to answer
+    Closed,
+    /// Anything else, which gets answered with a 400 Bad Request
+    Other(String),
+}
+
+impl From<ParseError> for RequestError {
+    fn from(error: ParseError) -> RequestError {
+        RequestError::Parse(error)
+    }
+}
+
+impl From<ReadError> for RequestError {
+    fn from(error: ReadError) -> RequestError {
+        match error {
+            ReadError::Closed => RequestError::Closed,
+            ReadError::Io(e) => RequestError::Other(format!("Failed to receive data: {}", e)),
+            ReadError::Parse(e) => RequestError::Parse(e),
+        }
+    }
+}
+
+/// This describes how the connections of a listener are answered
+#[derive(Debug, Clone)]
+pub enum Service {
+    /// The files of the sites are served over plain HTTP
+    Http,
+    /// The files of the sites are served over HTTPS, with the given configuration
+    Https(Arc<ServerConfig>),
+    /// Every request is redirected to the same URL on HTTPS, at the given port
+    RedirectToHttps(u16),
+    /// Only the metrics are served, at the given path
+    Metrics(String),
+}
+
+/// This is the internal request gate, which reads a single request from the connection and writes
+/// everything but the error responses to the client.
+///
+/// # Parameters
+///
+/// - `reader`: This is the reader of the connection, holding whatever was received past the
+///   previous request. The response is written to the stream it reads from.
+/// - `limits`: This are the limits the request has to stay within
+/// - `hosts`: This are the sites, of which the one the request is sent to serves it
+/// - `service`: This is how the listener the connection came from answers requests
+/// - `metrics_path`: This is the path the metrics are served at, if they are
+/// - `keep_alive_allowed`: This is false once the connection has served its last request
+/// - `remote`: This is the address of the client, which is recorded in the access log
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if the connection stays open for another request
+fn internal_request_gate<S: Read + Write>(
+    reader: &mut RequestReader<S>,
+    limits: &RequestLimits,
+    hosts: &VirtualHosts,
+    service: &Service,
+    metrics_path: Option<&str>,
+    keep_alive_allowed: bool,
+    remote: Option<IpAddr>,
+) -> Result<bool, RequestError> {
+    let head = reader.read_head(limits)?;
+    let started = Instant::now();
+    let mut request = parser::parse_head(&head, limits)?;
+    let body = if request.is_chunked() {
+        reader.read_chunked_body(limits)?
+    } else {
+        reader.read_body(request.content_length().unwrap_or(0), limits)?
+    };
+    request.set_body(body);
+
+    let span = tracing::info_span!(
+        "request",
+        id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        method = %request.method(),
+        path = request.request_path(),
+    );
+    let _entered = span.enter();
+    print_request(&request);
+    let keep_alive = keep_alive_allowed && request.wants_keep_alive();
+    let send_body = request.method() != &Method::Head;
+
+    let site = hosts.select(request.host());
+    let mut response = match service {
+        Service::RedirectToHttps(port) => redirect_to_https(&request, *port, site),
+        _ => match reject_invalid(&request, site) {
+            Some(rejected) => rejected,
+            None if metrics_path == Some(request.request_path()) => serve_metrics(&request, site),
+            None if matches!(service, Service::Metrics(_)) => {
+                http_codes::error(Status::NotFound, &site.error_pages)
             }
+            None => respond(&request, site)?,
+        },
+    };
+    if !request.is_http_1_0() {
+        response.chunk_unknown_length();
+    }
+    // The time it took to prepare a streamed response is only reported to clients that read it
+    if response.is_chunked() && request.accepts_trailers() {
+        let duration = started.elapsed().as_secs_f64() * 1000.0;
+        response = response.trailer("Server-Timing", &format!("app;dur={:.3}", duration));
+    }
+    let sent = send_response(reader.get_mut(), response, keep_alive, send_body);
+    record(remote, Some(&request), &sent, started);
+    Ok(sent.keep_alive)
+}
+
+/// This function builds the redirect of a plain HTTP request to the same URL on HTTPS
+///
+/// # Parameters
+///
+/// - `request`: This is the request that is redirected
+/// - `port`: This is the port of the HTTPS listener
+/// - `site`: This is the site the request is sent to, whose error pages are used
+///
+/// # Returns
+///
+/// Returns a `301 Moved Permanently` for `GET` and `HEAD` requests and a `308 Permanent Redirect`,
+/// which keeps the method, for all others. Requests without a host cannot be redirected and get
+/// a `400 Bad Request`.
+fn redirect_to_https(request: &HttpObject, port: u16, site: &SiteConfig) -> Response {
+    let Some(host) = request.host() else {
+        log::debug!("The request has no host to redirect to");
+        return http_codes::error(Status::BadRequest, &site.error_pages);
+    };
+    let authority = match port {
+        443 => host.to_string(),
+        port => format!("{}:{}", host, port),
+    };
+    let target = request.target();
+    let path_and_query = match target.split_once("://") {
+        // Absolute-form targets are redirected to their path, the host was taken from them
+        Some((_, rest)) => match rest.find(['/', '?']) {
+            Some(start) if rest[start..].starts_with('/') => rest[start..].to_string(),
+            Some(start) => format!("/{}", &rest[start..]),
+            None => "/".to_string(),
+        },
+        None if target == "*" => "/".to_string(),
+        None => target.to_string(),
+    };
+    let status = match request.method() {
+        Method::Get | Method::Head => Status::MovedPermanently,
+        _ => Status::PermanentRedirect,
+    };
+    http_codes::redirect(status, &format!("https://{}{}", authority, path_and_query))
+}
+
+/// This function answers a request for the metrics
+///
+/// # Parameters
+///
+/// - `request`: This is the request that is answered
+/// - `site`: This is the site whose error pages are used
+fn serve_metrics(request: &HttpObject, site: &SiteConfig) -> Response {
+    match request.method() {
+        Method::Get | Method::Head => http_codes::metrics(metrics::render()),
+        _ => http_codes::method_not_allowed("GET, HEAD", &site.error_pages),
+    }
+}
+
+/// This function checks the parts of a parsed request every answer depends on, before anything
+/// is served
+///
+/// # Parameters
+///
+/// - `request`: This is the request that is checked
+/// - `site`: This is the site whose error pages are used
+///
+/// # Returns
+///
+/// Returns the error `Response` the request gets, or `None` if it can be served
+fn reject_invalid(request: &HttpObject, site: &SiteConfig) -> Option<Response> {
+    if !request.is_http() {
+        log::debug!("The http version {} is not supported", request.version());
+        return Some(
+            http_codes::error(Status::HttpVersionNotSupported, &site.error_pages)
+                .header("Connection", "close"),
+        );
+    }
+    // RFC 7230 requires a Host header from every HTTP/1.1 client
+    if !request.is_http_1_0() && !request.headers().contains("Host") {
+        log::debug!("The request has no Host header");
+        return Some(http_codes::error(Status::BadRequest, &site.error_pages));
+    }
+    None
+}
 
-            let req_path = request.request_path();
-            let mime = request.weighted_mimes();
-            let fileresult;
-            match mime {
-                Ok(unwrapped_mime) => {
-                    fileresult = file_browser(req_path, unwrapped_mime);
+/// This function builds the response to a parsed request that passed `reject_invalid()`
+///
+/// # Parameters
+///
+/// - `request`: This is the request that is answered
+/// - `site`: This is the site the files are served from
+///
+/// # Returns
+///
+/// Returns the `Response`. Responses after which the connection cannot be reused carry a
+/// `Connection: close` header.
+///
+/// # Errors
+///
+/// Returns a `RequestError` if the request is invalid in a way the parser did not detect
+fn respond(request: &HttpObject, site: &SiteConfig) -> Result<Response, RequestError> {
+    match request.method() {
+        Method::Get | Method::Head => {}
+        Method::Options => return Ok(http_codes::options(ALLOWED_METHODS)),
+        Method::Other(method) => {
+            log::debug!("The method {} is not implemented", method);
+            return Ok(http_codes::error(Status::NotImplemented, &site.error_pages)
+                .header("Connection", "close"));
+        }
+        method => {
+            log::debug!("The method {} is not allowed on static files", method);
+            return Ok(http_codes::method_not_allowed(ALLOWED_METHODS, &site.error_pages));
+        }
+    }
+
+    let req_path = match file_path::normalize(request.request_path()) {
+        Ok(req_path) => req_path,
+        Err(PathError::Escapes) => {
+            log::debug!("The path {} escapes the document root", request.request_path());
+            return Ok(http_codes::error(Status::Forbidden, &site.error_pages));
+        }
+        Err(PathError::Invalid) => {
+            return Err(RequestError::Other(format!(
+                "The path {:?} is invalid",
+                request.request_path()
+            )))
+        }
+    };
+    let route = site.route(&req_path);
+    let mime = request.weighted_mimes();
+    let fileresult = match mime {
+        Ok(unwrapped_mime) => file_browser(&route, unwrapped_mime),
+        Err(e) => return Err(RequestError::Other(e)),
+    };
+    let (path, mime_type) = match fileresult {
+        Some(fileresult) => fileresult,
+        None => return Ok(http_codes::error(Status::NotFound, &site.error_pages)),
+    };
+
+    let encodings = request.weighted_encodings().map_err(RequestError::Other)?;
+    let compressible = route.compression && encoding::is_compressible(&mime_type);
+    let available = if compressible {
+        precompressed::available(&path, route.root)
+    } else {
+        Vec::new()
+    };
+    let encoding = match encoding::negotiate(&encodings, &available) {
+        // Bodies that are never compressed are sent even if identity was refused
+        _ if !compressible => Encoding::Identity,
+        Some(encoding) => encoding,
+        None => {
+            log::debug!("None of the encodings {:?} is supported", encodings);
+            return Ok(http_codes::error(Status::NotAcceptable, &site.error_pages));
+        }
+    };
+
+    let sibling = available
+        .contains(&encoding)
+        .then(|| precompressed::sibling(&path, encoding))
+        .flatten();
+    let metadata = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::warning!("Error: {}", e);
+            return Ok(http_codes::error(Status::NotFound, &site.error_pages));
+        }
+    };
+    let validators = Validators::new(&metadata, encoding, sibling.is_some());
+    match conditional::evaluate(request.method(), request.headers(), &validators) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            return Ok(route.apply(validators.apply(http_codes::not_modified(&mime_type))))
+        }
+        Precondition::Failed => {
+            return Ok(http_codes::error(Status::PreconditionFailed, &site.error_pages))
+        }
+    }
+
+    let opened = File::open(sibling.as_ref().unwrap_or(&path))
+        .and_then(|file| file.metadata().map(|metadata| (file, metadata.len())));
+    let response = match (opened, &sibling) {
+        (Ok((file, length)), Some(_)) => {
+            metrics::precompressed(true);
+            http_codes::precompressed(file, length, &mime_type, encoding)
+                .compressed_from(metadata.len())
+        }
+        (Ok((file, length)), None) => match http_codes::ok(file, length, &mime_type, encoding) {
+            Ok(response) => {
+                if response.original_length().is_some() {
+                    metrics::precompressed(false);
                 }
-                Err(e) => return Err(e),
+                response
             }
-            match fileresult {
-                Some(fileresult) => {
-                    http_codes::ok(stream, fileresult.0, fileresult.1);
-                    Ok(())
-                }
-                None => {
-                    http_codes::not_found(stream);
-                    Ok(())
-                }
+            Err(e) => {
+                log::error!("Failed to read {:?}: {}", path, e);
+                return Ok(http_codes::error(Status::InternalServerError, &site.error_pages));
             }
+        },
+        (Err(e), _) => {
+            log::warning!("Error: {}", e);
+            return Ok(http_codes::error(Status::NotFound, &site.error_pages));
+        }
+    };
+
+    // Only files sent as they are stored have ranges, a body compressed while sending does not
+    let has_ranges = sibling.is_some() || response.original_length().is_none();
+    let accept_ranges = if has_ranges { "bytes" } else { "none" };
+    let response = route.apply(validators.apply(response).header("Accept-Ranges", accept_ranges));
+    match request.headers().get("Range") {
+        Some(range)
+            if has_ranges && conditional::if_range_matches(request.headers(), &validators) =>
+        {
+            Ok(range::respond(response, range, &site.error_pages))
         }
-        Err(e) => Err(format!("Failed to receive data: {}", e)),
+        _ => Ok(response),
     }
 }
 
-/// This is the request gate function used by a TCP-Server to handle incoming requests. It directly
-/// writes the HTTP-Response to the client.
-pub fn request_gate(mut stream: TcpStream) {
-    let to_be_sent_response = internal_request_gate(&mut stream);
-    match to_be_sent_response {
-        Ok(_) => {
-            println!("The response was sent");
-        }
+/// This function writes a response to the client, telling it in the `Connection` header if the
+/// connection stays open
+///
+/// # Parameters
+///
+/// - `stream`: This is the connection the response is written to
+/// - `response`: This is the response that is sent
+/// - `keep_alive`: This is true if the request allows the connection to stay open
+/// - `send_body`: This is false if only the head is sent, e.g. for `HEAD` requests
+///
+/// # Returns
+///
+/// Returns what was sent, and if the connection stays open for another request
+fn send_response<W: Write>(
+    stream: &mut W,
+    mut response: Response,
+    keep_alive: bool,
+    send_body: bool,
+) -> Sent {
+    let closes = response
+        .headers()
+        .get_list("Connection")
+        .iter()
+        .any(|option| option.eq_ignore_ascii_case("close"));
+    let keep_alive = keep_alive && !closes && response.is_delimited();
+    response.set_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+    let status = response.status();
+    let original_length = response
+        .original_length()
+        .filter(|_| send_body && status == Status::Ok);
+    match response.write_to(stream, send_body) {
+        Ok(bytes) => Sent {
+            status,
+            bytes,
+            keep_alive,
+            original_length,
+        },
         Err(e) => {
-            println!("Request handling gave an error: {}", e);
-            http_codes::bad_request(&stream);
+            log::warning!("Failed to send the response: {}", e);
+            Sent {
+                status,
+                bytes: 0,
+                keep_alive: false,
+                original_length: None,
+            }
         }
     }
 }
 
-/// This function tokenizes the incoming http request and returns a struct that contains every
-/// necessary attribute
+/// This struct describes a response after it was written to the client
+struct Sent {
+    /// The status of the response
+    status: Status,
+    /// The amount of body bytes sent, which is `0` if sending failed
+    bytes: u64,
+    /// Whether the connection stays open for another request
+    keep_alive: bool,
+    /// The size of the file the body was compressed from, if a whole compressed body was sent
+    original_length: Option<u64>,
+}
+
+/// This function records a response in the access log and the metrics
 ///
-/// # Returns
+/// # Parameters
 ///
-/// Returns an instance of the HttpResponse struct
-fn request_tokenizer(request: &str) -> http_object::HttpObject {
-    let lines: Vec<&str> = request.split("\r\n").collect();
+/// - `remote`: This is the address of the client
+/// - `request`: This is the request that was answered, if it could be parsed
+/// - `sent`: This is what was sent
+/// - `started`: This is when answering the request started
+fn record(remote: Option<IpAddr>, request: Option<&HttpObject>, sent: &Sent, started: Instant) {
+    let duration = started.elapsed();
+    log::debug!(status = sent.status.code(), bytes = sent.bytes, ?duration, "Sent the response");
+    let method = request.map(|request| match request.method() {
+        // Unknown methods are counted together, so clients cannot add labels at will
+        Method::Other(_) => "OTHER",
+        method => method.as_str(),
+    });
+    metrics::response(method, sent.status.code(), sent.bytes, duration);
+    if let Some(original) = sent.original_length {
+        metrics::compressed(original, sent.bytes);
+    }
+    let header = |name| request.and_then(|request| request.headers().get(name));
+    access_log::record(&Entry {
+        remote,
+        time: SystemTime::now() - duration,
+        host: request.and_then(HttpObject::host),
+        request: request.map(|request| {
+            (
+                request.method().as_str(),
+                request.target(),
+                request.version(),
+            )
+        }),
+        status: sent.status.code(),
+        bytes: sent.bytes,
+        referer: header("Referer"),
+        user_agent: header("User-Agent"),
+        duration,
+    });
+}
 
-    let mut request_line = String::new();
-    let mut accept_header = String::new();
+/// This function prints the parsed request to the console, if the debug level is enabled
+fn print_request(request: &HttpObject) {
+    log::debug!(
+        "Received {} {} {}",
+        request.method(),
+        request.target(),
+        request.version()
+    );
+    for (name, value) in request.headers().iter() {
+        log::debug!("{}: {}", name, value);
+    }
+    log::debug!(
+        "Path: {}, Query: {}, Body: {} bytes",
+        request.request_path(),
+        request.query().unwrap_or_default(),
+        request.body().len()
+    );
+}
 
-    for line in &lines {
-        if line.starts_with("GET") {
-            request_line = line.to_string();
-        } else if line.starts_with("Accept:") || line.starts_with("accept:") {
-            accept_header = line.to_string();
-            break; // Assuming we only need the first Accept header.
-        }
+/// This is a connection between two of its requests. It is handed back to the TCP-Server while
+/// it waits for the next one, so it does not hold a worker in the meantime.
+pub struct Session {
+    reader: RequestReader<Box<dyn Socket>>,
+    /// The amount of requests served over the connection so far
+    served: usize,
+    remote: Option<IpAddr>,
+    /// When the connection is closed if the next request did not start arriving by then
+    deadline: Instant,
+    span: tracing::Span,
+    _open: metrics::OpenConnection,
+}
+
+impl Idle for Session {
+    fn socket(&self) -> &TcpStream {
+        self.reader.get_ref().tcp()
     }
 
-    http_object::HttpObject::new(request_line, accept_header)
+    fn deadline(&self) -> Instant {
+        self.deadline
+    }
 }
 
-/// This function searches for a matching file in the file system and returns the file if it
-/// exists
-fn file_browser(filepath: &str, accepted_mimes: Vec<(String, f32)>) -> Option<(Vec<u8>, String)> {
-    let base_path = "public".to_string();
-    let parsed_filepath = parse_filepath(filepath);
-    let path_pattern = base_path + &parsed_filepath;
-    let mut matching_files: Vec<(PathBuf, f32)> = Vec::new();
+impl Session {
+    /// This Initializes a new `Session` for a connection that was just accepted, which includes
+    /// the TLS handshake of encrypted listeners
+    ///
+    /// # Parameters
+    ///
+    /// - `stream`: This is the connection to the client
+    /// - `limits`: This are the limits the connection has to stay within
+    /// - `service`: This is how the listener the connection came from answers it
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Session`, or `None` if the TLS handshake failed
+    fn open(stream: TcpStream, limits: &RequestLimits, service: &Service) -> Option<Session> {
+        let peer = stream.peer_addr().ok();
+        let span = tracing::info_span!("connection", peer = field::Empty);
+        if let Some(peer) = peer {
+            span.record("peer", field::display(peer));
+        }
+        let entered = span.enter();
+        log::debug!("Connection opened");
+        let open = metrics::connection_opened();
+        if let Err(e) = stream.set_read_timeout(Some(limits.keep_alive_timeout)) {
+            log::warning!("Failed to set the keep-alive timeout: {}", e);
+        }
 
-    for entry in glob::glob(&path_pattern).unwrap() {
-        append_to_matching_files(&mut matching_files, entry, accepted_mimes.clone());
+        let socket: Box<dyn Socket> = match service {
+            Service::Https(config) => match tls::accept(stream, config, limits.header_timeout) {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    log::debug!("The TLS handshake failed: {}", e);
+                    return None;
+                }
+            },
+            _ => Box::new(stream),
+        };
+        drop(entered);
+        Some(Session {
+            reader: RequestReader::new(socket),
+            served: 0,
+            remote: peer.map(|address| address.ip()),
+            deadline: Instant::now(),
+            span,
+            _open: open,
+        })
     }
+}
 
-    if matching_files.len() == 0 {
-        return None;
+/// This is the request gate function used by a TCP-Server to handle incoming connections. It
+/// serves requests from the connection until none is left to answer, and directly writes the
+/// HTTP-Responses to the client. A connection the client keeps open is handed back to wait for
+/// its next request, until the client or the keep-alive limits close it.
+///
+/// # Parameters
+///
+/// - `turn`: This is the connection, either just accepted or one that waited for its next request
+/// - `limits`: This are the limits every request and the connection have to stay within
+/// - `hosts`: This are the sites, picked by the host each request is sent to
+/// - `service`: This is how the listener the connection came from answers it
+/// - `metrics_path`: This is the path plain HTTP listeners serve the metrics at besides the
+///   files, if they do. A `Service::Metrics` listener serves them at its own path instead.
+/// - `connection`: This is where the connection reports whether it serves a request, so it can
+///   be closed between requests when the server stops
+///
+/// # Returns
+///
+/// Returns the `Session` of the connection if it waits for its next request
+pub fn request_gate(
+    turn: Turn<Session>,
+    limits: &RequestLimits,
+    hosts: &VirtualHosts,
+    service: &Service,
+    metrics_path: Option<&str>,
+    connection: &Connection,
+) -> Option<Session> {
+    let (mut session, resumed) = match turn {
+        Turn::Accepted(stream) => (Session::open(stream, limits, service)?, false),
+        Turn::Readable(session) => (session, true),
+        Turn::Expired(mut session) => {
+            session.reader.get_mut().close();
+            return None;
+        }
+    };
+    let _entered = session.span.clone().entered();
+
+    // Encrypted listeners and those redirecting to them never answer with the metrics
+    let metrics_path = match service {
+        Service::Metrics(path) => Some(path.as_str()),
+        Service::Http => metrics_path,
+        Service::Https(_) | Service::RedirectToHttps(_) => None,
+    };
+    // The connection was idle, and was closed right away if the server stopped
+    let arrived = !resumed || (connection.busy() && session.reader.wait_for_request());
+    if arrived && serve_connection(&mut session, limits, hosts, service, metrics_path, connection) {
+        session.deadline = Instant::now() + limits.keep_alive_timeout;
+        return Some(session);
     }
+    session.reader.get_mut().close();
+    None
+}
 
-    let used_file = best_match(matching_files);
-    let used_filepath = used_file.to_str().unwrap().to_string();
-    let used_mime = mime_guess::from_path(used_filepath).first_or_octet_stream();
-    let file_content = std::fs::read(used_file);
-    match file_content {
-        Ok(content) => {
-            return Some((content, correct_mime(used_mime, accepted_mimes)));
+/// This function serves the requests of a connection, as long as the next one already arrived
+///
+/// # Parameters
+///
+/// - `session`: This is the connection to the client
+/// - `limits`: This are the limits every request and the connection have to stay within
+/// - `hosts`: This are the sites, picked by the host each request is sent to
+/// - `service`: This is how the listener the connection came from answers requests
+/// - `metrics_path`: This is the path the metrics are served at, if they are
+/// - `connection`: This is where the connection reports whether it serves a request
+///
+/// # Returns
+///
+/// Returns `true` if the connection stays open and waits for its next request
+fn serve_connection(
+    session: &mut Session,
+    limits: &RequestLimits,
+    hosts: &VirtualHosts,
+    service: &Service,
+    metrics_path: Option<&str>,
+    connection: &Connection,
+) -> bool {
+    loop {
+        session.served += 1;
+        let keep_alive_allowed =
+            session.served < limits.max_keep_alive_requests && !connection.is_stopping();
+        let to_be_sent_response = internal_request_gate(
+            &mut session.reader,
+            limits,
+            hosts,
+            service,
+            metrics_path,
+            keep_alive_allowed,
+            session.remote,
+        );
+        match to_be_sent_response {
+            Ok(true) => {}
+            Ok(false) => return false,
+            Err(e) => {
+                // The host of a request that failed is unknown, so the default server answers
+                error_response(session.reader.get_mut(), e, hosts.default_site(), session.remote);
+                return false;
+            }
         }
-        Err(e) => {
-            println!("Error: {}", e);
-            return None;
+        // A request that already arrived is answered right away, otherwise the connection waits
+        // for it without holding the worker
+        if !session.reader.is_buffered() && !session.reader.get_mut().has_pending() {
+            return connection.idle();
+        } else if connection.is_stopping() {
+            return false;
         }
     }
 }
 
-/// This function parses the filepath and returns a string that can be used to search for the file in
-/// the file system
+/// This function generates the precompressed `.br` and `.gz` siblings for every compressible file
+/// served by the sites, so they do not have to be compressed again for every request.
 ///
 /// # Parameters
 ///
-/// - `filepath`: This is the filepath that is to be parsed
+/// - `hosts`: This are the sites, whose document roots and location roots are precompressed
+pub fn precompress(hosts: &VirtualHosts) {
+    for root in hosts.roots() {
+        match precompressed::generate(root) {
+            Ok(written) => log::info!("Precompressed {} files in {:?}", written, root),
+            Err(e) => log::error!("Failed to precompress {:?}: {}", root, e),
+        }
+    }
+}
+
+/// This function answers a request that failed with the fitting error response. The connection is
+/// closed afterwards, as it is unclear where the next request would start.
+fn error_response<W: Write>(
+    stream: &mut W,
+    error: RequestError,
+    site: &SiteConfig,
+    remote: Option<IpAddr>,
+) {
+    let started = Instant::now();
+    let status = match error {
+        RequestError::Parse(e) => {
+            log::info!("The request could not be parsed: {}", e);
+            Status::from_code(e.status_code()).unwrap_or(Status::BadRequest)
+        }
+        RequestError::Closed => {
+            log::debug!("The client closed the connection");
+            return;
+        }
+        RequestError::Other(e) => {
+            log::info!("Request handling gave an error: {}", e);
+            Status::BadRequest
+        }
+    };
+    let sent = send_response(stream, http_codes::error(status, &site.error_pages), false, true);
+    record(remote, None, &sent, started);
+}
+
+/// This function searches for a matching file in the file system and returns its path and mime
+/// type if it exists. Only regular files that lie inside the document root, even after resolving
+/// symlinks, are considered. Precompressed siblings are left out, they are picked during the
+/// encoding negotiation instead. A directory is answered with the first index file name that
+/// matches a file.
+///
+/// # Parameters
+///
+/// - `route`: This is the route of the request, holding the root, the request path relative to
+///   it and the index file names
+/// - `accepted_mimes`: This is a vector of tuples that contain the mime type and the weight
+fn file_browser(route: &Route, accepted_mimes: Vec<(String, f32)>) -> Option<(PathBuf, String)> {
+    let filepath = route.path.as_str();
+    let base_path = route.root.to_string_lossy().to_string();
+    // A precompressed sibling is served when it is named in full, but never matches a wildcard
+    let named = PathBuf::from(format!("{}{}", base_path, filepath));
+
+    for index_file in route.index_files {
+        let parsed_filepath = parse_filepath(filepath, index_file);
+        let path_pattern = glob::Pattern::escape(&base_path) + &parsed_filepath;
+        let mut matching_files: Vec<(PathBuf, f32)> = Vec::new();
+
+        let Ok(entries) = glob::glob(&path_pattern) else {
+            log::warning!("The index file name {:?} is no valid pattern", index_file);
+            continue;
+        };
+        for entry in entries {
+            if let Ok(path) = &entry {
+                if !path.is_file()
+                    || !file_path::is_inside_root(path, route.root)
+                    || (precompressed::is_sibling(path) && *path != named)
+                {
+                    continue;
+                }
+            }
+            append_to_matching_files(&mut matching_files, entry, accepted_mimes.clone());
+        }
+
+        if !matching_files.is_empty() {
+            let used_file = best_match(matching_files);
+            let used_mime = mime_guess::from_path(&used_file).first_or_octet_stream();
+            return Some((used_file, used_mime.to_string()));
+        }
+        // Only directories have further index file names to fall back on
+        if !filepath.ends_with('/') {
+            break;
+        }
+    }
+    None
+}
+
+/// This function parses the filepath and returns a glob pattern that can be used to search for the
+/// file in the file system. Glob metacharacters of the filepath are escaped, so only the wildcards
+/// added here take effect.
+///
+/// # Parameters
+///
+/// - `filepath`: This is the normalized filepath that is to be parsed
+/// - `index_file`: This is the index file name looked for if the filepath is a directory. Its
+///   wildcards are kept.
 ///
 /// # Returns
 ///
 /// Returns a `String` that can be used to search for the file in the file system
-fn parse_filepath(filepath: &str) -> String {
-    let mut parsed_filepath = filepath.to_string();
-    // TODO: Check if allowing ../ is a security risk
-    if parsed_filepath == "/" {
-        parsed_filepath = "/index.*".to_string();
-    } else if parsed_filepath.contains('.') {
-        parsed_filepath = format!("/{}", parsed_filepath);
+fn parse_filepath(filepath: &str, index_file: &str) -> String {
+    let escaped_filepath = glob::Pattern::escape(filepath);
+    let file_name = filepath.rsplit('/').next().unwrap_or_default();
+
+    if filepath.ends_with('/') {
+        format!("{}{}", escaped_filepath, index_file)
+    } else if file_name.contains('.') {
+        escaped_filepath
     } else {
-        parsed_filepath = format!("{}*", parsed_filepath);
+        format!("{}*", escaped_filepath)
     }
-    parsed_filepath
 }
 
 /// This function appends the matching files to the matching_files vector
@@ -163,11 +812,11 @@ fn append_to_matching_files(
                     weight = w;
                 }
 
-                println!("Serving file: {:?}", path);
+                log::trace!("Found the candidate {:?}", path);
                 matching_files.push((path, weight));
             }
         }
-        Err(e) => println!("{:?}", e),
+        Err(e) => log::warning!("{:?}", e),
     }
 }
 
@@ -183,7 +832,7 @@ fn append_to_matching_files(
 fn default_weight(mimes: &Vec<(String, f32)>) -> f32 {
     for (m, w) in mimes {
         if m == "*/*" {
-            return w.clone();
+            return *w;
         }
     }
     0.0
@@ -203,7 +852,7 @@ fn find_weight_for_mime(extension: String, mimes: &Vec<(String, f32)>) -> Option
     for (m, w) in mimes {
         let current_extension = m.split("/").collect::<Vec<&str>>()[1];
         if extension == current_extension {
-            return Some(w.clone());
+            return Some(*w);
         }
     }
     None
@@ -232,41 +881,131 @@ fn best_match(matching_files: Vec<(PathBuf, f32)>) -> PathBuf {
     best_match.to_path_buf()
 }
 
-fn correct_mime(mime: Mime, accepted_mimes: Vec<(String, f32)>) -> String {
-    let mime_string = mime.to_string();
-    for (m, _) in accepted_mimes {
-        if m.eq(&mime_string) {
-            return mime_string;
-        }
-    }
-    "*/*".to_string()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tcp;
 
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::Path;
     use std::thread;
     use tokio::test as tokio_test;
 
-    #[tokio_test]
-    async fn test_ok_writes_ok() -> Result<(), reqwest::Error> {
-        let listener = tcp::spawn_tcp_server("127.0.0.1:0");
+    /// This returns the site of the repository, served from `public`
+    fn test_site() -> SiteConfig {
+        SiteConfig::new(Path::new("public"), None, Vec::new()).expect("public is missing")
+    }
+
+    /// This builds a server for a test, serving `public` over plain HTTP with the default limits
+    /// and two workers until something else is set
+    struct TestServer {
+        hosts: VirtualHosts,
+        service: Service,
+        metrics_path: Option<&'static str>,
+        limits: RequestLimits,
+        workers: usize,
+        shutdown: Arc<tcp::Shutdown>,
+    }
+
+    impl TestServer {
+        fn new() -> TestServer {
+            TestServer {
+                hosts: VirtualHosts::from(test_site()),
+                service: Service::Http,
+                metrics_path: None,
+                limits: RequestLimits::default(),
+                workers: 2,
+                shutdown: Arc::new(tcp::Shutdown::new(tcp::SHUTDOWN_TIMEOUT)),
+            }
+        }
+
+        /// This sets the sites served, either a single `SiteConfig` or `VirtualHosts`
+        fn hosts(mut self, hosts: impl Into<VirtualHosts>) -> TestServer {
+            self.hosts = hosts.into();
+            self
+        }
+
+        /// This sets how connections are answered
+        fn service(mut self, service: Service) -> TestServer {
+            self.service = service;
+            self
+        }
+
+        /// This sets the path the metrics are served at besides the files
+        fn metrics_path(mut self, path: &'static str) -> TestServer {
+            self.metrics_path = Some(path);
+            self
+        }
+
+        /// This sets the limits of every request and connection
+        fn limits(mut self, limits: RequestLimits) -> TestServer {
+            self.limits = limits;
+            self
+        }
+
+        /// This sets the amount of worker threads
+        fn workers(mut self, workers: usize) -> TestServer {
+            self.workers = workers;
+            self
+        }
 
-        let _port = listener
-            .local_addr()
-            .expect("Failed to get the local address")
-            .port();
+        /// This shares the `Shutdown` with the test, so it can stop the server
+        fn shutdown(mut self, shutdown: &Arc<tcp::Shutdown>) -> TestServer {
+            self.shutdown = Arc::clone(shutdown);
+            self
+        }
+
+        /// This spawns the server on a random port and returns the port
+        fn spawn(self) -> u16 {
+            let listener = tcp::spawn_tcp_server("127.0.0.1:0", tcp::RECONNECT_TRIES);
+            let port = listener
+                .local_addr()
+                .expect("Failed to get the local address")
+                .port();
+
+            let TestServer {
+                hosts,
+                service,
+                metrics_path,
+                limits,
+                workers,
+                shutdown,
+            } = self;
+            thread::spawn(move || {
+                tcp::handle_incoming_connections(
+                    vec![(listener, service)],
+                    workers,
+                    &shutdown,
+                    move |turn, service, connection| {
+                        request_gate(turn, &limits, &hosts, service, metrics_path, connection)
+                    },
+                );
+            });
+
+            port
+        }
+    }
+
+    /// This sends a raw request to the server and returns the whole response
+    fn send_raw_request(port: u16, request: &[u8]) -> std::io::Result<String> {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        stream.write_all(request)?;
 
-        thread::spawn(move || {
-            tcp::handle_incoming_connections(listener, &request_gate);
-        });
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer)?;
+
+        Ok(String::from_utf8_lossy(&buffer).to_string())
+    }
+
+    #[tokio_test]
+    async fn test_ok_writes_ok() -> Result<(), reqwest::Error> {
+        let _port = TestServer::new().spawn();
 
         let client = reqwest::Client::new();
 
         let res = client
-            .get(format!("http://127.0.0.1:{}", _port.to_string()))
+            .get(format!("http://127.0.0.1:{}", _port))
             .send()
             .await?;
 
@@ -282,23 +1021,14 @@ mod tests {
 
     #[tokio_test]
     async fn test_not_found_gives_400() -> Result<(), reqwest::Error> {
-        let listener = tcp::spawn_tcp_server("127.0.0.1:0");
-
-        let _port = listener
-            .local_addr()
-            .expect("Failed to get the local address")
-            .port();
-
-        thread::spawn(move || {
-            tcp::handle_incoming_connections(listener, &request_gate);
-        });
+        let _port = TestServer::new().spawn();
 
         let client = reqwest::Client::new();
 
         let res = client
             .get(format!(
                 "http://127.0.0.1:{}/jgerhgirehglrekrgrej",
-                _port.to_string()
+                _port
             ))
             .send()
             .await?;
@@ -313,16 +1043,7 @@ mod tests {
 
     #[test]
     fn test_malformed_request_triggers_bad_request() -> std::io::Result<()> {
-        use std::io::{Read, Write};
-        use std::net::TcpStream;
-
-        let address = "127.0.0.1:0";
-        let listener = tcp::spawn_tcp_server(address);
-        let port = listener.local_addr().unwrap().port();
-
-        std::thread::spawn(move || {
-            tcp::handle_incoming_connections(listener, &request_gate);
-        });
+        let port = TestServer::new().spawn();
 
         let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
 
@@ -339,4 +1060,944 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_large_headers_are_read_completely() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nCookie: {}\r\n\r\n",
+            "a".repeat(4000)
+        );
+
+        let response = send_raw_request(port, request.as_bytes())?;
+
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK"),
+            "A request with headers larger than a single read was not served"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_headers_trigger_431() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_header_size)
+        );
+
+        let response = send_raw_request(port, request.as_bytes())?;
+
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"),
+            "The response does not contain the expected 431 status"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_overly_long_target_triggers_414() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+        let request = format!(
+            "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_target_length)
+        );
+
+        let response = send_raw_request(port, request.as_bytes())?;
+
+        assert!(
+            response.starts_with("HTTP/1.1 414 URI Too Long"),
+            "The response does not contain the expected 414 status"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_serves_several_requests() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /jgerhgirehglrekrgrej HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(response.matches("HTTP/1.1 404 Not Found").count(), 1);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 2);
+        assert_eq!(response.matches("Connection: close").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_connection_is_closed_after_timeout() -> std::io::Result<()> {
+        let port = TestServer::new()
+            .limits(RequestLimits {
+                keep_alive_timeout: std::time::Duration::from_millis(200),
+                ..RequestLimits::default()
+            })
+            .workers(1)
+            .spawn();
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        // Without the idle timeout the read would block until this one ran out
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let started = Instant::now();
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer)?;
+        let elapsed = started.elapsed();
+
+        assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK"));
+        assert!(
+            elapsed >= std::time::Duration::from_millis(200)
+                && elapsed < std::time::Duration::from_secs(2),
+            "The idle connection was closed after {:?} instead of the timeout",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_connections_do_not_hold_the_workers() -> std::io::Result<()> {
+        let port = TestServer::new().workers(2).spawn();
+
+        let mut idle = Vec::new();
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+            stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+            stream.write_all(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+            let mut head = Vec::new();
+            let mut byte = [0; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte)?;
+                head.push(byte[0]);
+            }
+            assert!(String::from_utf8_lossy(&head).starts_with("HTTP/1.1 200 OK"));
+            idle.push(stream);
+        }
+
+        // Both workers would still wait on the idle connections if they held on to them
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        let started = Instant::now();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer)?;
+        let elapsed = started.elapsed();
+
+        assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK"));
+        assert!(
+            elapsed < std::time::Duration::from_secs(1),
+            "The new client was answered after {:?}",
+            elapsed
+        );
+
+        // The idle connections are served again once their next request arrives
+        for mut stream in idle {
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer)?;
+            assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_trickled_header_section_triggers_408() -> std::io::Result<()> {
+        let port = TestServer::new()
+            .limits(RequestLimits {
+                header_timeout: std::time::Duration::from_millis(300),
+                ..RequestLimits::default()
+            })
+            .spawn();
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        stream.write_all(b"GET / HTTP/1.1\r\n")?;
+        let started = Instant::now();
+        // Every line arrives well within the read timeout, but the section never ends
+        for _ in 0..20 {
+            thread::sleep(std::time::Duration::from_millis(100));
+            if stream.write_all(b"X-Slow: 1\r\n").is_err() {
+                break;
+            }
+        }
+        let mut buffer = Vec::new();
+        let _ = stream.read_to_end(&mut buffer);
+
+        assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 408 Request Timeout"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stopping_closes_idle_connections() -> std::io::Result<()> {
+        let shutdown = Arc::new(tcp::Shutdown::new(tcp::SHUTDOWN_TIMEOUT));
+        let port = TestServer::new().workers(1).shutdown(&shutdown).spawn();
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        thread::sleep(std::time::Duration::from_millis(200));
+        let started = Instant::now();
+        shutdown.stop();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: keep-alive"));
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(1),
+            "The idle connection was not closed when the server stopped"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_requests_per_connection() -> std::io::Result<()> {
+        let port = TestServer::new()
+            .limits(RequestLimits {
+                max_keep_alive_requests: 2,
+                ..RequestLimits::default()
+            })
+            .workers(1)
+            .spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )?;
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.contains("Connection: close"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_filepath_escapes_glob_characters() {
+        assert_eq!(parse_filepath("/", "index.*"), "/index.*");
+        assert_eq!(parse_filepath("/docs/", "index.*"), "/docs/index.*");
+        assert_eq!(parse_filepath("/index.html", "index.*"), "/index.html");
+        assert_eq!(parse_filepath("/index", "index.*"), "/index*");
+        assert_eq!(parse_filepath("/*.html", "index.*"), "/[*].html");
+        assert_eq!(parse_filepath("/ind?x", "index.*"), "/ind[?]x*");
+    }
+
+    #[test]
+    fn test_path_traversal_is_forbidden() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for target in ["/../Cargo.toml", "/%2e%2e/Cargo.toml", "/a/../../src/main.rs"] {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                target
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(
+                response.starts_with("HTTP/1.1 403 Forbidden"),
+                "{} was not forbidden",
+                target
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_characters_do_not_match_files() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for target in ["/*.html", "/ind%3Fx.html", "/[i]ndex.html", "/%2A.html"] {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                target
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found"),
+                "{} matched a file",
+                target
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_head_sends_headers_without_body() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let get = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        let head = send_raw_request(
+            port,
+            b"HEAD / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+
+        let get_headers = get.split("\r\n\r\n").next().unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(head.trim_end(), get_headers, "HEAD did not match the GET headers");
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_lists_allowed_methods() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for target in ["*", "/"] {
+            let request = format!(
+                "OPTIONS {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                target
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(response.starts_with("HTTP/1.1 204 No Content"));
+            assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_methods_give_405() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for method in ["POST", "PUT", "DELETE"] {
+            let request = format!(
+                "{} / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                method
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(
+                response.starts_with("HTTP/1.1 405 Method Not Allowed"),
+                "{} was not answered with 405",
+                method
+            );
+            assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_methods_give_501() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(port, b"BREW /pot HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_1_0_is_served_and_closed() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(port, b"GET / HTTP/1.0\r\n\r\n")?;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.contains("Served HTML content from Anes HTTP!"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_1_0_keep_alive() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+        )?;
+
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_versions_give_505() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for version in ["HTTP/2.0", "HTTP/3.0", "HTTP/0.9"] {
+            let request = format!("GET / {}\r\nHost: localhost\r\n\r\n", version);
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(
+                response.starts_with("HTTP/1.1 505 HTTP Version Not Supported"),
+                "{} was not answered with 505",
+                version
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_encoding_is_negotiated() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for (accept_encoding, expected) in [
+            ("gzip, br;q=0.8", Some("gzip")),
+            ("br, zstd", Some("br")),
+            ("zstd, gzip;q=0.5", Some("zstd")),
+            ("deflate", Some("deflate")),
+            ("compress", None),
+        ] {
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n",
+                accept_encoding
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.contains("Vary: Accept-Encoding\r\n"));
+            match expected {
+                Some(coding) => assert!(
+                    response.contains(&format!("Content-Encoding: {}\r\n", coding)),
+                    "{} was not answered with {}",
+                    accept_encoding,
+                    coding
+                ),
+                None => assert!(!response.contains("Content-Encoding")),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_types_are_sent_as_is() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /favicon.ico HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+        )?;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(!response.contains("Content-Encoding"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refused_identity_gives_406() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: identity;q=0\r\nConnection: close\r\n\r\n",
+        )?;
+
+        assert!(response.starts_with("HTTP/1.1 406 Not Acceptable"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_requests() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        let header = |name: &str| {
+            response
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+                .map(|value| value.to_string())
+                .unwrap()
+        };
+        let etag = header("ETag");
+        let last_modified = header("Last-Modified");
+
+        for condition in [
+            format!("If-None-Match: {}", etag),
+            format!("If-Modified-Since: {}", last_modified),
+        ] {
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\nConnection: close\r\n\r\n",
+                condition
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+
+            assert!(response.starts_with("HTTP/1.1 304 Not Modified"), "{}", condition);
+            assert!(response.contains(&format!("ETag: {}\r\n", etag)));
+            assert!(response.ends_with("\r\n\r\n"), "a 304 carried a body");
+        }
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nIf-Match: \"other\"\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_requests() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-2\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(response.contains("Content-Range: bytes 0-2/"));
+        assert!(response.ends_with("\r\n\r\n<b>"));
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=100000-\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-2\r\nIf-Range: \"outdated\"\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Accept-Ranges: bytes\r\n"));
+
+        // A body compressed while sending is always sent whole
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nRange: bytes=0-2\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Encoding: gzip\r\n"));
+        assert!(response.contains("Accept-Ranges: none\r\n"));
+        assert!(!response.contains("Content-Range"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunked_request_bodies_keep_the_connection_usable() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n0\r\nExpires: never\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"));
+        assert!(response.contains("HTTP/1.1 200 OK"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_transfer_encodings_are_rejected() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        let response = send_raw_request(
+            port,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\nhello",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+
+        let response = send_raw_request(
+            port,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented"));
+
+        let response = send_raw_request(
+            port,
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serves_a_configured_site() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("anes-http-site-{}", std::process::id()));
+        let (root, errors) = (base.join("www"), base.join("errors"));
+        std::fs::create_dir_all(root.join("docs"))?;
+        std::fs::create_dir_all(&errors)?;
+        std::fs::write(root.join("docs/home.htm"), "home")?;
+        std::fs::write(root.join("docs/index.txt"), "index")?;
+        std::fs::write(errors.join("404.html"), "custom not found")?;
+
+        let site = SiteConfig::new(
+            &root,
+            Some(&errors),
+            vec!["default.*".to_string(), "home.htm".to_string()],
+        )
+        .expect("The site could not be resolved");
+        let port = TestServer::new().hosts(site).spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /docs/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nhome"));
+
+        let response = send_raw_request(
+            port,
+            b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(response.ends_with("\r\n\r\ncustom not found"));
+
+        std::fs::remove_dir_all(base)
+    }
+
+    #[test]
+    fn test_precompressed_siblings_stay_inside_the_root() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("anes-http-siblings-{}", std::process::id()));
+        let root = base.join("www");
+        std::fs::create_dir_all(&root)?;
+        std::fs::write(root.join("app.js"), "let app;")?;
+        std::fs::write(base.join("secret.gz"), "secret")?;
+        std::os::unix::fs::symlink(base.join("secret.gz"), root.join("app.js.gz"))?;
+        std::fs::write(root.join("data.txt"), "data")?;
+        std::fs::write(root.join("data.txt.gz"), "gzipped data")?;
+
+        let site = SiteConfig::new(&root, None, vec!["index.html".to_string()])
+            .expect("The site could not be resolved");
+        let port = TestServer::new().hosts(site).spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /app.js HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(!response.contains("secret"));
+
+        let response = send_raw_request(
+            port,
+            b"GET /data.txt.gz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\ngzipped data"));
+
+        let response = send_raw_request(
+            port,
+            b"GET /data HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\ndata"));
+
+        std::fs::remove_dir_all(base)
+    }
+
+    #[test]
+    fn test_locations_set_headers_and_compression() -> std::io::Result<()> {
+        let mut site = test_site();
+        site.headers = vec![("X-Content-Type-Options".to_string(), "nosniff".to_string())];
+        let mut location = Location::new("/static", Some(Path::new("public"))).unwrap();
+        location.compression = Some(false);
+        location.cache_control = Some("max-age=3600".to_string());
+        site.locations.push(location);
+        let port = TestServer::new().hosts(site).spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /static/index.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Cache-Control: max-age=3600\r\n"));
+        assert!(response.contains("X-Content-Type-Options: nosniff\r\n"));
+        assert!(!response.contains("Content-Encoding"));
+
+        let response = send_raw_request(
+            port,
+            b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        assert!(response.contains("Content-Encoding: gzip\r\n"));
+        assert!(!response.contains("Cache-Control"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serves_the_site_of_the_host() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("anes-http-hosts-{}", std::process::id()));
+        let mut hosts = Vec::new();
+        for (name, pattern) in [("default", None), ("a", Some("a.test")), ("b", Some("*.b.test"))] {
+            std::fs::create_dir_all(base.join(name))?;
+            std::fs::write(base.join(name).join("index.html"), name)?;
+            hosts.push(VirtualHost {
+                names: pattern
+                    .map(|pattern| vec![HostPattern::parse(pattern).unwrap()])
+                    .unwrap_or_default(),
+                site: SiteConfig::new(&base.join(name), None, Vec::new()).unwrap(),
+                certificate: None,
+            });
+        }
+        let port = TestServer::new().hosts(VirtualHosts::new(hosts, 0)).spawn();
+
+        for (host, body) in [
+            ("A.test:8080", "a"),
+            ("www.b.test", "b"),
+            ("b.test", "default"),
+            ("unknown.test", "default"),
+        ] {
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                host
+            );
+            let response = send_raw_request(port, request.as_bytes())?;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", host);
+            assert!(response.ends_with(&format!("\r\n\r\n{}", body)), "{}", host);
+        }
+        let response = send_raw_request(
+            port,
+            b"GET http://a.test/ HTTP/1.1\r\nHost: unknown.test\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.ends_with("\r\n\r\na"));
+        let response = send_raw_request(port, b"GET / HTTP/1.0\r\n\r\n")?;
+        assert!(response.ends_with("\r\n\r\ndefault"));
+
+        std::fs::remove_dir_all(base)
+    }
+
+    /// This generates a self-signed certificate for the given names and loads it like the server
+    /// does, returning it together with the certificate clients have to trust
+    fn self_signed(
+        directory: &Path,
+        names: &[&str],
+    ) -> (Arc<rustls::sign::CertifiedKey>, rustls_pki_types::CertificateDer<'static>) {
+        let names = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let generated = rcgen::generate_simple_self_signed(names.clone()).unwrap();
+        let (certificate, key) = (
+            directory.join(format!("{}.pem", names[0])),
+            directory.join(format!("{}.key", names[0])),
+        );
+        std::fs::write(&certificate, generated.cert.pem()).unwrap();
+        std::fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+        (
+            tls::load_certificate(&certificate, &key).unwrap(),
+            generated.cert.der().clone(),
+        )
+    }
+
+    /// This sends a request over TLS and returns the response, together with the certificate the
+    /// server presented and the negotiated application protocol
+    fn send_tls_request(
+        port: u16,
+        server_name: &str,
+        trusted: &[rustls_pki_types::CertificateDer<'static>],
+        request: &[u8],
+    ) -> std::io::Result<(String, Vec<u8>, Option<Vec<u8>>)> {
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in trusted {
+            roots.add(certificate.clone()).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let server_name = rustls_pki_types::ServerName::try_from(server_name.to_string()).unwrap();
+        let connection = rustls::ClientConnection::new(Arc::new(config), server_name).unwrap();
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        let mut stream = rustls::StreamOwned::new(connection, stream);
+        stream.write_all(request)?;
+
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer)?;
+        let presented = stream.conn.peer_certificates().unwrap()[0].to_vec();
+        let protocol = stream.conn.alpn_protocol().map(<[u8]>::to_vec);
+        Ok((String::from_utf8_lossy(&buffer).to_string(), presented, protocol))
+    }
+
+    #[test]
+    fn test_serves_https_with_the_certificate_of_the_host() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("anes-http-https-{}", std::process::id()));
+        std::fs::create_dir_all(&base)?;
+        let mut hosts = Vec::new();
+        let mut trusted = Vec::new();
+        for (name, patterns) in [("a", ["a.test"]), ("b", ["*.b.test"])] {
+            std::fs::create_dir_all(base.join(name))?;
+            std::fs::write(base.join(name).join("index.html"), name)?;
+            let (certificate, der) = self_signed(&base, &patterns);
+            trusted.push(der);
+            hosts.push(VirtualHost {
+                names: vec![HostPattern::parse(patterns[0]).unwrap()],
+                site: SiteConfig::new(&base.join(name), None, Vec::new()).unwrap(),
+                certificate: Some(certificate),
+            });
+        }
+        let hosts = Arc::new(VirtualHosts::new(hosts, 0));
+        let config = tls::server_config(&tls::TlsSettings::default(), Arc::clone(&hosts)).unwrap();
+        let port = TestServer::new()
+            .hosts((*hosts).clone())
+            .service(Service::Https(config))
+            .spawn();
+
+        for (index, (server_name, body)) in [("a.test", "a"), ("www.b.test", "b")].iter().enumerate() {
+            let request = format!(
+                "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                server_name
+            );
+            let (response, presented, protocol) =
+                send_tls_request(port, server_name, &trusted, request.as_bytes())?;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+            assert!(response.ends_with(&format!("\r\n\r\n{}", body)));
+            assert_eq!(presented, trusted[index].to_vec());
+            assert_eq!(protocol.as_deref(), Some(&b"http/1.1"[..]));
+        }
+
+        // Plain HTTP cannot be spoken to an HTTPS listener
+        assert!(!send_raw_request(port, b"GET / HTTP/1.1\r\nHost: a.test\r\n\r\n")?
+            .starts_with("HTTP/1.1"));
+        std::fs::remove_dir_all(base)
+    }
+
+    #[test]
+    fn test_tls_handshake_has_a_deadline() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("anes-http-handshake-{}", std::process::id()));
+        std::fs::create_dir_all(&base)?;
+        let (certificate, _) = self_signed(&base, &["localhost"]);
+        let hosts = Arc::new(VirtualHosts::new(
+            vec![VirtualHost {
+                names: Vec::new(),
+                site: test_site(),
+                certificate: Some(certificate),
+            }],
+            0,
+        ));
+        let config = tls::server_config(&tls::TlsSettings::default(), Arc::clone(&hosts)).unwrap();
+        let port = TestServer::new()
+            .hosts((*hosts).clone())
+            .service(Service::Https(config))
+            .limits(RequestLimits {
+                header_timeout: std::time::Duration::from_millis(300),
+                ..RequestLimits::default()
+            })
+            .spawn();
+
+        // The start of a record, with the rest of the handshake never following
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        stream.write_all(&[0x16, 0x03, 0x01])?;
+        let started = Instant::now();
+        let mut buffer = Vec::new();
+        let _ = stream.read_to_end(&mut buffer);
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(2),
+            "The stalled handshake was kept for {:?}",
+            started.elapsed()
+        );
+        std::fs::remove_dir_all(base)
+    }
+
+    #[test]
+    fn test_redirects_to_https() -> std::io::Result<()> {
+        let port = TestServer::new()
+            .service(Service::RedirectToHttps(8443))
+            .spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /docs/a%20b.html?x=1 HTTP/1.1\r\nHost: Example.test:7878\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"), "{}", response);
+        assert!(response.contains("Location: https://example.test:8443/docs/a%20b.html?x=1\r\n"));
+
+        let response = send_raw_request(
+            port,
+            b"POST http://example.test?x HTTP/1.1\r\nHost: example.test\r\nContent-Length: 0\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect"));
+        assert!(response.contains("Location: https://example.test:8443/?x\r\n"));
+
+        let response = send_raw_request(port, b"GET / HTTP/1.0\r\n\r\n")?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serves_the_metrics_at_their_path() -> std::io::Result<()> {
+        let port = TestServer::new().metrics_path("/metrics").spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let response = send_raw_request(
+            port,
+            b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+        assert!(response.contains("anes_http_requests_total{method=\"GET\",status=\"200\"} "));
+        assert!(response.contains("anes_http_request_duration_seconds_bucket{le=\"+Inf\"} "));
+        assert!(!response.contains("anes_http_compression_ratio_count 0\n"));
+        assert!(!response.contains("anes_http_open_connections 0\n"));
+
+        let response = send_raw_request(
+            port,
+            b"DELETE /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"));
+
+        // Requests that are not valid are rejected before the metrics are looked at
+        let response = send_raw_request(port, b"GET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+        let response = send_raw_request(port, b"GET /metrics HTTP/2.0\r\nHost: localhost\r\n\r\n")?;
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported"));
+
+        // Listeners redirecting to HTTPS redirect the metrics path like every other one
+        let port = TestServer::new()
+            .service(Service::RedirectToHttps(8443))
+            .metrics_path("/metrics")
+            .spawn();
+        let response = send_raw_request(
+            port,
+            b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"), "{}", response);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_listeners_only_serve_the_metrics() -> std::io::Result<()> {
+        let port = TestServer::new()
+            .service(Service::Metrics("/stats".to_string()))
+            .spawn();
+
+        let response = send_raw_request(
+            port,
+            b"GET /stats HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE anes_http_requests_total counter\n"));
+        let response = send_raw_request(
+            port,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_or_repeated_host_gives_400() -> std::io::Result<()> {
+        let port = TestServer::new().spawn();
+
+        for request in [
+            &b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nHost: a.test\r\nHost: b.test\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a/b\r\n\r\n",
+        ] {
+            let response = send_raw_request(port, request)?;
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+        }
+        Ok(())
+    }
 }
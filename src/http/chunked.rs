@@ -0,0 +1,97 @@
+use std::io::{self, Write};
+
+use super::headers::HeaderMap;
+
+/// This writes a body with the chunked transfer coding of RFC 9112, section 7.1. Every write
+/// becomes one chunk, prefixed by its size in hex, and `finish()` writes the terminating chunk
+/// followed by the trailer section.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    /// This Initializes a new `ChunkedWriter`
+    ///
+    /// # Parameters
+    ///
+    /// - `inner`: This is the connection the chunks are written to
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `ChunkedWriter`
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// This writes the last, empty chunk, which tells the client the body is complete, and the
+    /// trailer fields after it
+    ///
+    /// # Parameters
+    ///
+    /// - `trailers`: This are the fields sent after the body, which may be empty
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if writing to the client failed
+    pub fn finish(mut self, trailers: &HeaderMap) -> io::Result<()> {
+        let mut end = String::from("0\r\n");
+        for (name, value) in trailers.iter() {
+            end.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        end.push_str("\r\n");
+        self.inner.write_all(end.as_bytes())?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner
+            .write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_chunks() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output);
+
+        writer.write_all(b"Hello, ").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b"chunked world!").unwrap();
+        writer.finish(&HeaderMap::new()).unwrap();
+
+        assert_eq!(output, b"7\r\nHello, \r\ne\r\nchunked world!\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_writes_trailers_after_the_last_chunk() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output);
+        let mut trailers = HeaderMap::new();
+        trailers.append("Server-Timing", "app;dur=1.5");
+
+        writer.write_all(b"body").unwrap();
+        writer.finish(&trailers).unwrap();
+
+        assert_eq!(
+            output,
+            b"4\r\nbody\r\n0\r\nServer-Timing: app;dur=1.5\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::encoding::Encoding;
+use super::headers::HeaderMap;
+use super::method::Method;
+use super::response::Response;
+
+/// This holds the validators of a representation, which clients send back in conditional requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+/// This is the outcome of evaluating the preconditions of a request
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    /// Every precondition holds, the request is served as usual
+    Proceed,
+    /// The client already has the current representation, which is answered with 304
+    NotModified,
+    /// A precondition does not hold, which is answered with 412
+    Failed,
+}
+
+impl Validators {
+    /// This computes the validators of a file from its size and modification time. The entity tag
+    /// of a compressed representation carries the coding, so it never matches the one of another
+    /// coding. Compressing on the fly is not guaranteed to produce the same bytes every time, so
+    /// only those entity tags are weak.
+    ///
+    /// # Parameters
+    ///
+    /// - `metadata`: This is the metadata of the original file
+    /// - `encoding`: This is the coding the representation is sent with
+    /// - `precompressed`: This is true if the representation is a precompressed sibling
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Validators`
+    pub fn new(metadata: &Metadata, encoding: Encoding, precompressed: bool) -> Validators {
+        let last_modified = metadata.modified().ok();
+        let modified = last_modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let mut opaque = format!(
+            "{:x}-{:x}.{:x}",
+            metadata.len(),
+            modified.as_secs(),
+            modified.subsec_nanos()
+        );
+
+        let etag = match encoding {
+            Encoding::Identity => format!("\"{}\"", opaque),
+            encoding => {
+                opaque.push('-');
+                opaque.push_str(encoding.token());
+                if precompressed {
+                    format!("\"{}\"", opaque)
+                } else {
+                    format!("W/\"{}\"", opaque)
+                }
+            }
+        };
+
+        Validators {
+            etag,
+            last_modified,
+        }
+    }
+
+    /// This adds the `ETag` and `Last-Modified` headers to a response
+    ///
+    /// # Parameters
+    ///
+    /// - `response`: This is the response the headers are added to
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Response` with the validators
+    pub fn apply(&self, response: Response) -> Response {
+        let response = response.header("ETag", &self.etag);
+        match self.last_modified {
+            Some(last_modified) => {
+                response.header("Last-Modified", &httpdate::fmt_http_date(last_modified))
+            }
+            None => response,
+        }
+    }
+
+    /// This checks if the representation was modified after a date. HTTP dates only have a
+    /// precision of seconds, so the modification time is truncated to full seconds.
+    fn modified_since(&self, date: SystemTime) -> bool {
+        match self.last_modified {
+            Some(last_modified) => seconds(last_modified) > seconds(date),
+            None => true,
+        }
+    }
+}
+
+/// This function evaluates the preconditions of a request in the order of RFC 9110, section 13.2.2
+///
+/// # Parameters
+///
+/// - `method`: This is the method of the request
+/// - `headers`: This are the header fields of the request
+/// - `validators`: This are the validators of the selected representation
+///
+/// # Returns
+///
+/// Returns the `Precondition` that decides how the request is answered
+pub fn evaluate(method: &Method, headers: &HeaderMap, validators: &Validators) -> Precondition {
+    let is_get_or_head = matches!(method, Method::Get | Method::Head);
+
+    if headers.contains("If-Match") {
+        if !matches_any(headers, "If-Match", &validators.etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = header_date(headers, "If-Unmodified-Since") {
+        if validators.modified_since(date) {
+            return Precondition::Failed;
+        }
+    }
+
+    if headers.contains("If-None-Match") {
+        if matches_any(headers, "If-None-Match", &validators.etag, false) {
+            return if is_get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if is_get_or_head {
+        if let Some(date) = header_date(headers, "If-Modified-Since") {
+            if !validators.modified_since(date) {
+                return Precondition::NotModified;
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// This function checks the `If-Range` field of a range request. The ranges are only sent if the
+/// client still has the current representation, which is decided with the strong comparison of
+/// the entity tag or an exact match of the modification date.
+///
+/// # Parameters
+///
+/// - `headers`: This are the header fields of the request
+/// - `validators`: This are the validators of the selected representation
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if there is no `If-Range` field or it matches
+pub fn if_range_matches(headers: &HeaderMap, validators: &Validators) -> bool {
+    let value = match headers.get("If-Range") {
+        Some(value) => value.trim(),
+        None => return true,
+    };
+
+    if value.starts_with('"') || value.starts_with("W/") {
+        return !validators.etag.starts_with("W/") && value == validators.etag;
+    }
+    match (httpdate::parse_http_date(value), validators.last_modified) {
+        (Ok(date), Some(last_modified)) => seconds(date) == seconds(last_modified),
+        _ => false,
+    }
+}
+
+/// This function returns the full seconds since the unix epoch, the precision of HTTP dates
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// This function parses a header field holding an HTTP date. Invalid dates are ignored, as
+/// RFC 9110 requires.
+fn header_date(headers: &HeaderMap, name: &str) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|value| httpdate::parse_http_date(value.trim()).ok())
+}
+
+/// This function checks if any entity tag of an `If-Match` or `If-None-Match` field matches the
+/// entity tag of the representation
+///
+/// # Parameters
+///
+/// - `headers`: This are the header fields of the request
+/// - `name`: This is the name of the header field
+/// - `etag`: This is the entity tag of the representation
+/// - `strong`: This is true for the strong comparison, where weak entity tags never match
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if `*` or a matching entity tag was sent
+fn matches_any(headers: &HeaderMap, name: &str, etag: &str, strong: bool) -> bool {
+    let (etag_is_weak, etag_opaque) = split_weak(etag);
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(|value| entity_tags(value))
+        .any(|tag| {
+            if tag == "*" {
+                return true;
+            }
+            let (tag_is_weak, tag_opaque) = split_weak(tag);
+            tag_opaque == etag_opaque && !(strong && (tag_is_weak || etag_is_weak))
+        })
+}
+
+/// This function splits an entity tag into its weakness and the opaque quoted part
+fn split_weak(tag: &str) -> (bool, &str) {
+    match tag.strip_prefix("W/") {
+        Some(opaque) => (true, opaque),
+        None => (false, tag),
+    }
+}
+
+/// This function splits a list of entity tags. A comma is a valid character inside an entity tag,
+/// so the list is split at the commas outside of the quotes.
+///
+/// # Parameters
+///
+/// - `value`: This is the value of the header field
+///
+/// # Returns
+///
+/// Returns a `Vec<&str>` of the entity tags, including the quotes and weakness indicator
+fn entity_tags(value: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, character) in value.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                tags.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    tags.push(value[start..].trim());
+    tags.retain(|tag| !tag.is_empty());
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn validators(etag: &str, last_modified: u64) -> Validators {
+        Validators {
+            etag: etag.to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(last_modified)),
+        }
+    }
+
+    fn headers(fields: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in fields {
+            headers.append(name, value);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_etags_differ_per_coding() {
+        let metadata = std::fs::metadata("Cargo.toml").unwrap();
+
+        let identity = Validators::new(&metadata, Encoding::Identity, false);
+        let gzip = Validators::new(&metadata, Encoding::Gzip, false);
+        let brotli = Validators::new(&metadata, Encoding::Brotli, true);
+
+        assert!(identity.etag.starts_with('"'));
+        assert!(gzip.etag.starts_with("W/\"") && gzip.etag.ends_with("-gzip\""));
+        assert!(brotli.etag.starts_with('"') && brotli.etag.ends_with("-br\""));
+        assert_eq!(
+            identity,
+            Validators::new(&metadata, Encoding::Identity, false)
+        );
+    }
+
+    #[test]
+    fn test_if_none_match_gives_not_modified() {
+        let current = validators("W/\"abc\"", 1000);
+
+        for value in ["\"abc\"", "W/\"abc\"", "\"x\", W/\"abc\"", "*"] {
+            assert_eq!(
+                evaluate(
+                    &Method::Get,
+                    &headers(&[("If-None-Match", value)]),
+                    &current
+                ),
+                Precondition::NotModified,
+                "{} did not match",
+                value
+            );
+        }
+        assert_eq!(
+            evaluate(
+                &Method::Get,
+                &headers(&[("If-None-Match", "\"abcd\"")]),
+                &current
+            ),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(&Method::Post, &headers(&[("If-None-Match", "*")]), &current),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn test_if_match_uses_strong_comparison() {
+        let strong = validators("\"abc\"", 1000);
+        let weak = validators("W/\"abc\"", 1000);
+
+        let request = headers(&[("If-Match", "\"abc\"")]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &strong),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(&Method::Get, &request, &weak),
+            Precondition::Failed
+        );
+
+        let request = headers(&[("If-Match", "*")]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &weak),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn test_dates_are_compared_in_seconds() {
+        let current = Validators {
+            etag: "\"abc\"".to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(784_111_777_500)),
+        };
+        let at = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let before = "Sun, 06 Nov 1994 08:49:36 GMT";
+
+        let request = headers(&[("If-Modified-Since", at)]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::NotModified
+        );
+        let request = headers(&[("If-Modified-Since", before)]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::Proceed
+        );
+        let request = headers(&[("If-Modified-Since", "yesterday")]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::Proceed
+        );
+
+        let request = headers(&[("If-Unmodified-Since", before)]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::Failed
+        );
+        let request = headers(&[("If-Unmodified-Since", at)]);
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn test_etags_take_precedence_over_dates() {
+        let current = validators("\"abc\"", 1000);
+        let request = headers(&[
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+
+        assert_eq!(
+            evaluate(&Method::Get, &request, &current),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn test_if_range() {
+        let strong = validators("\"abc\"", 784_111_777);
+        let weak = validators("W/\"abc\"", 784_111_777);
+
+        assert!(if_range_matches(&headers(&[]), &weak));
+        let request = headers(&[("If-Range", "\"abc\"")]);
+        assert!(if_range_matches(&request, &strong));
+        assert!(!if_range_matches(&request, &weak));
+        let request = headers(&[("If-Range", "W/\"abc\"")]);
+        assert!(!if_range_matches(&request, &weak));
+
+        let request = headers(&[("If-Range", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(if_range_matches(&request, &strong));
+        let request = headers(&[("If-Range", "Sun, 06 Nov 1994 08:49:36 GMT")]);
+        assert!(!if_range_matches(&request, &strong));
+    }
+
+    #[test]
+    fn test_entity_tag_lists() {
+        assert_eq!(
+            entity_tags("\"a,b\", W/\"c\" ,,\"d\""),
+            vec!["\"a,b\"", "W/\"c\"", "\"d\""]
+        );
+    }
+}
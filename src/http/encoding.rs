@@ -0,0 +1,338 @@
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{self, prelude::*};
+
+/// This is the quality brotli compresses with, trading some ratio for speed.
+const BROTLI_QUALITY: u32 = 5;
+/// This is the base-2 logarithm of the brotli window size.
+const BROTLI_WINDOW: u32 = 22;
+/// This is the level zstd compresses with.
+const ZSTD_LEVEL: i32 = 3;
+
+/// This enum holds the content codings the server can apply to a response body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+/// This are the content codings in the order the server prefers them when the client weighs
+/// several of them equally.
+const PREFERENCE: [Encoding; 5] = [
+    Encoding::Brotli,
+    Encoding::Zstd,
+    Encoding::Gzip,
+    Encoding::Deflate,
+    Encoding::Identity,
+];
+
+impl Encoding {
+    /// This returns the token of the coding, as used in `Accept-Encoding` and `Content-Encoding`
+    pub fn token(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// This compresses data with the coding. The `deflate` coding is the zlib format, as required
+    /// by RFC 9110.
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: This is the data that is compressed
+    ///
+    /// # Returns
+    ///
+    /// Returns the compressed data, which is a copy of `data` for the identity coding
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Identity => data.to_vec(),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Failed to write data to gzip encoder");
+                encoder.finish().expect("Failed to compress data")
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Failed to write data to deflate encoder");
+                encoder.finish().expect("Failed to compress data")
+            }
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(
+                        &mut compressed,
+                        4096,
+                        BROTLI_QUALITY,
+                        BROTLI_WINDOW,
+                    );
+                    encoder
+                        .write_all(data)
+                        .expect("Failed to write data to brotli encoder");
+                }
+                compressed
+            }
+            Encoding::Zstd => zstd::encode_all(data, ZSTD_LEVEL).expect("Failed to compress data"),
+        }
+    }
+
+    /// This wraps a reader, so everything read from it comes out compressed with the coding. The
+    /// data is compressed while it is read, so it never has to be in memory as a whole.
+    ///
+    /// # Parameters
+    ///
+    /// - `reader`: This is the reader of the uncompressed data, e.g. a file
+    ///
+    /// # Returns
+    ///
+    /// Returns the compressing reader, which is `reader` itself for the identity coding
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the zstd encoder cannot be set up
+    pub fn encoder<R: Read + Send + 'static>(&self, reader: R) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Encoding::Identity => Box::new(reader),
+            Encoding::Gzip => {
+                Box::new(flate2::read::GzEncoder::new(reader, Compression::default()))
+            }
+            Encoding::Deflate => Box::new(flate2::read::ZlibEncoder::new(
+                reader,
+                Compression::default(),
+            )),
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            )),
+            Encoding::Zstd => Box::new(zstd::stream::read::Encoder::new(reader, ZSTD_LEVEL)?),
+        })
+    }
+}
+
+/// This function picks the content coding for a response, following the rules of RFC 9110: a
+/// coding that is not listed takes the weight of `*`, and `identity` is acceptable unless it is
+/// refused explicitly or through `*;q=0`. Of the codings with the highest weight, a precompressed
+/// one wins, followed by the one the server prefers.
+///
+/// # Parameters
+///
+/// - `accepted`: This are the codings of the accept-encoding attribute with their weights, as
+///   returned by `HttpObject::weighted_encodings()`
+/// - `precompressed`: This are the codings a precompressed copy of the body exists for
+///
+/// # Returns
+///
+/// Returns an `Option<Encoding>` that is `None` if the client accepts none of the codings, which
+/// is answered with 406 Not Acceptable
+pub fn negotiate(accepted: &[(String, f32)], precompressed: &[Encoding]) -> Option<Encoding> {
+    let weight_of = |token: &str| {
+        accepted
+            .iter()
+            .find(|(coding, _)| coding == token)
+            .map(|(_, weight)| *weight)
+    };
+    let wildcard = weight_of("*");
+
+    let mut best: Option<(Encoding, f32)> = None;
+    let candidates = precompressed
+        .iter()
+        .chain(PREFERENCE.iter().filter(|e| !precompressed.contains(e)));
+    for &encoding in candidates {
+        // `x-gzip` is an alias of `gzip`
+        let explicit = match encoding {
+            Encoding::Gzip => weight_of("gzip").or_else(|| weight_of("x-gzip")),
+            encoding => weight_of(encoding.token()),
+        };
+        let weight = match (explicit, wildcard, encoding) {
+            (Some(weight), _, _) => weight,
+            (None, Some(weight), _) => weight,
+            (None, None, Encoding::Identity) => 1.0,
+            (None, None, _) => 0.0,
+        };
+        if weight > 0.0 && best.is_none_or(|(_, best_weight)| weight > best_weight) {
+            best = Some((encoding, weight));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+/// This function decides if compressing a body of the given mime type is worthwhile. Text and
+/// text-like formats are, while images, audio, video, fonts like woff2 and archives are already
+/// compressed.
+///
+/// # Parameters
+///
+/// - `mime_type`: This is the mime type of the body, optionally with parameters
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if the body should be compressed
+pub fn is_compressible(mime_type: &str) -> bool {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence.as_str(),
+            "application/javascript"
+                | "application/json"
+                | "application/xml"
+                | "application/wasm"
+                | "application/manifest+json"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn weights(codings: &[(&str, f32)]) -> Vec<(String, f32)> {
+        codings
+            .iter()
+            .map(|(coding, weight)| (coding.to_string(), *weight))
+            .collect()
+    }
+
+    #[test]
+    fn test_highest_weight_wins() {
+        assert_eq!(
+            negotiate(&weights(&[("gzip", 0.5), ("deflate", 1.0)]), &[]),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            negotiate(&weights(&[("gzip", 1.0), ("br", 1.0), ("zstd", 1.0)]), &[]),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate(&weights(&[("x-gzip", 1.0)]), &[]),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_precompressed_codings_win_ties() {
+        let accepted = weights(&[("br", 1.0), ("gzip", 1.0)]);
+        assert_eq!(negotiate(&accepted, &[]), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate(&accepted, &[Encoding::Gzip]),
+            Some(Encoding::Gzip)
+        );
+
+        let accepted = weights(&[("br", 1.0), ("gzip", 0.5)]);
+        assert_eq!(
+            negotiate(&accepted, &[Encoding::Gzip]),
+            Some(Encoding::Brotli)
+        );
+    }
+
+    #[test]
+    fn test_identity_is_the_default() {
+        assert_eq!(negotiate(&[], &[]), Some(Encoding::Identity));
+        assert_eq!(
+            negotiate(&weights(&[("compress", 1.0)]), &[]),
+            Some(Encoding::Identity)
+        );
+        assert_eq!(
+            negotiate(&weights(&[("gzip", 0.0)]), &[]),
+            Some(Encoding::Identity)
+        );
+    }
+
+    #[test]
+    fn test_wildcard_and_refused_identity() {
+        assert_eq!(
+            negotiate(&weights(&[("*", 1.0)]), &[]),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate(&weights(&[("*", 1.0), ("br", 0.0), ("zstd", 0.0)]), &[]),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(&weights(&[("identity", 0.0)]), &[]), None);
+        assert_eq!(negotiate(&weights(&[("*", 0.0)]), &[]), None);
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let data = b"Served HTML content from Anes HTTP! ".repeat(20);
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&Encoding::Gzip.encode(&data)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let mut decoded = Vec::new();
+        flate2::read::ZlibDecoder::new(&Encoding::Deflate.encode(&data)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&Encoding::Brotli.encode(&data)[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let decoded = zstd::decode_all(&Encoding::Zstd.encode(&data)[..]).unwrap();
+        assert_eq!(decoded, data);
+
+        assert_eq!(Encoding::Identity.encode(&data), data);
+    }
+
+    #[test]
+    fn test_streaming_encoders_match_the_buffered_ones() {
+        let data = b"Served HTML content from Anes HTTP! ".repeat(20);
+
+        for encoding in PREFERENCE {
+            let mut streamed = Vec::new();
+            encoding
+                .encoder(io::Cursor::new(data.clone()))
+                .unwrap()
+                .read_to_end(&mut streamed)
+                .unwrap();
+            assert_eq!(
+                streamed,
+                encoding.encode(&data),
+                "{} differs",
+                encoding.token()
+            );
+        }
+    }
+
+    #[test]
+    fn test_compressed_types_are_skipped() {
+        assert!(is_compressible("text/html"));
+        assert!(is_compressible("text/css; charset=utf-8"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(is_compressible("application/javascript"));
+        assert!(!is_compressible("image/x-icon"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+        assert!(!is_compressible("font/woff2"));
+        assert!(!is_compressible("video/mp4"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+/// This describes why a request path cannot be mapped onto the document root
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    /// The path climbs above the document root with `..` segments
+    Escapes,
+    /// The path is not absolute or contains a NUL byte, which no file name can contain
+    Invalid,
+}
+
+/// This function removes the `.` and `..` segments of a percent-decoded request path, following
+/// the `remove_dot_segments` algorithm of RFC 3986. Empty segments are collapsed as well, so the
+/// result never contains `//`. Unlike RFC 3986, a `..` that would climb above the root is an error
+/// instead of being dropped silently.
+///
+/// # Parameters
+///
+/// - `path`: This is the percent-decoded request path
+///
+/// # Returns
+///
+/// Returns the normalized path, which always starts with a `/`
+///
+/// # Errors
+///
+/// Returns `PathError::Escapes` if the path climbs above the root, and `PathError::Invalid` if it
+/// is not absolute or contains a NUL byte
+pub fn normalize(path: &str) -> Result<String, PathError> {
+    if !path.starts_with('/') || path.contains('\0') {
+        return Err(PathError::Invalid);
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(PathError::Escapes);
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// This function checks that a file really lies inside the document root once every symlink is
+/// resolved
+///
+/// # Parameters
+///
+/// - `file`: This is the file that is about to be served
+/// - `root`: This is the document root
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if the file is inside the root. It is false if either path
+/// cannot be resolved.
+pub fn is_inside_root(file: &Path, root: &Path) -> bool {
+    match (file.canonicalize(), root.canonicalize()) {
+        (Ok(file), Ok(root)) => file.starts_with(root),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_removes_dot_segments() {
+        assert_eq!(normalize("/"), Ok("/".to_string()));
+        assert_eq!(normalize("/a/./b/../c"), Ok("/a/c".to_string()));
+        assert_eq!(normalize("/a//b///c"), Ok("/a/b/c".to_string()));
+        assert_eq!(normalize("/a/b/.."), Ok("/a/".to_string()));
+        assert_eq!(normalize("/a/."), Ok("/a/".to_string()));
+        assert_eq!(normalize("/a/../"), Ok("/".to_string()));
+        assert_eq!(normalize("/docs/"), Ok("/docs/".to_string()));
+        assert_eq!(normalize("/..a/b.."), Ok("/..a/b..".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_escaping_paths() {
+        assert_eq!(normalize("/.."), Err(PathError::Escapes));
+        assert_eq!(normalize("/../etc/passwd"), Err(PathError::Escapes));
+        assert_eq!(normalize("/a/../../etc/passwd"), Err(PathError::Escapes));
+    }
+
+    #[test]
+    fn test_rejects_invalid_paths() {
+        assert_eq!(normalize("*"), Err(PathError::Invalid));
+        assert_eq!(normalize("/index.html\0.png"), Err(PathError::Invalid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_out_of_the_root_are_detected() {
+        let base = std::env::temp_dir().join(format!("anes-http-root-{}", std::process::id()));
+        let root = base.join("public");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("inside.html"), "inside").unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+        let _ = std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link.txt"));
+
+        assert!(is_inside_root(&root.join("inside.html"), &root));
+        assert!(!is_inside_root(&root.join("link.txt"), &root));
+        assert!(!is_inside_root(&root.join("missing.html"), &root));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
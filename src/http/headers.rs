@@ -0,0 +1,147 @@
+/// This is a map of HTTP header fields. Field names are compared case-insensitively, a name can
+/// appear multiple times and the original order and spelling of the fields is kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    /// This Initializes a new, empty `HeaderMap`
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `HeaderMap`
+    pub fn new() -> HeaderMap {
+        HeaderMap { fields: Vec::new() }
+    }
+
+    /// This appends a field to the map, keeping all fields that already have the same name
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: This is the name of the header field
+    /// - `value`: This is the value of the header field
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// This replaces every field with the given name by a single new field. If there was no such
+    /// field, the new one is appended.
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: This is the name of the header field
+    /// - `value`: This is the value of the header field
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.fields.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.append(name, value);
+    }
+
+    /// This returns the value of the first field with the given name
+    ///
+    /// # Returns
+    ///
+    /// Returns an `Option<&str>` that is `None` if there is no such field
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// This returns the values of every field with the given name, in the order they were added
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Vec<&str>` of the values, which is empty if there is no such field
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// This returns the values of every field with the given name, split up at the commas of a
+    /// list-based field like `Accept-Encoding` or `Connection`. Empty list elements are skipped.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Vec<&str>` of the trimmed list elements
+    pub fn get_list(&self, name: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .map(|element| element.trim())
+            .filter(|element| !element.is_empty())
+            .collect()
+    }
+
+    /// This checks if a field with the given name exists
+    ///
+    /// # Returns
+    ///
+    /// Returns a `bool` that is true if there is at least one such field
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// This returns every field in the order they were added
+    ///
+    /// # Returns
+    ///
+    /// Returns an iterator over the names and values
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// This returns the amount of fields in the map, counting repeated names separately
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", "text/html");
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert!(headers.contains("Content-type"));
+    }
+
+    #[test]
+    fn test_repeated_fields_are_kept() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept-Encoding", "gzip");
+        headers.append("accept-encoding", "br, , deflate");
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(
+            headers.get_all("ACCEPT-ENCODING"),
+            vec!["gzip", "br, , deflate"]
+        );
+        assert_eq!(headers.get("Accept-Encoding"), Some("gzip"));
+        assert_eq!(
+            headers.get_list("Accept-Encoding"),
+            vec!["gzip", "br", "deflate"]
+        );
+    }
+
+    #[test]
+    fn test_insert_replaces_all_fields() {
+        let mut headers = HeaderMap::new();
+        headers.append("Vary", "Accept");
+        headers.append("vary", "Accept-Encoding");
+        headers.insert("VARY", "Origin");
+
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("Vary"), Some("Origin"));
+    }
+}
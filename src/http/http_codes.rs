@@ -1,69 +1,199 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::io::prelude::*;
-use std::net::TcpStream;
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::Path;
 
-mod bad_request;
-mod not_found;
-mod ok;
+use super::encoding::{self, Encoding};
+use super::response::{Body, Response, Status};
+use crate::metrics;
 
-/// This function writes a 400 Bad Request response to the client. The 400 file served is under
-/// `public/400.html`.
-/// If the file is not found, the response is a simple string.
-pub fn bad_request(stream: &TcpStream) {
-    err_handler(stream, "/public/400.html", "400 - Bad Request", bad_request::BAD_REQUEST.to_string())
+/// This is the size up to which files are compressed in memory instead of while sending.
+const MAX_BUFFERED_COMPRESSION: u64 = 1024 * 1024;
+
+/// This function builds a 200 OK response carrying the file content. The content is compressed
+/// with the negotiated coding, unless its mime type is already compressed. Small files are
+/// compressed in memory, so their length is known up front, while larger files are compressed
+/// while they are sent.
+///
+/// # Parameters
+///
+/// - `file`: This is the file that is served
+/// - `length`: This is the size of the file
+/// - `mime_type`: This is the mime type of the file
+/// - `encoding`: This is the coding negotiated with `encoding::negotiate()`
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the file cannot be read or the encoder cannot be set up
+pub fn ok(
+    mut file: File,
+    length: u64,
+    mime_type: &str,
+    encoding: Encoding,
+) -> io::Result<Response> {
+    let response = Response::new(Status::Ok).header("Content-Type", mime_type);
+    if !encoding::is_compressible(mime_type) {
+        return Ok(response.body(Body::File(file, length)));
+    }
+
+    let response = response.header("Vary", "Accept-Encoding");
+    if encoding == Encoding::Identity {
+        return Ok(response.body(Body::File(file, length)));
+    }
+
+    let response = response
+        .header("Content-Encoding", encoding.token())
+        .compressed_from(length);
+    if length <= MAX_BUFFERED_COMPRESSION {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(response.body(Body::Bytes(encoding.encode(&data))))
+    } else {
+        Ok(response.body(Body::Stream(encoding.encoder(file)?, None)))
+    }
+}
+
+/// This function builds a 200 OK response carrying a precompressed copy of the file content.
+///
+/// # Parameters
+///
+/// - `file`: This is the precompressed sibling
+/// - `length`: This is the size of the sibling
+/// - `mime_type`: This is the mime type of the original file
+/// - `encoding`: This is the coding the sibling is compressed with
+pub fn precompressed(file: File, length: u64, mime_type: &str, encoding: Encoding) -> Response {
+    Response::new(Status::Ok)
+        .header("Content-Type", mime_type)
+        .header("Vary", "Accept-Encoding")
+        .header("Content-Encoding", encoding.token())
+        .body(Body::File(file, length))
 }
 
-pub fn not_found(stream: &TcpStream) {
-    err_handler(stream, "/public/404.html", "404 - Not Found", not_found::NOT_FOUND.to_string())
+/// This function builds a 304 Not Modified response, which tells the client to use its cached
+/// copy. It carries the same `Vary` header as the 200 OK response it replaces.
+///
+/// # Parameters
+///
+/// - `mime_type`: This is the mime type of the file
+pub fn not_modified(mime_type: &str) -> Response {
+    let response = Response::new(Status::NotModified);
+    if encoding::is_compressible(mime_type) {
+        response.header("Vary", "Accept-Encoding")
+    } else {
+        response
+    }
+}
+
+/// This function builds an error response for the given status. The page served is
+/// `<code>.html` inside the error page directory, e.g. `404.html`. If the file is not found, the
+/// body is a simple string like `404 - Not Found`.
+///
+/// # Parameters
+///
+/// - `status`: This is the status of the error response
+/// - `error_pages`: This is the directory holding the error pages
+pub fn error(status: Status, error_pages: &Path) -> Response {
+    let html_path = error_pages.join(format!("{}.html", status.code()));
+    let html_content = match std::fs::read(&html_path) {
+        Ok(html_content) => html_content,
+        Err(_) => format!("{} - {}", status.code(), status.reason()).into_bytes(),
+    };
+
+    Response::new(status)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::Bytes(html_content))
+}
+
+/// This function builds a 405 Method Not Allowed response, listing the supported methods in the
+/// `Allow` header.
+pub fn method_not_allowed(allowed_methods: &str, error_pages: &Path) -> Response {
+    error(Status::MethodNotAllowed, error_pages).header("Allow", allowed_methods)
+}
 
+/// This function builds a redirect to another URL, given in the `Location` header.
+///
+/// # Parameters
+///
+/// - `status`: This is the status of the redirect, e.g. `301 Moved Permanently`
+/// - `location`: This is the URL the client is sent to
+pub fn redirect(status: Status, location: &str) -> Response {
+    Response::new(status)
+        .header("Location", location)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::Bytes(
+            format!("{} - {}: {}", status.code(), status.reason(), location).into_bytes(),
+        ))
 }
 
-/// This function sends a 200 OK response to the client. It does so by sending the header and body
-/// separately.
-pub fn ok(mut stream: &TcpStream, data: Vec<u8>, mime_type: String) {
-    let mut response = ok::OK.to_string();
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    encoder
-        .write_all(&data)
-        .expect("Failed to write data to gzip encoder");
-    let compressed_data = encoder.finish().expect("Failed to compress data");
-
-    response.push_str(&format!(
-        "Content-Length: {}\r\nContent-Type: {}\r\n\r\n",
-        compressed_data.len(),
-        mime_type
-    ));
-
-    let _ = stream.write_all(response.as_bytes());
-    let _ = stream.write_all(&compressed_data);
+/// This function builds the answer to an `OPTIONS` request, a 204 No Content response listing the
+/// supported methods in the `Allow` header.
+pub fn options(allowed_methods: &str) -> Response {
+    Response::new(Status::NoContent).header("Allow", allowed_methods)
 }
 
-/// This is the generic error handler for all error responses. It sends the response base and the
-/// compressed data to the client.
-fn err_handler(mut stream: &TcpStream, filename: &'static str, default_html: &'static str, response_base: String) {
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    let html_path = Path::new(filename);
-    let mut html_content = default_html.to_string();
+/// This function builds the response carrying the metrics of the server, which are never cached.
+///
+/// # Parameters
+///
+/// - `text`: This are the metrics in the Prometheus text format
+pub fn metrics(text: String) -> Response {
+    Response::new(Status::Ok)
+        .header("Content-Type", metrics::CONTENT_TYPE)
+        .header("Cache-Control", "no-store")
+        .body(Body::Bytes(text.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_falls_back_to_a_simple_page() {
+        let mut output = Vec::new();
+        error(Status::ImATeapot, Path::new("public"))
+            .write_to(&mut output, true)
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 418 I'm a teapot\r\n"));
+        assert!(!output.contains("Content-Encoding"));
+        assert!(output.ends_with("\r\n\r\n418 - I'm a teapot"));
+    }
+
+    #[test]
+    fn test_ok_compresses_only_compressible_types() {
+        let index = || File::open("public/index.html").unwrap();
+
+        let html = ok(index(), 13, "text/html", Encoding::Gzip).unwrap();
+        assert_eq!(html.headers().get("Content-Encoding"), Some("gzip"));
+        assert_eq!(html.headers().get("Vary"), Some("Accept-Encoding"));
+
+        let plain = ok(index(), 13, "text/html", Encoding::Identity).unwrap();
+        assert!(!plain.headers().contains("Content-Encoding"));
+        assert_eq!(plain.headers().get("Vary"), Some("Accept-Encoding"));
+
+        let icon = ok(index(), 13, "image/x-icon", Encoding::Brotli).unwrap();
+        assert!(!icon.headers().contains("Content-Encoding"));
+        assert!(!icon.headers().contains("Vary"));
+    }
+
+    #[test]
+    fn test_large_files_are_compressed_while_sending() {
+        let large = ok(
+            File::open("public/index.html").unwrap(),
+            MAX_BUFFERED_COMPRESSION + 1,
+            "text/html",
+            Encoding::Gzip,
+        )
+        .unwrap();
+
+        assert!(!large.is_delimited());
+    }
+
+    #[test]
+    fn test_method_not_allowed_lists_the_methods() {
+        let response = method_not_allowed("GET, HEAD", Path::new("public"));
 
-    if html_path.exists() {
-        html_content = std::fs::read_to_string(html_path).expect("Failed to open error html");
+        assert_eq!(response.status(), Status::MethodNotAllowed);
+        assert_eq!(response.headers().get("Allow"), Some("GET, HEAD"));
     }
-    let mut response: String = response_base;
-    encoder
-        .write_all(&html_content.as_bytes())
-        .expect("Failed to write data to gzip encoder");
-    let compressed_data = encoder.finish().expect("Failed to compress data");
-
-    response.push_str(&format!(
-        "Content-Length: {}\r\n\r\n",
-        compressed_data.len()
-    ));
-
-    println!("{}", response);
-    println!("{:?}", compressed_data);
-
-    let _ = stream.write_all(response.as_bytes());
-    let _ = stream.write_all(&compressed_data);
 }
@@ -1,138 +1,361 @@
+use super::headers::HeaderMap;
+use super::method::Method;
+
 /// This struct is used to store the attributes of the incoming http request
+#[derive(Debug)]
 pub struct HttpObject {
-    request: String,
-    accept: String,
-    // accept_encoding: String,
+    method: Method,
+    target: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    headers: HeaderMap,
+    host: Option<String>,
+    body: Vec<u8>,
 }
 
-/// This is the implementation of the HttpResponse. It gives the user methods to more easily
-/// interact with the HttpResponse struct by giving helper functions
+/// This is the implementation of the HttpObject. It gives the user methods to more easily
+/// interact with the HttpObject struct by giving helper functions
 impl HttpObject {
-    /// This Initializes a new `HttpObject`
+    /// This Initializes a new `HttpObject` without a body
+    ///
+    /// # Parameters
+    ///
+    /// - `method`: This is the method of the request line
+    /// - `target`: This is the raw request target, as it was sent by the client
+    /// - `path`: This is the percent-decoded path of the request target
+    /// - `query`: This is the raw query of the request target, if there is one
+    /// - `version`: This is the version of the request line, e.g. `HTTP/1.1`
+    /// - `headers`: This are the header fields of the request
+    /// - `host`: This is the normalized host the request is sent to, if it names one
     ///
     /// # Returns
     ///
     /// It returns the newly created `HttpObject`
-    pub fn new(request: String, accept: String) -> HttpObject {
+    pub fn new(
+        method: Method,
+        target: String,
+        path: String,
+        query: Option<String>,
+        version: String,
+        headers: HeaderMap,
+        host: Option<String>,
+    ) -> HttpObject {
         HttpObject {
-            request,
-            accept,
-            // Initialize accept_encoding or any other fields as needed
+            method,
+            target,
+            path,
+            query,
+            version,
+            headers,
+            host,
+            body: Vec::new(),
         }
     }
 
-    /// This function checks if the incoming request is an HTTP request
-    /// 
+    /// This function checks if the incoming request is an HTTP request this server can answer,
+    /// which is every HTTP/1.x request. Minor versions above 1.1 are answered as HTTP/1.1.
+    ///
     /// # Returns
     ///
-    /// Returns a boolean value that is true if the request is an HTTP request
+    /// Returns a boolean value that is true if the request is an HTTP/1.x request
     pub fn is_http(&self) -> bool {
-        let split_request = self.request.split_whitespace().collect::<Vec<&str>>();
-        if split_request.len() != 3 { return false; }
-        if split_request[0] != "GET" { return false; }
+        self.version.starts_with("HTTP/1.")
+    }
+
+    /// This function checks if the incoming request was sent by an HTTP/1.0 client, which
+    /// neither understands chunked responses nor keeps connections alive by default
+    pub fn is_http_1_0(&self) -> bool {
+        self.version == "HTTP/1.0"
+    }
+
+    /// This function returns the method of the incoming HTTP request
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
 
-        split_request[2] == "HTTP/1.1"
+    /// This function returns the request target exactly as it was sent by the client
+    pub fn target(&self) -> &str {
+        &self.target
     }
 
     /// This function returns the request path of the incoming HTTP request
     ///
     /// # Returns
     ///
-    /// Returns a `&str` of the request path
+    /// Returns a `&str` of the percent-decoded request path
     pub fn request_path(&self) -> &str {
-        self.request.split_whitespace().collect::<Vec<&str>>()[1]
+        &self.path
     }
 
-    /// This function returns the accept attribute of the incoming HTTP request
+    /// This function returns the query of the incoming HTTP request, without the leading `?`
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// This function returns the version of the incoming HTTP request, e.g. `HTTP/1.1`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// This function returns the host the request is sent to
     ///
     /// # Returns
     ///
-    /// Returns a `&str` of the accept attribute
-    pub fn weighted_mimes(&self) -> Result<Vec<(String, f32)>, String> {
-        if !self.accept_is_valid() {
-            return Err("No accept attribute found".to_string());
-        }
+    /// Returns the lowercase host without port and trailing dot, taken from an absolute-form
+    /// target or the `Host` header, or `None` if the request names no host
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
 
-        let types = self.extract_types();
-        let weights = self.extract_weights(types.len());
+    /// This function returns the header fields of the incoming HTTP request
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
 
-        let weighted_types = self.connect_types_to_weights(&types, &weights);
+    /// This function returns the body of the incoming HTTP request
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
 
-        Ok(weighted_types)
+    /// This function sets the body of the incoming HTTP request
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
     }
 
-    /// This function checks if the accept attribute is valid
+    /// This function returns the announced length of the body
     ///
     /// # Returns
     ///
-    /// It returns a `bool` that is true if the accept attribute is valid
-    fn accept_is_valid(&self) -> bool {
-        if !self.accept.len() == 0 { return false; }
-        if !(self.accept.contains("Accept:") || self.accept.contains("accept:")) { return false };
+    /// Returns an `Option<usize>` that is `None` if there is no valid `Content-Length` field
+    pub fn content_length(&self) -> Option<usize> {
+        self.headers.get("Content-Length")?.trim().parse().ok()
+    }
 
-        true
+    /// This function checks if the body of the request is sent with the chunked transfer coding,
+    /// which the parser only accepts as the last and only coding
+    pub fn is_chunked(&self) -> bool {
+        self.headers
+            .get_list("Transfer-Encoding")
+            .last()
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
     }
 
-    /// This function extracts the weights from the accept attribute
-    ///
-    /// # Parameters
-    ///
-    /// - `amount`: This is the amount of weights that are expected to be extracted. It is used to
-    /// fill the weights with `1.0` if there are no weights
+    /// This function checks if the client announced with `TE: trailers` that it reads trailer
+    /// fields after a chunked body
+    pub fn accepts_trailers(&self) -> bool {
+        self.headers
+            .get_list("TE")
+            .iter()
+            .any(|coding| coding.eq_ignore_ascii_case("trailers"))
+    }
+
+    /// This function checks if the client wants the connection to stay open after the response.
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`, HTTP/1.0
+    /// connections only stay open if the client sends `Connection: keep-alive`.
     ///
     /// # Returns
     ///
-    /// It returns a `Vec<f32>` of the weights. They are ordered as they appear in the accept. If
-    /// there are no weights it defaults to `1.0` for every type
-    fn extract_weights(&self, amount: usize) -> Vec<f32> {
-        let weights = self.accept.split("q=").collect::<Vec<&str>>();
-        if weights.len() == 1 {
-            return vec![1.0; amount]
+    /// Returns a `bool` that is true if the connection should be kept alive
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.headers.get_list("Connection");
+        let has_option = |option: &str| connection.iter().any(|c| c.eq_ignore_ascii_case(option));
+
+        if has_option("close") {
+            return false;
         }
-        let mut handled_weights: Vec<f32> = Vec::new();
-        for i in 1..weights.len() {
-            handled_weights.push(weights[i][..3].parse::<f32>().unwrap());
+        if self.is_http_1_0() {
+            return has_option("keep-alive");
         }
-
-        handled_weights
+        true
     }
 
-    /// This functions connects the given mime types to the passed weights
+    /// This function returns the mime types of the accept attribute together with their weights.
+    /// A request without an accept attribute accepts everything.
     ///
-    /// # Parameters
+    /// # Returns
     ///
-    /// - `types`: This is a `Vec<&str>` of the types
-    /// - `weights`: This is a `Vec<f32>` of the weights
+    /// Returns a `Vec<(String, f32)>` of the mime types and their weights, ordered as they appear
+    /// in the accept attribute
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// It returns a `Vec<(String, f32)>` of the types and their weights
-    fn connect_types_to_weights(&self, types: &Vec<&str>, weights: &Vec<f32>) -> Vec<(String, f32)> {
+    /// Returns an error if a mime type or a weight is malformed
+    pub fn weighted_mimes(&self) -> Result<Vec<(String, f32)>, String> {
+        if !self.headers.contains("Accept") {
+            return Ok(vec![("*/*".to_string(), 1.0)]);
+        }
+
         let mut weighted_types: Vec<(String, f32)> = Vec::new();
-        for i in 0..types.len() {
-            let type_vec = types[i].split(",").collect::<Vec<&str>>();
-            for j in 0..type_vec.len() {
-                weighted_types.push((type_vec[j].to_string(), weights[i]));
+        for media_range in self.headers.get_list("Accept") {
+            let mut parameters = media_range.split(';');
+            let mime_type = parameters.next().unwrap_or_default().trim();
+            if !mime_type.contains('/') {
+                return Err(format!(
+                    "Invalid mime type in accept attribute: {}",
+                    mime_type
+                ));
             }
+
+            weighted_types.push((mime_type.to_string(), extract_weight(parameters)?));
         }
-        weighted_types
+
+        Ok(weighted_types)
     }
 
-    /// This function extracts the types from the accept attribute
+    /// This function returns the content codings of the accept-encoding attribute together with
+    /// their weights. The codings are lowercased, as they are case-insensitive.
     ///
     /// # Returns
     ///
-    /// It returns a `Vec<&str>` of the types. They are ordered as they appear in the accept
-    fn extract_types(&self) -> Vec<&str> {
-        let formatted_accept = self.accept.split(": ").collect::<Vec<&str>>()[1];
-        let mut types = formatted_accept.split(";").collect::<Vec<&str>>();
-        if types.len() == 1 {
-            return vec![formatted_accept]
+    /// Returns a `Vec<(String, f32)>` of the codings and their weights, which is empty if there is
+    /// no accept-encoding attribute
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a weight is malformed
+    pub fn weighted_encodings(&self) -> Result<Vec<(String, f32)>, String> {
+        let mut weighted_codings: Vec<(String, f32)> = Vec::new();
+        for coding in self.headers.get_list("Accept-Encoding") {
+            let mut parameters = coding.split(';');
+            let name = parameters.next().unwrap_or_default().trim();
+            weighted_codings.push((name.to_ascii_lowercase(), extract_weight(parameters)?));
         }
-        types.pop();
-        for i in 1..types.len() {
-            types[i] = &types[i][6..];
+
+        Ok(weighted_codings)
+    }
+}
+
+/// This function extracts the weight from the parameters of a media range
+///
+/// # Parameters
+///
+/// - `parameters`: This are the `;`-separated parameters following the mime type
+///
+/// # Returns
+///
+/// It returns the weight as a `f32`. If there is no weight it defaults to `1.0`
+fn extract_weight<'a>(parameters: impl Iterator<Item = &'a str>) -> Result<f32, String> {
+    for parameter in parameters {
+        if let Some((name, value)) = parameter.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("q") {
+                return match value.trim().parse::<f32>() {
+                    Ok(weight) if (0.0..=1.0).contains(&weight) => Ok(weight),
+                    _ => Err(format!("Invalid weight in accept attribute: {}", value)),
+                };
+            }
         }
-        types
+    }
+    Ok(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_accept(accept: Option<&str>) -> HttpObject {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.append("Accept", accept);
+        }
+        HttpObject::new(
+            Method::Get,
+            "/".to_string(),
+            "/".to_string(),
+            None,
+            "HTTP/1.1".to_string(),
+            headers,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_only_http_1_is_supported() {
+        let mut request = request_with_accept(None);
+
+        for (version, supported) in [
+            ("HTTP/1.0", true),
+            ("HTTP/1.1", true),
+            ("HTTP/1.2", true),
+            ("HTTP/0.9", false),
+            ("HTTP/2.0", false),
+            ("HTTP/3.0", false),
+        ] {
+            request.version = version.to_string();
+            assert_eq!(request.is_http(), supported, "{} was misjudged", version);
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_defaults_per_version() {
+        let mut request = request_with_accept(None);
+        assert!(request.wants_keep_alive(), "HTTP/1.1 should be persistent");
+
+        request.version = "HTTP/1.0".to_string();
+        assert!(!request.wants_keep_alive(), "HTTP/1.0 should close");
+
+        request.headers.append("Connection", "Keep-Alive");
+        assert!(request.wants_keep_alive(), "HTTP/1.0 asked to keep alive");
+    }
+
+    #[test]
+    fn test_connection_close_is_honored() {
+        let mut request = request_with_accept(None);
+        request.headers.append("Connection", "upgrade, close");
+
+        assert!(!request.wants_keep_alive());
+    }
+
+    #[test]
+    fn test_weighted_mimes() {
+        let request = request_with_accept(Some("text/html, application/xml;q=0.9, */*;q=0.8"));
+
+        assert_eq!(
+            request.weighted_mimes().unwrap(),
+            vec![
+                ("text/html".to_string(), 1.0),
+                ("application/xml".to_string(), 0.9),
+                ("*/*".to_string(), 0.8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_accept_accepts_everything() {
+        let request = request_with_accept(None);
+
+        assert_eq!(
+            request.weighted_mimes().unwrap(),
+            vec![("*/*".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_invalid_accept_is_an_error() {
+        assert!(request_with_accept(Some("text/html;q=abc"))
+            .weighted_mimes()
+            .is_err());
+        assert!(request_with_accept(Some("html")).weighted_mimes().is_err());
+    }
+
+    #[test]
+    fn test_weighted_encodings() {
+        let mut request = request_with_accept(None);
+        assert!(request.weighted_encodings().unwrap().is_empty());
+
+        request
+            .headers
+            .append("Accept-Encoding", "GZIP, br;q=0.5, identity; q=0");
+        assert_eq!(
+            request.weighted_encodings().unwrap(),
+            vec![
+                ("gzip".to_string(), 1.0),
+                ("br".to_string(), 0.5),
+                ("identity".to_string(), 0.0),
+            ]
+        );
+
+        request.headers.append("Accept-Encoding", "zstd;q=2");
+        assert!(request.weighted_encodings().is_err());
     }
 }
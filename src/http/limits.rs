@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+/// This is the default size of the whole header section, request line included.
+const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+/// This is the default length of a single header line.
+const DEFAULT_MAX_HEADER_LINE_SIZE: usize = 8 * 1024;
+/// This is the default amount of header fields.
+const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+/// This is the default length of the request target.
+const DEFAULT_MAX_TARGET_LENGTH: usize = 8 * 1024;
+/// This is the default size of a request body.
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+/// This is the default amount of seconds the header section of a request may take to arrive.
+const DEFAULT_HEADER_TIMEOUT: u64 = 10;
+/// This is the default amount of seconds an idle keep-alive connection is kept open.
+const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+/// This is the default amount of requests served over a single connection.
+const DEFAULT_MAX_KEEP_ALIVE_REQUESTS: usize = 100;
+
+/// This struct holds the limits every incoming request, and every connection, has to stay within
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLimits {
+    /// The size of the whole header section, exceeding it is answered with 431
+    pub max_header_size: usize,
+    /// The length of a single header line, exceeding it is answered with 431
+    pub max_header_line_size: usize,
+    /// The amount of header fields, exceeding it is answered with 431
+    pub max_header_count: usize,
+    /// The length of the request target, exceeding it is answered with 414
+    pub max_target_length: usize,
+    /// The size of the request body, exceeding it is answered with 413
+    pub max_body_size: usize,
+    /// The time from the first byte of a request, or the start of a TLS handshake, until the
+    /// header section or the handshake has to be complete. Running out is answered with 408.
+    pub header_timeout: Duration,
+    /// The time a connection may sit idle between two requests before it is closed
+    pub keep_alive_timeout: Duration,
+    /// The amount of requests served over one connection before it is closed
+    pub max_keep_alive_requests: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_header_line_size: DEFAULT_MAX_HEADER_LINE_SIZE,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_target_length: DEFAULT_MAX_TARGET_LENGTH,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            header_timeout: Duration::from_secs(DEFAULT_HEADER_TIMEOUT),
+            keep_alive_timeout: Duration::from_secs(DEFAULT_KEEP_ALIVE_TIMEOUT),
+            max_keep_alive_requests: DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// This is the value of the `Allow` header, listing every method the static file server supports.
+pub const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// This enum holds the method of a request. The methods of RFC 7231 and RFC 5789 are known by
+/// name, everything else is kept as it was sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Method {
+    Get,
+    Head,
+    Options,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Connect,
+    Trace,
+    Other(String),
+}
+
+impl Method {
+    /// This parses the method token of a request line. Methods are case-sensitive, so `get` is an
+    /// unknown method and not `GET`.
+    ///
+    /// # Parameters
+    ///
+    /// - `token`: This is the method token of the request line
+    ///
+    /// # Returns
+    ///
+    /// Returns the matching `Method`
+    pub fn parse(token: &str) -> Method {
+        match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "CONNECT" => Method::Connect,
+            "TRACE" => Method::Trace,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    /// This returns the method as it appears in a request line
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+            Method::Other(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_known_methods() {
+        for token in [
+            "GET", "HEAD", "OPTIONS", "POST", "PUT", "DELETE", "PATCH", "CONNECT", "TRACE",
+        ] {
+            let method = Method::parse(token);
+            assert!(
+                !matches!(method, Method::Other(_)),
+                "{} is not known",
+                token
+            );
+            assert_eq!(method.as_str(), token);
+        }
+    }
+
+    #[test]
+    fn test_methods_are_case_sensitive() {
+        assert_eq!(Method::parse("get"), Method::Other("get".to_string()));
+        assert_eq!(Method::parse("BREW").to_string(), "BREW");
+    }
+}
@@ -0,0 +1,652 @@
+use std::fmt;
+
+use super::headers::HeaderMap;
+use super::http_object::HttpObject;
+use super::limits::RequestLimits;
+use super::method::Method;
+
+/// This describes everything that can be wrong with an incoming request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The request ended before the empty line that terminates the header section
+    Incomplete,
+    /// The request line does not consist of exactly a method, a target and a version
+    MalformedRequestLine,
+    /// The method is not a valid token
+    InvalidMethod,
+    /// The request target is not in origin-, absolute- or asterisk-form
+    InvalidTarget,
+    /// The version is not of the form `HTTP/x.y`
+    InvalidVersion,
+    /// The request target is longer than the `max_target_length` limit
+    UriTooLong,
+    /// A header line starts with whitespace, which is the obsolete line folding of RFC 7230
+    ObsoleteLineFolding,
+    /// A header line has no colon
+    MalformedHeader,
+    /// A header name is empty or contains something that is not a token character
+    InvalidHeaderName,
+    /// A header value contains control characters
+    InvalidHeaderValue,
+    /// The request line or a header field contains characters that are not allowed there
+    InvalidCharacter,
+    /// The header section or a single header line is larger than its limit
+    HeaderTooLarge,
+    /// There are more header fields than the `max_header_count` limit
+    TooManyHeaders,
+    /// The path contains a broken percent-encoding or does not decode to UTF-8
+    InvalidPercentEncoding,
+    /// The `Content-Length` is not a number, or repeated with different values
+    InvalidContentLength,
+    /// The body is larger than the `max_body_size` limit
+    BodyTooLarge,
+    /// The `Transfer-Encoding` does not end with `chunked`, is sent together with a
+    /// `Content-Length` or by an HTTP/1.0 client
+    InvalidTransferEncoding,
+    /// The `Transfer-Encoding` lists a coding other than `chunked`, which the server cannot decode
+    UnsupportedTransferCoding,
+    /// A chunk of a chunked body has an invalid size line or is not terminated by CRLF
+    InvalidChunk,
+    /// The `Host` header is repeated, or it or the authority of the target is no valid host
+    InvalidHost,
+    /// The header section did not arrive within the `header_timeout` limit
+    HeaderTimeout,
+}
+
+impl ParseError {
+    /// This returns the status code the client should be answered with
+    ///
+    /// # Returns
+    ///
+    /// Returns `408` for header sections that arrive too slowly, `413` for oversized bodies, `414`
+    /// for overly long targets, `431` for oversized header sections, `501` for transfer codings
+    /// the server does not know and `400` for everything else
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ParseError::HeaderTimeout => 408,
+            ParseError::BodyTooLarge => 413,
+            ParseError::UnsupportedTransferCoding => 501,
+            ParseError::UriTooLong => 414,
+            ParseError::HeaderTooLarge | ParseError::TooManyHeaders => 431,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ParseError::Incomplete => "the header section is not terminated",
+            ParseError::MalformedRequestLine => "the request line is malformed",
+            ParseError::InvalidMethod => "the method is not a valid token",
+            ParseError::InvalidTarget => "the request target is invalid",
+            ParseError::InvalidVersion => "the http version is invalid",
+            ParseError::UriTooLong => "the request target is too long",
+            ParseError::ObsoleteLineFolding => "obsolete line folding is not supported",
+            ParseError::MalformedHeader => "a header line has no colon",
+            ParseError::InvalidHeaderName => "a header name is invalid",
+            ParseError::InvalidHeaderValue => "a header value contains control characters",
+            ParseError::InvalidCharacter => "the request contains invalid characters",
+            ParseError::HeaderTooLarge => "the header section is too large",
+            ParseError::TooManyHeaders => "there are too many header fields",
+            ParseError::InvalidPercentEncoding => "the path has an invalid percent-encoding",
+            ParseError::InvalidContentLength => "the content length is invalid",
+            ParseError::BodyTooLarge => "the body is too large",
+            ParseError::InvalidTransferEncoding => "the transfer encoding is invalid",
+            ParseError::UnsupportedTransferCoding => "the transfer coding is not supported",
+            ParseError::InvalidChunk => "a chunk of the body is malformed",
+            ParseError::InvalidHost => "the host is invalid or repeated",
+            ParseError::HeaderTimeout => "the header section did not arrive in time",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// This function parses the request line and the header fields of a request
+///
+/// # Parameters
+///
+/// - `head`: This is the header section of the request, with or without the terminating empty
+///   line
+/// - `limits`: This are the limits the request line and header fields have to stay within
+///
+/// # Returns
+///
+/// Returns the parsed `HttpObject` without a body
+///
+/// # Errors
+///
+/// Returns a `ParseError` describing the first problem found in the header section
+pub fn parse_head(head: &[u8], limits: &RequestLimits) -> Result<HttpObject, ParseError> {
+    let mut lines = head
+        .split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        // RFC 7230 asks servers to ignore empty lines received before the request line
+        .skip_while(|line| line.is_empty());
+
+    let request_line = lines.next().ok_or(ParseError::Incomplete)?;
+    let (method, target, version) = parse_request_line(request_line, limits)?;
+    let (path, query) = parse_target(&target)?;
+
+    let mut headers = HeaderMap::new();
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = parse_header_line(line, limits)?;
+        if headers.len() == limits.max_header_count {
+            return Err(ParseError::TooManyHeaders);
+        }
+        headers.append(&name, &value);
+    }
+
+    validate_content_length(&headers)?;
+    validate_transfer_encoding(&headers, &version)?;
+    let host = parse_host(&target, &headers)?;
+
+    Ok(HttpObject::new(
+        Method::parse(&method),
+        target,
+        path,
+        query,
+        version,
+        headers,
+        host,
+    ))
+}
+
+/// This function splits the request line into the method, the request target and the version
+fn parse_request_line(
+    line: &[u8],
+    limits: &RequestLimits,
+) -> Result<(String, String, String), ParseError> {
+    if line
+        .iter()
+        .any(|b| !b.is_ascii() || (b.is_ascii_control() && *b != b'\t'))
+    {
+        return Err(ParseError::InvalidCharacter);
+    }
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidCharacter)?;
+
+    let parts = line.split(' ').collect::<Vec<&str>>();
+    if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+        return Err(ParseError::MalformedRequestLine);
+    }
+    let (method, target, version) = (parts[0], parts[1], parts[2]);
+
+    if !method.bytes().all(is_token_char) {
+        return Err(ParseError::InvalidMethod);
+    }
+    if target.len() > limits.max_target_length {
+        return Err(ParseError::UriTooLong);
+    }
+    if !target.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(ParseError::InvalidCharacter);
+    }
+    if !is_valid_version(version) {
+        return Err(ParseError::InvalidVersion);
+    }
+
+    Ok((method.to_string(), target.to_string(), version.to_string()))
+}
+
+/// This function checks that the version has the form `HTTP/<digit>.<digit>`
+fn is_valid_version(version: &str) -> bool {
+    match version.strip_prefix("HTTP/").map(|v| v.as_bytes()) {
+        Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// This function splits the request target into the percent-decoded path and the raw query
+///
+/// # Returns
+///
+/// Returns a tuple of the decoded path and the query, if there is one
+fn parse_target(target: &str) -> Result<(String, Option<String>), ParseError> {
+    if target == "*" {
+        return Ok(("*".to_string(), None));
+    }
+    if target.contains('#') {
+        return Err(ParseError::InvalidTarget);
+    }
+
+    let origin_form = if target.starts_with('/') {
+        target
+    } else if let Some(authority_and_path) = strip_scheme(target) {
+        match authority_and_path.find(['/', '?']) {
+            Some(index) if authority_and_path[index..].starts_with('/') => {
+                &authority_and_path[index..]
+            }
+            Some(index) => {
+                return Ok((
+                    "/".to_string(),
+                    Some(authority_and_path[index + 1..].to_string()),
+                ))
+            }
+            None => "/",
+        }
+    } else {
+        return Err(ParseError::InvalidTarget);
+    };
+
+    let (raw_path, query) = match origin_form.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (origin_form, None),
+    };
+
+    Ok((percent_decode(raw_path)?, query))
+}
+
+/// This function strips the `http://` or `https://` scheme of an absolute-form target
+fn strip_scheme(target: &str) -> Option<&str> {
+    let (scheme, rest) = target.split_once("://")?;
+    if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// This function determines the host a request is sent to. The authority of an absolute-form
+/// target wins over the `Host` header, as RFC 7230 demands.
+///
+/// # Returns
+///
+/// Returns the lowercase host without port and trailing dot, or `None` if the request names no
+/// host
+///
+/// # Errors
+///
+/// Returns `ParseError::InvalidHost` if the `Host` header is repeated or a host is invalid
+fn parse_host(target: &str, headers: &HeaderMap) -> Result<Option<String>, ParseError> {
+    let values = headers.get_all("Host");
+    if values.len() > 1 {
+        return Err(ParseError::InvalidHost);
+    }
+    let header = match values.first() {
+        Some(value) => normalize_host(value)?,
+        None => None,
+    };
+
+    let authority = strip_scheme(target).map(|rest| match rest.find(['/', '?']) {
+        Some(index) => &rest[..index],
+        None => rest,
+    });
+    match authority {
+        Some(authority) => normalize_host(authority),
+        None => Ok(header),
+    }
+}
+
+/// This function strips the port of an authority and brings the host into a comparable form
+///
+/// # Returns
+///
+/// Returns the lowercase host without port and trailing dot, or `None` if the authority is empty
+///
+/// # Errors
+///
+/// Returns `ParseError::InvalidHost` if the authority has user information, an invalid port or a
+/// host with characters that are not allowed in host names or ip addresses
+fn normalize_host(authority: &str) -> Result<Option<String>, ParseError> {
+    if authority.is_empty() {
+        return Ok(None);
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']').ok_or(ParseError::InvalidHost)?;
+            if ip.is_empty()
+                || !ip
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
+            {
+                return Err(ParseError::InvalidHost);
+            }
+            let port = match port {
+                "" => None,
+                port => Some(port.strip_prefix(':').ok_or(ParseError::InvalidHost)?),
+            };
+            (&authority[..ip.len() + 2], port)
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    // The port may be empty according to RFC 3986
+    if port.is_some_and(|port| !port.bytes().all(|b| b.is_ascii_digit())) {
+        return Err(ParseError::InvalidHost);
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let is_ip_literal = host.starts_with('[');
+    if host.is_empty() || (!is_ip_literal && !host.bytes().all(is_host_char)) {
+        return Err(ParseError::InvalidHost);
+    }
+    Ok(Some(host.to_ascii_lowercase()))
+}
+
+/// This function checks if a byte may appear in a host name or IPv4 address
+fn is_host_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+/// This function decodes every `%XX` sequence of the given string
+///
+/// # Errors
+///
+/// Returns `ParseError::InvalidPercentEncoding` if a `%` is not followed by two hex digits or the
+/// decoded bytes are not valid UTF-8
+pub fn percent_decode(encoded: &str) -> Result<String, ParseError> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(ParseError::InvalidPercentEncoding)?;
+            decoded.push(hex);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| ParseError::InvalidPercentEncoding)
+}
+
+/// This function splits a header line into the field name and the trimmed field value. It is also
+/// used for the trailer section of chunked bodies, which follows the same rules.
+pub fn parse_header_line(
+    line: &[u8],
+    limits: &RequestLimits,
+) -> Result<(String, String), ParseError> {
+    if line.len() > limits.max_header_line_size {
+        return Err(ParseError::HeaderTooLarge);
+    }
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(ParseError::ObsoleteLineFolding);
+    }
+
+    let colon = line
+        .iter()
+        .position(|b| *b == b':')
+        .ok_or(ParseError::MalformedHeader)?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+    if name.is_empty() || !name.iter().copied().all(is_token_char) {
+        return Err(ParseError::InvalidHeaderName);
+    }
+    if value
+        .iter()
+        .any(|b| (b.is_ascii_control() && *b != b'\t') || *b == 0x7f)
+    {
+        return Err(ParseError::InvalidHeaderValue);
+    }
+
+    let value = String::from_utf8_lossy(value);
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+
+    Ok((String::from_utf8_lossy(name).to_string(), value.to_string()))
+}
+
+/// This function checks that every `Content-Length` field holds the same, valid number
+fn validate_content_length(headers: &HeaderMap) -> Result<(), ParseError> {
+    let mut length: Option<&str> = None;
+    for value in headers.get_list("Content-Length") {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        if value.parse::<usize>().is_err() || length.is_some_and(|length| length != value) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        length = Some(value);
+    }
+    Ok(())
+}
+
+/// This function checks that a `Transfer-Encoding` can be decoded. As required by RFC 9112,
+/// `chunked` has to be the final coding, and a request carrying both a `Transfer-Encoding` and a
+/// `Content-Length`, or a `Transfer-Encoding` from an HTTP/1.0 client, is rejected, since
+/// intermediaries could disagree on where its body ends.
+fn validate_transfer_encoding(headers: &HeaderMap, version: &str) -> Result<(), ParseError> {
+    if !headers.contains("Transfer-Encoding") {
+        return Ok(());
+    }
+    if headers.contains("Content-Length") || version == "HTTP/1.0" {
+        return Err(ParseError::InvalidTransferEncoding);
+    }
+
+    let codings = headers.get_list("Transfer-Encoding");
+    match codings.split_last() {
+        Some((last, others)) if last.eq_ignore_ascii_case("chunked") => {
+            if others.is_empty() {
+                Ok(())
+            } else if others.iter().any(|c| c.eq_ignore_ascii_case("chunked")) {
+                Err(ParseError::InvalidTransferEncoding)
+            } else {
+                Err(ParseError::UnsupportedTransferCoding)
+            }
+        }
+        _ => Err(ParseError::InvalidTransferEncoding),
+    }
+}
+
+/// This function checks if the byte is a `tchar` as defined in RFC 7230
+pub fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(head: &[u8]) -> Result<HttpObject, ParseError> {
+        parse_head(head, &RequestLimits::default())
+    }
+
+    #[test]
+    fn test_parses_simple_request() {
+        let request = parse(
+            b"GET /docs/a%20b.html?lang=en HTTP/1.1\r\nHost: localhost\r\nAccept: text/html\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method(), &Method::Get);
+        assert_eq!(request.target(), "/docs/a%20b.html?lang=en");
+        assert_eq!(request.request_path(), "/docs/a b.html");
+        assert_eq!(request.query(), Some("lang=en"));
+        assert_eq!(request.version(), "HTTP/1.1");
+        assert_eq!(request.headers().get("host"), Some("localhost"));
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn test_keeps_repeated_headers() {
+        let request = parse(b"GET / HTTP/1.1\r\nCookie: a=1\r\ncookie: b=2\r\n\r\n").unwrap();
+
+        assert_eq!(request.headers().len(), 2);
+        assert_eq!(request.headers().get_list("cookie"), vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn test_absolute_form_target() {
+        let request = parse(b"GET http://example.test/a?b HTTP/1.1\r\n\r\n").unwrap();
+
+        assert_eq!(request.request_path(), "/a");
+        assert_eq!(request.query(), Some("b"));
+    }
+
+    #[test]
+    fn test_ignores_leading_empty_lines() {
+        assert!(parse(b"\r\nGET / HTTP/1.1\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_malformed_request_lines() {
+        assert_eq!(
+            parse(b"NOT HTTP DATA\r\n\r\n").unwrap_err(),
+            ParseError::InvalidVersion
+        );
+        assert_eq!(
+            parse(b"GET  / HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::MalformedRequestLine
+        );
+        assert_eq!(
+            parse(b"GET /\r\n\r\n").unwrap_err(),
+            ParseError::MalformedRequestLine
+        );
+        assert_eq!(
+            parse(b"G(T / HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidMethod
+        );
+        assert_eq!(
+            parse(b"GET index.html HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidTarget
+        );
+        assert_eq!(
+            parse(b"GET /\x01 HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidCharacter
+        );
+    }
+
+    #[test]
+    fn test_rejects_obsolete_line_folding() {
+        let error = parse(b"GET / HTTP/1.1\r\nX-Long: first\r\n second\r\n\r\n").unwrap_err();
+
+        assert_eq!(error, ParseError::ObsoleteLineFolding);
+        assert_eq!(error.status_code(), 400);
+    }
+
+    #[test]
+    fn test_rejects_invalid_headers() {
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n").unwrap_err(),
+            ParseError::MalformedHeader
+        );
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n").unwrap_err(),
+            ParseError::InvalidHeaderName
+        );
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nX-Test: a\x00b\r\n\r\n").unwrap_err(),
+            ParseError::InvalidHeaderValue
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_percent_encoding() {
+        assert_eq!(
+            parse(b"GET /%zz HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidPercentEncoding
+        );
+        assert_eq!(
+            parse(b"GET /%ff HTTP/1.1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidPercentEncoding
+        );
+    }
+
+    #[test]
+    fn test_rejects_conflicting_content_length() {
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n")
+                .unwrap_err(),
+            ParseError::InvalidContentLength
+        );
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n").unwrap_err(),
+            ParseError::InvalidContentLength
+        );
+    }
+
+    #[test]
+    fn test_validates_transfer_encoding() {
+        let request = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n").unwrap();
+        assert!(request.is_chunked());
+
+        for (head, error) in [
+            (
+                &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n"[..],
+                ParseError::InvalidTransferEncoding,
+            ),
+            (
+                b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n",
+                ParseError::InvalidTransferEncoding,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+                ParseError::InvalidTransferEncoding,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n",
+                ParseError::InvalidTransferEncoding,
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+                ParseError::UnsupportedTransferCoding,
+            ),
+        ] {
+            assert_eq!(parse(head).unwrap_err(), error);
+        }
+        assert_eq!(ParseError::UnsupportedTransferCoding.status_code(), 501);
+    }
+
+    #[test]
+    fn test_parses_the_host() {
+        let host = |head: &[u8]| parse(head).map(|request| request.host().map(String::from));
+
+        assert_eq!(
+            host(b"GET / HTTP/1.1\r\nHost: WWW.Example.Test.:8080\r\n\r\n"),
+            Ok(Some("www.example.test".to_string()))
+        );
+        assert_eq!(
+            host(b"GET / HTTP/1.1\r\nHost: [::1]:80\r\n\r\n"),
+            Ok(Some("[::1]".to_string()))
+        );
+        assert_eq!(
+            host(b"GET http://a.test/ HTTP/1.1\r\nHost: b.test\r\n\r\n"),
+            Ok(Some("a.test".to_string()))
+        );
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost:\r\n\r\n"), Ok(None));
+        assert_eq!(host(b"GET / HTTP/1.0\r\n\r\n"), Ok(None));
+
+        for head in [
+            &b"GET / HTTP/1.1\r\nHost: a.test\r\nHost: b.test\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nHost: user@a.test\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a.test:http\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a/b\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: [::1\r\n\r\n",
+        ] {
+            assert_eq!(parse(head).unwrap_err(), ParseError::InvalidHost);
+        }
+    }
+
+    #[test]
+    fn test_oversized_requests_map_to_their_status() {
+        let long_target = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_target_length)
+        );
+        let error = parse(long_target.as_bytes()).unwrap_err();
+        assert_eq!(error.status_code(), 414);
+
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_header_line_size)
+        );
+        let error = parse(long_header.as_bytes()).unwrap_err();
+        assert_eq!(error.status_code(), 431);
+
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(RequestLimits::default().max_header_count + 1)
+        );
+        let error = parse(many_headers.as_bytes()).unwrap_err();
+        assert_eq!(error.status_code(), 431);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::encoding::{self, Encoding};
+use super::file_path;
+
+/// This are the codings precompressed siblings are looked up and generated for, together with the
+/// extension appended to the original file name, e.g. `index.html.br`.
+const SIBLINGS: [(Encoding, &str); 2] = [(Encoding::Brotli, "br"), (Encoding::Gzip, "gz")];
+
+/// This function returns the path of the sibling holding a precompressed copy of a file
+///
+/// # Parameters
+///
+/// - `path`: This is the path of the original file
+/// - `extension`: This is the extension of the sibling, without the dot
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+    PathBuf::from(sibling)
+}
+
+/// This function checks that a sibling exists and is at least as new as the original, so an
+/// outdated copy is never served after the original was edited. Symlinks are not followed, so
+/// the time of a link is compared and not the one of the file it points to.
+fn is_fresh(sibling: &Path, original: &Path) -> bool {
+    let modified =
+        |path: &Path| fs::symlink_metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(sibling), modified(original)) {
+        (Ok(sibling), Ok(original)) => sibling >= original,
+        _ => false,
+    }
+}
+
+/// This function returns the codings a file has an up-to-date precompressed sibling for. Like
+/// the file itself, a sibling is only used if it lies inside the root once every symlink is
+/// resolved.
+///
+/// # Parameters
+///
+/// - `path`: This is the path of the original file
+/// - `root`: This is the root the file is served from
+///
+/// # Returns
+///
+/// Returns a `Vec<Encoding>` ordered by the preference of the server
+pub fn available(path: &Path, root: &Path) -> Vec<Encoding> {
+    SIBLINGS
+        .iter()
+        .filter(|(_, extension)| {
+            let sibling = sibling_path(path, extension);
+            sibling.is_file()
+                && file_path::is_inside_root(&sibling, root)
+                && is_fresh(&sibling, path)
+        })
+        .map(|(encoding, _)| *encoding)
+        .collect()
+}
+
+/// This function returns the precompressed sibling of a file for a coding
+///
+/// # Parameters
+///
+/// - `path`: This is the path of the original file
+/// - `encoding`: This is the coding of the sibling
+///
+/// # Returns
+///
+/// Returns an `Option<PathBuf>` that is `None` if no siblings exist for the coding
+pub fn sibling(path: &Path, encoding: Encoding) -> Option<PathBuf> {
+    SIBLINGS
+        .iter()
+        .find(|(sibling_encoding, _)| *sibling_encoding == encoding)
+        .map(|(_, extension)| sibling_path(path, extension))
+}
+
+/// This function checks if a file is the precompressed sibling of another file. Those are only
+/// served on their own when they are requested by their full name, never for a wildcard like
+/// `index.*` that matches the original as well.
+///
+/// # Parameters
+///
+/// - `path`: This is the path of the file that is checked
+///
+/// # Returns
+///
+/// Returns a `bool` that is true if the file has a sibling extension and its original exists
+pub fn is_sibling(path: &Path) -> bool {
+    let is_sibling_extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| SIBLINGS.iter().any(|(_, e)| *e == extension));
+
+    is_sibling_extension && path.with_extension("").is_file()
+}
+
+/// This function generates the precompressed siblings for every compressible file below a
+/// directory. Siblings that are already up to date are left alone. Symlinks are skipped, so
+/// nothing outside the directory is read or written.
+///
+/// # Parameters
+///
+/// - `root`: This is the directory, usually the document root
+///
+/// # Returns
+///
+/// Returns the amount of siblings written
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the directory cannot be read or a sibling cannot be written
+pub fn generate(root: &Path) -> io::Result<usize> {
+    let mut written = 0;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_symlink() {
+            continue;
+        }
+        if file_type.is_dir() {
+            written += generate(&path)?;
+            continue;
+        }
+
+        let mime_type = mime_guess::from_path(&path).first_or_octet_stream();
+        if is_sibling(&path) || !encoding::is_compressible(mime_type.as_ref()) {
+            continue;
+        }
+
+        let mut content = None;
+        for (encoding, extension) in SIBLINGS {
+            let sibling = sibling_path(&path, extension);
+            let is_link = fs::symlink_metadata(&sibling)
+                .is_ok_and(|metadata| metadata.file_type().is_symlink());
+            if is_link || is_fresh(&sibling, &path) {
+                continue;
+            }
+            if content.is_none() {
+                content = Some(fs::read(&path)?);
+            }
+            let data = content.as_deref().unwrap_or_default();
+            fs::write(&sibling, encoding.encode(data))?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_generates_and_finds_siblings() {
+        let root =
+            std::env::temp_dir().join(format!("anes-http-precompressed-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "<b>index</b>".repeat(10)).unwrap();
+        fs::write(root.join("docs/style.css"), "b { color: red; }").unwrap();
+        fs::write(root.join("favicon.ico"), [0, 0, 1, 0]).unwrap();
+
+        assert!(available(&root.join("index.html"), &root).is_empty());
+        assert_eq!(generate(&root).unwrap(), 4);
+        assert_eq!(
+            generate(&root).unwrap(),
+            0,
+            "fresh siblings were regenerated"
+        );
+
+        assert_eq!(
+            available(&root.join("docs/style.css"), &root),
+            vec![Encoding::Brotli, Encoding::Gzip]
+        );
+        assert!(available(&root.join("favicon.ico"), &root).is_empty());
+        assert!(is_sibling(&root.join("index.html.gz")));
+        assert!(!is_sibling(&root.join("index.html")));
+
+        let gzip = sibling(&root.join("index.html"), Encoding::Gzip).unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(gzip).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "<b>index</b>".repeat(10));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
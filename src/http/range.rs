@@ -0,0 +1,462 @@
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::http_codes;
+use super::response::{Body, Response, Status};
+use crate::log;
+
+/// This is the amount of ranges a single request may ask for. Requests for more ranges are served
+/// in full, as lots of tiny ranges cost more than sending the whole body.
+const MAX_RANGES: usize = 64;
+
+/// This is an inclusive range of bytes, as in `bytes=0-499`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+/// This is the outcome of parsing a `Range` header field against a body of a known length
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// The field is invalid or asks for too many ranges, so the whole body is sent
+    Full,
+    /// The satisfiable ranges, ordered and with overlapping ranges merged
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlaps the body, which is answered with 416
+    NotSatisfiable,
+}
+
+/// This function parses the value of a `Range` header field. Only the `bytes` unit is supported,
+/// with first-last ranges (`0-499`), open ranges (`500-`) and suffix ranges (`-500`).
+///
+/// # Parameters
+///
+/// - `value`: This is the value of the `Range` header field
+/// - `length`: This is the length of the whole body
+///
+/// # Returns
+///
+/// Returns the `RangeRequest` deciding how the body is sent
+pub fn parse(value: &str, length: u64) -> RangeRequest {
+    let specs = match value.trim().split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return RangeRequest::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        count += 1;
+        if count > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+        let (first, last) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return RangeRequest::Full,
+        };
+        let parse_bound = |bound: &str| {
+            (!bound.is_empty() && bound.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| bound.parse::<u64>().ok())
+                .flatten()
+        };
+
+        let range = match (parse_bound(first), parse_bound(last)) {
+            (Some(first), Some(last)) if first <= last => (first < length).then(|| ByteRange {
+                first,
+                last: last.min(length - 1),
+            }),
+            (Some(first), None) if last.is_empty() => (first < length).then(|| ByteRange {
+                first,
+                last: length - 1,
+            }),
+            (None, Some(suffix)) if first.is_empty() => {
+                (suffix > 0 && length > 0).then(|| ByteRange {
+                    first: length.saturating_sub(suffix),
+                    last: length - 1,
+                })
+            }
+            _ => return RangeRequest::Full,
+        };
+        ranges.extend(range);
+    }
+
+    if count == 0 {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::NotSatisfiable;
+    }
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+/// This function orders the ranges and merges those that overlap or are adjacent
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.first);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(previous) if range.first <= previous.last.saturating_add(1) => {
+                previous.last = previous.last.max(range.last);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// This function answers a `Range` request by cutting the ranges out of a 200 OK response. A
+/// single range becomes a 206 Partial Content response with a `Content-Range` header, several
+/// ranges become a `multipart/byteranges` body. File bodies are not read into memory, only the
+/// requested ranges are copied from the file while sending.
+///
+/// # Parameters
+///
+/// - `response`: This is the complete 200 OK response
+/// - `value`: This is the value of the `Range` header field
+/// - `error_pages`: This is the directory holding the error pages
+///
+/// # Returns
+///
+/// Returns the 206 Partial Content or 416 Range Not Satisfiable response. The response is
+/// returned unchanged if it is no 200 OK, its length is unknown or the field is ignored.
+pub fn respond(response: Response, value: &str, error_pages: &Path) -> Response {
+    let (status, mut headers, body) = response.into_parts();
+    let length = match (&body, status) {
+        (Body::Bytes(_) | Body::File(..), Status::Ok) => body.length().unwrap_or_default(),
+        _ => return Response::from_parts(status, headers, body),
+    };
+
+    let ranges = match parse(value, length) {
+        RangeRequest::Full => return Response::from_parts(status, headers, body),
+        RangeRequest::NotSatisfiable => {
+            return http_codes::error(Status::RangeNotSatisfiable, error_pages)
+                .header("Content-Range", &format!("bytes */{}", length))
+        }
+        RangeRequest::Partial(ranges) => ranges,
+    };
+    let content_range =
+        |range: &ByteRange| format!("bytes {}-{}/{}", range.first, range.last, length);
+
+    if let [range] = ranges.as_slice() {
+        headers.insert("Content-Range", &content_range(range));
+        let partial = match cut(body, range) {
+            Ok(partial) => partial,
+            Err(e) => {
+                log::error!(
+                    "Failed to seek to the range {}: {}",
+                    content_range(range),
+                    e
+                );
+                return http_codes::error(Status::InternalServerError, error_pages);
+            }
+        };
+        return Response::from_parts(Status::PartialContent, headers, partial);
+    }
+
+    let boundary = boundary();
+    let content_type = headers
+        .get("Content-Type")
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let mut parts = VecDeque::new();
+    for range in &ranges {
+        let part_head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            content_type,
+            content_range(range)
+        );
+        parts.push_back(Part::Bytes(Cursor::new(part_head.into_bytes())));
+        parts.push_back(Part::Range(*range));
+    }
+    let end = format!("\r\n--{}--\r\n", boundary);
+    parts.push_back(Part::Bytes(Cursor::new(end.into_bytes())));
+
+    headers.insert(
+        "Content-Type",
+        &format!("multipart/byteranges; boundary={}", boundary),
+    );
+    let multipart = match MultipartReader::new(body, parts) {
+        Ok(multipart) => multipart,
+        Err(e) => {
+            log::error!("Failed to prepare the ranges: {}", e);
+            return http_codes::error(Status::InternalServerError, error_pages);
+        }
+    };
+    let multipart_length = multipart.length();
+    Response::from_parts(
+        Status::PartialContent,
+        headers,
+        Body::Stream(Box::new(multipart), Some(multipart_length)),
+    )
+}
+
+/// This function cuts a single range out of a body
+///
+/// # Errors
+///
+/// Returns an `io::Error` if seeking in a file body failed
+fn cut(body: Body, range: &ByteRange) -> io::Result<Body> {
+    let length = range.last - range.first + 1;
+    match body {
+        Body::Bytes(data) => Ok(Body::Bytes(
+            data[range.first as usize..=range.last as usize].to_vec(),
+        )),
+        Body::File(mut file, _) => {
+            file.seek(SeekFrom::Current(range.first as i64))?;
+            Ok(Body::File(file, length))
+        }
+        body => Ok(body),
+    }
+}
+
+/// This is a piece of a `multipart/byteranges` body
+enum Part {
+    /// The boundary and the headers of a part, or the final boundary
+    Bytes(Cursor<Vec<u8>>),
+    /// A range of the original body
+    Range(ByteRange),
+}
+
+/// This reads a `multipart/byteranges` body part by part, copying the ranges from the original
+/// body only when they are sent
+struct MultipartReader {
+    body: Body,
+    /// The position of a file body when the response was built, which the ranges are relative to
+    base: u64,
+    parts: VecDeque<Part>,
+}
+
+impl MultipartReader {
+    /// This Initializes a new `MultipartReader`
+    ///
+    /// # Parameters
+    ///
+    /// - `body`: This is the original body, either in memory or a file
+    /// - `parts`: This are the parts of the multipart body, in order
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the position of a file body cannot be read
+    fn new(mut body: Body, parts: VecDeque<Part>) -> io::Result<MultipartReader> {
+        let base = match &mut body {
+            Body::File(file, _) => file.stream_position()?,
+            _ => 0,
+        };
+        Ok(MultipartReader { body, base, parts })
+    }
+
+    /// This returns the length of the whole multipart body
+    fn length(&self) -> u64 {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.get_ref().len() as u64,
+                Part::Range(range) => range.last - range.first + 1,
+            })
+            .sum()
+    }
+
+    /// This reads from the original body at an offset
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.body {
+            Body::Bytes(data) => {
+                let start = offset as usize;
+                buf.copy_from_slice(&data[start..start + buf.len()]);
+                Ok(buf.len())
+            }
+            Body::File(file, _) => {
+                file.seek(SeekFrom::Start(self.base + offset))?;
+                file.read(buf)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Read for MultipartReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !buf.is_empty() {
+            match self.parts.front_mut() {
+                None => return Ok(0),
+                Some(Part::Bytes(bytes)) => match bytes.read(buf)? {
+                    0 => {
+                        self.parts.pop_front();
+                    }
+                    read => return Ok(read),
+                },
+                Some(Part::Range(range)) => {
+                    let range = *range;
+                    let wanted = buf.len().min((range.last - range.first + 1) as usize);
+                    let read = self.read_at(range.first, &mut buf[..wanted])?;
+                    if read == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "the body got shorter while it was sent",
+                        ));
+                    }
+
+                    if let Some(Part::Range(range)) = self.parts.front_mut() {
+                        range.first += read as u64;
+                        if range.first > range.last {
+                            self.parts.pop_front();
+                        }
+                    }
+                    return Ok(read);
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// This function creates the boundary separating the parts of a `multipart/byteranges` body. It
+/// only has to be unlikely to appear in the body.
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    format!("anes-http-{:x}-{:x}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(first: u64, last: u64) -> ByteRange {
+        ByteRange { first, last }
+    }
+
+    #[test]
+    fn test_parses_single_ranges() {
+        assert_eq!(
+            parse("bytes=0-499", 1000),
+            RangeRequest::Partial(vec![range(0, 499)])
+        );
+        assert_eq!(
+            parse("bytes=500-", 1000),
+            RangeRequest::Partial(vec![range(500, 999)])
+        );
+        assert_eq!(
+            parse("bytes=-200", 1000),
+            RangeRequest::Partial(vec![range(800, 999)])
+        );
+        assert_eq!(
+            parse("bytes=-2000", 1000),
+            RangeRequest::Partial(vec![range(0, 999)])
+        );
+        assert_eq!(
+            parse("Bytes=900-5000", 1000),
+            RangeRequest::Partial(vec![range(900, 999)])
+        );
+    }
+
+    #[test]
+    fn test_coalesces_multiple_ranges() {
+        assert_eq!(
+            parse("bytes=500-599, 0-99, 50-149, 150-199", 1000),
+            RangeRequest::Partial(vec![range(0, 199), range(500, 599)])
+        );
+    }
+
+    #[test]
+    fn test_invalid_fields_are_ignored() {
+        for value in [
+            "items=0-1",
+            "bytes=",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=1",
+            "bytes=+1-2",
+        ] {
+            assert_eq!(
+                parse(value, 1000),
+                RangeRequest::Full,
+                "{} was not ignored",
+                value
+            );
+        }
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(&many, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn test_unsatisfiable_ranges() {
+        assert_eq!(parse("bytes=1000-", 1000), RangeRequest::NotSatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), RangeRequest::NotSatisfiable);
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::NotSatisfiable);
+        assert_eq!(
+            parse("bytes=2000-3000, 10-19", 1000),
+            RangeRequest::Partial(vec![range(10, 19)])
+        );
+    }
+
+    #[test]
+    fn test_cuts_ranges_out_of_files() {
+        let full = || {
+            let file = std::fs::File::open("Cargo.toml").unwrap();
+            let length = file.metadata().unwrap().len();
+            Response::new(Status::Ok).body(Body::File(file, length))
+        };
+        let serialize = |response: Response| {
+            let mut output = Vec::new();
+            response.write_to(&mut output, true).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let single = serialize(respond(full(), "bytes=1-7", Path::new("public")));
+        assert!(single.ends_with("Content-Length: 7\r\n\r\npackage"));
+
+        let multiple = serialize(respond(full(), "bytes=0-0,2-4", Path::new("public")));
+        let content_length: usize = multiple
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = multiple.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(body.len(), content_length);
+        assert!(body.contains("Content-Range: bytes 0-0/"));
+        assert!(body.contains("\r\n\r\n[\r\n"));
+        assert!(body.contains("\r\n\r\nack\r\n"));
+    }
+
+    #[test]
+    fn test_builds_partial_responses() {
+        let full = || {
+            Response::new(Status::Ok)
+                .header("Content-Type", "text/plain")
+                .body(Body::Bytes(b"0123456789".to_vec()))
+        };
+
+        let single = respond(full(), "bytes=2-4", Path::new("public"));
+        assert_eq!(single.status(), Status::PartialContent);
+        assert_eq!(single.headers().get("Content-Range"), Some("bytes 2-4/10"));
+
+        let multiple = respond(full(), "bytes=0-1,8-", Path::new("public"));
+        let content_type = multiple.headers().get("Content-Type").unwrap().to_string();
+        let boundary = content_type.split("boundary=").nth(1).unwrap().to_string();
+        let mut output = Vec::new();
+        multiple.write_to(&mut output, true).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(content_type.starts_with("multipart/byteranges"));
+        assert!(output.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+        assert!(output.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"));
+        assert!(output.ends_with(&format!("\r\n--{}--\r\n", boundary)));
+
+        let unsatisfiable = respond(full(), "bytes=10-", Path::new("public"));
+        assert_eq!(unsatisfiable.status(), Status::RangeNotSatisfiable);
+        assert_eq!(
+            unsatisfiable.headers().get("Content-Range"),
+            Some("bytes */10")
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use std::io::{self, ErrorKind, Read};
+use std::time::Instant;
+
+use super::limits::RequestLimits;
+use super::parser::{self, ParseError};
+
+/// This is the amount of bytes requested from the stream with every read.
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// This describes why reading a request from the client failed
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client closed the connection, or let it sit idle for too long, without starting a
+    /// request
+    Closed,
+    /// The stream failed, e.g. because the client reset the connection
+    Io(io::Error),
+    /// The request exceeds a limit or ended halfway through
+    Parse(ParseError),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+/// This reads requests from a stream incrementally. Everything received past the end of a request
+/// stays in the buffer, so a request split across several TCP segments, or several requests in a
+/// single segment, are handled correctly.
+pub struct RequestReader<R: Read> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> RequestReader<R> {
+    /// This Initializes a new `RequestReader` with an empty buffer
+    ///
+    /// # Parameters
+    ///
+    /// - `inner`: This is the stream the requests are read from
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `RequestReader`
+    pub fn new(inner: R) -> RequestReader<R> {
+        RequestReader {
+            inner,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// This returns the stream, so the responses can be written to it
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// This returns the stream, e.g. to wait on it
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// This checks if anything was received past the end of the previous request
+    pub fn is_buffered(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// This reads from the stream until the empty line that terminates the header section. Once
+    /// the first byte of it arrived, the whole section has to arrive within the `header_timeout`
+    /// limit, so a client trickling it in cannot hold the connection forever.
+    ///
+    /// # Parameters
+    ///
+    /// - `limits`: This are the limits the header section has to stay within
+    ///
+    /// # Returns
+    ///
+    /// Returns the header section, including the terminating empty line
+    ///
+    /// # Errors
+    ///
+    /// Returns `ReadError::Closed` if the client closed the connection before sending anything or
+    /// the read timeout of the stream ran out, and a `ReadError::Parse` if the header section is
+    /// too large, ends prematurely or did not arrive in time
+    pub fn read_head(&mut self, limits: &RequestLimits) -> Result<Vec<u8>, ReadError> {
+        let mut searched = 0;
+        let mut deadline =
+            (!self.buffer.is_empty()).then(|| Instant::now() + limits.header_timeout);
+        loop {
+            if let Some(end) = find_head_end(&self.buffer, searched) {
+                if end > limits.max_header_size {
+                    return Err(oversized_head_error(&self.buffer, limits));
+                }
+                return Ok(self.buffer.drain(..end).collect());
+            }
+            if self.buffer.len() > limits.max_header_size {
+                return Err(oversized_head_error(&self.buffer, limits));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(ReadError::Parse(ParseError::HeaderTimeout));
+            }
+
+            // The terminator could start in the last three bytes that were already searched
+            searched = self.buffer.len().saturating_sub(3);
+            let received = match self.fill() {
+                Ok(received) => received,
+                // The idle timeout of a keep-alive connection ran out
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Err(ReadError::Closed)
+                }
+                Err(e) => return Err(ReadError::Io(e)),
+            };
+            if received == 0 {
+                return if self.buffer.iter().all(|b| *b == b'\r' || *b == b'\n') {
+                    Err(ReadError::Closed)
+                } else {
+                    Err(ReadError::Parse(ParseError::Incomplete))
+                };
+            }
+            deadline.get_or_insert_with(|| Instant::now() + limits.header_timeout);
+        }
+    }
+
+    /// This blocks until the next request starts arriving, without reading the whole of it
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` once data arrived, and `false` if the client closed the connection, the
+    /// read timeout of the stream ran out or reading failed
+    pub fn wait_for_request(&mut self) -> bool {
+        self.is_buffered() || matches!(self.fill(), Ok(received) if received > 0)
+    }
+
+    /// This reads a body of a known length from the stream
+    ///
+    /// # Parameters
+    ///
+    /// - `length`: This is the length of the body, as announced by the `Content-Length`
+    /// - `limits`: This are the limits the body has to stay within
+    ///
+    /// # Returns
+    ///
+    /// Returns the body
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ReadError::Parse` if the body is larger than allowed or ends prematurely
+    pub fn read_body(
+        &mut self,
+        length: usize,
+        limits: &RequestLimits,
+    ) -> Result<Vec<u8>, ReadError> {
+        if length > limits.max_body_size {
+            return Err(ReadError::Parse(ParseError::BodyTooLarge));
+        }
+        while self.buffer.len() < length {
+            if self.fill()? == 0 {
+                return Err(ReadError::Parse(ParseError::Incomplete));
+            }
+        }
+        Ok(self.buffer.drain(..length).collect())
+    }
+
+    /// This reads a body sent with the chunked transfer coding of RFC 9112, section 7.1. Chunk
+    /// extensions are ignored, and the trailer section is checked against the header limits and
+    /// then discarded, since no trailer field changes how the request is answered.
+    ///
+    /// # Parameters
+    ///
+    /// - `limits`: This are the limits the decoded body and the trailer section have to stay
+    ///   within
+    ///
+    /// # Returns
+    ///
+    /// Returns the decoded body
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ReadError::Parse` if a chunk is malformed, the body is larger than allowed or
+    /// ends prematurely
+    pub fn read_chunked_body(&mut self, limits: &RequestLimits) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line(limits.max_header_line_size, ParseError::InvalidChunk)?;
+            let size = line
+                .split(|b| *b == b';')
+                .next()
+                .and_then(|size| std::str::from_utf8(size).ok())
+                .map(|size| size.trim_matches(|c| c == ' ' || c == '\t'))
+                .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|size| usize::from_str_radix(size, 16).ok())
+                .ok_or(ReadError::Parse(ParseError::InvalidChunk))?;
+            if size == 0 {
+                break;
+            }
+            if size > limits.max_body_size - body.len() {
+                return Err(ReadError::Parse(ParseError::BodyTooLarge));
+            }
+
+            // The data of every chunk is followed by a CRLF
+            while self.buffer.len() < size + 2 {
+                if self.fill()? == 0 {
+                    return Err(ReadError::Parse(ParseError::Incomplete));
+                }
+            }
+            if &self.buffer[size..size + 2] != b"\r\n" {
+                return Err(ReadError::Parse(ParseError::InvalidChunk));
+            }
+            body.extend(self.buffer.drain(..size));
+            self.buffer.drain(..2);
+        }
+
+        let mut trailer_size = 0;
+        let mut trailer_count = 0;
+        loop {
+            let line = self.read_line(limits.max_header_line_size, ParseError::HeaderTooLarge)?;
+            if line.is_empty() {
+                return Ok(body);
+            }
+            trailer_size += line.len() + 2;
+            trailer_count += 1;
+            if trailer_size > limits.max_header_size {
+                return Err(ReadError::Parse(ParseError::HeaderTooLarge));
+            }
+            if trailer_count > limits.max_header_count {
+                return Err(ReadError::Parse(ParseError::TooManyHeaders));
+            }
+            parser::parse_header_line(&line, limits).map_err(ReadError::Parse)?;
+        }
+    }
+
+    /// This reads a single line terminated by CRLF from the stream
+    ///
+    /// # Parameters
+    ///
+    /// - `max_length`: This is the length the line may have, without the CRLF
+    /// - `too_long`: This is the error returned if the line is longer
+    ///
+    /// # Returns
+    ///
+    /// Returns the line without the CRLF
+    fn read_line(&mut self, max_length: usize, too_long: ParseError) -> Result<Vec<u8>, ReadError> {
+        let mut searched = 0;
+        loop {
+            if let Some(position) = self.buffer[searched..].iter().position(|b| *b == b'\n') {
+                let end = searched + position;
+                if end == 0 || self.buffer[end - 1] != b'\r' {
+                    return Err(ReadError::Parse(ParseError::InvalidChunk));
+                }
+                if end - 1 > max_length {
+                    return Err(ReadError::Parse(too_long));
+                }
+                let line = self.buffer.drain(..=end).take(end - 1).collect();
+                return Ok(line);
+            }
+            if self.buffer.len() > max_length + 2 {
+                return Err(ReadError::Parse(too_long));
+            }
+
+            searched = self.buffer.len();
+            if self.fill()? == 0 {
+                return Err(ReadError::Parse(ParseError::Incomplete));
+            }
+        }
+    }
+
+    /// This reads the next chunk from the stream into the buffer
+    ///
+    /// # Returns
+    ///
+    /// Returns the amount of bytes read, which is `0` once the client closed the stream
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(received) => {
+                    self.buffer.extend_from_slice(&chunk[..received]);
+                    return Ok(received);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // TLS clients often close the connection without announcing it first
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// This function returns the index right behind the empty line that terminates the header section
+///
+/// # Parameters
+///
+/// - `buffer`: This is the data received so far
+/// - `from`: This is the index the search starts at, so already searched data is skipped
+///
+/// # Returns
+///
+/// Returns an `Option<usize>` that is `None` if the header section is not terminated yet
+fn find_head_end(buffer: &[u8], from: usize) -> Option<usize> {
+    buffer[from..]
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| from + position + 4)
+}
+
+/// This function decides which error an oversized header section is answered with. If not even
+/// the request line ended within the limit, the request target is what is too long.
+fn oversized_head_error(buffer: &[u8], limits: &RequestLimits) -> ReadError {
+    let request_line_start = buffer
+        .iter()
+        .position(|b| *b != b'\r' && *b != b'\n')
+        .unwrap_or(0);
+    let request_line_end = buffer[request_line_start..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|position| request_line_start + position);
+
+    match request_line_end {
+        Some(end) if end <= limits.max_header_size => ReadError::Parse(ParseError::HeaderTooLarge),
+        _ => ReadError::Parse(ParseError::UriTooLong),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This is a stream that hands out its data in tiny pieces, like a slow client would
+    struct TrickleStream {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl Read for TrickleStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = (self.position + 3).min(self.data.len());
+            let piece = &self.data[self.position..end];
+            buf[..piece.len()].copy_from_slice(piece);
+            self.position = end;
+            Ok(piece.len())
+        }
+    }
+
+    #[test]
+    fn test_reads_head_split_across_reads() {
+        let stream = TrickleStream {
+            data: b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec(),
+            position: 0,
+        };
+        let mut reader = RequestReader::new(stream);
+
+        let head = reader.read_head(&RequestLimits::default()).unwrap();
+
+        assert_eq!(head, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    }
+
+    #[test]
+    fn test_slow_head_runs_into_the_deadline() {
+        /// This is a stream that hands out a header line every few milliseconds, forever
+        struct EndlessHead;
+
+        impl Read for EndlessHead {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                let line = b"X-Slow: 1\r\n";
+                buf[..line.len()].copy_from_slice(line);
+                Ok(line.len())
+            }
+        }
+
+        let limits = RequestLimits {
+            header_timeout: std::time::Duration::from_millis(50),
+            ..RequestLimits::default()
+        };
+        let error = RequestReader::new(EndlessHead)
+            .read_head(&limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::HeaderTimeout)));
+    }
+
+    #[test]
+    fn test_waits_for_the_next_request() {
+        let mut reader = RequestReader::new(&b"GET / HTTP/1.1\r\n\r\n"[..]);
+        assert!(reader.wait_for_request());
+        assert!(reader.wait_for_request());
+        reader.read_head(&RequestLimits::default()).unwrap();
+        assert!(!reader.wait_for_request());
+    }
+
+    #[test]
+    fn test_keeps_data_after_the_head() {
+        let data: &[u8] =
+            b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        let mut reader = RequestReader::new(data);
+        let limits = RequestLimits::default();
+
+        reader.read_head(&limits).unwrap();
+        assert_eq!(reader.read_body(5, &limits).unwrap(), b"hello");
+        assert_eq!(
+            reader.read_head(&limits).unwrap(),
+            b"GET / HTTP/1.1\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_reads_heads_larger_than_a_chunk() {
+        let cookie = "a".repeat(READ_CHUNK_SIZE * 2);
+        let request = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie);
+        let mut reader = RequestReader::new(request.as_bytes());
+
+        let head = reader.read_head(&RequestLimits::default()).unwrap();
+
+        assert_eq!(head.len(), request.len());
+    }
+
+    #[test]
+    fn test_oversized_head_is_rejected() {
+        let limits = RequestLimits {
+            max_header_size: 64,
+            ..RequestLimits::default()
+        };
+
+        let request = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(64));
+        let error = RequestReader::new(request.as_bytes())
+            .read_head(&limits)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::Parse(ParseError::HeaderTooLarge)
+        ));
+
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64));
+        let error = RequestReader::new(request.as_bytes())
+            .read_head(&limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::UriTooLong)));
+    }
+
+    #[test]
+    fn test_closed_and_incomplete_requests() {
+        let limits = RequestLimits::default();
+
+        let error = RequestReader::new(&b""[..]).read_head(&limits).unwrap_err();
+        assert!(matches!(error, ReadError::Closed));
+
+        let error = RequestReader::new(&b"GET / HTTP/1.1\r\nHost: a"[..])
+            .read_head(&limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::Incomplete)));
+    }
+
+    #[test]
+    fn test_reads_chunked_body() {
+        let stream = TrickleStream {
+            data: b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nGET / HTTP/1.1\r\n\r\n"
+                .to_vec(),
+            position: 0,
+        };
+        let mut reader = RequestReader::new(stream);
+        let limits = RequestLimits::default();
+
+        assert_eq!(reader.read_chunked_body(&limits).unwrap(), b"hello, world");
+        assert_eq!(
+            reader.read_head(&limits).unwrap(),
+            b"GET / HTTP/1.1\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_malformed_chunks_are_rejected() {
+        let limits = RequestLimits::default();
+
+        for data in [
+            &b"x\r\nhello\r\n0\r\n\r\n"[..],
+            b"5\r\nhelloX\r\n0\r\n\r\n",
+            b"5\nhello\r\n0\r\n\r\n",
+            b"-5\r\nhello\r\n0\r\n\r\n",
+            b"fffffffffffffffffffff\r\n",
+        ] {
+            let error = RequestReader::new(data)
+                .read_chunked_body(&limits)
+                .unwrap_err();
+            assert!(
+                matches!(error, ReadError::Parse(ParseError::InvalidChunk)),
+                "{:?} was accepted",
+                String::from_utf8_lossy(data)
+            );
+        }
+
+        let error = RequestReader::new(&b"5\r\nhel"[..])
+            .read_chunked_body(&limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::Incomplete)));
+
+        let error = RequestReader::new(&b"0\r\nBad Trailer: x\r\n\r\n"[..])
+            .read_chunked_body(&limits)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::Parse(ParseError::InvalidHeaderName)
+        ));
+    }
+
+    #[test]
+    fn test_oversized_chunked_body_is_rejected() {
+        let limits = RequestLimits {
+            max_body_size: 8,
+            ..RequestLimits::default()
+        };
+
+        let error = RequestReader::new(&b"5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"[..])
+            .read_chunked_body(&limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn test_oversized_body_is_rejected() {
+        let limits = RequestLimits {
+            max_body_size: 4,
+            ..RequestLimits::default()
+        };
+
+        let error = RequestReader::new(&b"hello"[..])
+            .read_body(5, &limits)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::Parse(ParseError::BodyTooLarge)));
+    }
+}
@@ -0,0 +1,656 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use super::chunked::ChunkedWriter;
+use super::headers::HeaderMap;
+
+/// This is the value of the `Server` header sent with every response.
+const SERVER_NAME: &str = "Anes HTTP";
+
+/// This enum holds the status of a response. Every status code registered with IANA is known by
+/// name, together with its reason phrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Status {
+    Continue,
+    SwitchingProtocols,
+    Processing,
+    EarlyHints,
+    Ok,
+    Created,
+    Accepted,
+    NonAuthoritativeInformation,
+    NoContent,
+    ResetContent,
+    PartialContent,
+    MultiStatus,
+    AlreadyReported,
+    ImUsed,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    UseProxy,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    PaymentRequired,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    ProxyAuthenticationRequired,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    ImATeapot,
+    MisdirectedRequest,
+    UnprocessableContent,
+    Locked,
+    FailedDependency,
+    TooEarly,
+    UpgradeRequired,
+    PreconditionRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    UnavailableForLegalReasons,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    VariantAlsoNegotiates,
+    InsufficientStorage,
+    LoopDetected,
+    NotExtended,
+    NetworkAuthenticationRequired,
+}
+
+impl Status {
+    /// This looks up the status belonging to a numeric status code
+    ///
+    /// # Parameters
+    ///
+    /// - `code`: This is the three digit status code
+    ///
+    /// # Returns
+    ///
+    /// Returns an `Option<Status>` that is `None` if the code is not registered
+    pub fn from_code(code: u16) -> Option<Status> {
+        let status = match code {
+            100 => Status::Continue,
+            101 => Status::SwitchingProtocols,
+            102 => Status::Processing,
+            103 => Status::EarlyHints,
+            200 => Status::Ok,
+            201 => Status::Created,
+            202 => Status::Accepted,
+            203 => Status::NonAuthoritativeInformation,
+            204 => Status::NoContent,
+            205 => Status::ResetContent,
+            206 => Status::PartialContent,
+            207 => Status::MultiStatus,
+            208 => Status::AlreadyReported,
+            226 => Status::ImUsed,
+            300 => Status::MultipleChoices,
+            301 => Status::MovedPermanently,
+            302 => Status::Found,
+            303 => Status::SeeOther,
+            304 => Status::NotModified,
+            305 => Status::UseProxy,
+            307 => Status::TemporaryRedirect,
+            308 => Status::PermanentRedirect,
+            400 => Status::BadRequest,
+            401 => Status::Unauthorized,
+            402 => Status::PaymentRequired,
+            403 => Status::Forbidden,
+            404 => Status::NotFound,
+            405 => Status::MethodNotAllowed,
+            406 => Status::NotAcceptable,
+            407 => Status::ProxyAuthenticationRequired,
+            408 => Status::RequestTimeout,
+            409 => Status::Conflict,
+            410 => Status::Gone,
+            411 => Status::LengthRequired,
+            412 => Status::PreconditionFailed,
+            413 => Status::PayloadTooLarge,
+            414 => Status::UriTooLong,
+            415 => Status::UnsupportedMediaType,
+            416 => Status::RangeNotSatisfiable,
+            417 => Status::ExpectationFailed,
+            418 => Status::ImATeapot,
+            421 => Status::MisdirectedRequest,
+            422 => Status::UnprocessableContent,
+            423 => Status::Locked,
+            424 => Status::FailedDependency,
+            425 => Status::TooEarly,
+            426 => Status::UpgradeRequired,
+            428 => Status::PreconditionRequired,
+            429 => Status::TooManyRequests,
+            431 => Status::RequestHeaderFieldsTooLarge,
+            451 => Status::UnavailableForLegalReasons,
+            500 => Status::InternalServerError,
+            501 => Status::NotImplemented,
+            502 => Status::BadGateway,
+            503 => Status::ServiceUnavailable,
+            504 => Status::GatewayTimeout,
+            505 => Status::HttpVersionNotSupported,
+            506 => Status::VariantAlsoNegotiates,
+            507 => Status::InsufficientStorage,
+            508 => Status::LoopDetected,
+            510 => Status::NotExtended,
+            511 => Status::NetworkAuthenticationRequired,
+            _ => return None,
+        };
+        Some(status)
+    }
+
+    /// This returns the numeric status code, e.g. `404` for `Status::NotFound`
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Continue => 100,
+            Status::SwitchingProtocols => 101,
+            Status::Processing => 102,
+            Status::EarlyHints => 103,
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NonAuthoritativeInformation => 203,
+            Status::NoContent => 204,
+            Status::ResetContent => 205,
+            Status::PartialContent => 206,
+            Status::MultiStatus => 207,
+            Status::AlreadyReported => 208,
+            Status::ImUsed => 226,
+            Status::MultipleChoices => 300,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::SeeOther => 303,
+            Status::NotModified => 304,
+            Status::UseProxy => 305,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::PaymentRequired => 402,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::NotAcceptable => 406,
+            Status::ProxyAuthenticationRequired => 407,
+            Status::RequestTimeout => 408,
+            Status::Conflict => 409,
+            Status::Gone => 410,
+            Status::LengthRequired => 411,
+            Status::PreconditionFailed => 412,
+            Status::PayloadTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::UnsupportedMediaType => 415,
+            Status::RangeNotSatisfiable => 416,
+            Status::ExpectationFailed => 417,
+            Status::ImATeapot => 418,
+            Status::MisdirectedRequest => 421,
+            Status::UnprocessableContent => 422,
+            Status::Locked => 423,
+            Status::FailedDependency => 424,
+            Status::TooEarly => 425,
+            Status::UpgradeRequired => 426,
+            Status::PreconditionRequired => 428,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::UnavailableForLegalReasons => 451,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::BadGateway => 502,
+            Status::ServiceUnavailable => 503,
+            Status::GatewayTimeout => 504,
+            Status::HttpVersionNotSupported => 505,
+            Status::VariantAlsoNegotiates => 506,
+            Status::InsufficientStorage => 507,
+            Status::LoopDetected => 508,
+            Status::NotExtended => 510,
+            Status::NetworkAuthenticationRequired => 511,
+        }
+    }
+
+    /// This returns the reason phrase sent in the status line, e.g. `Not Found`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Continue => "Continue",
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::Processing => "Processing",
+            Status::EarlyHints => "Early Hints",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NonAuthoritativeInformation => "Non-Authoritative Information",
+            Status::NoContent => "No Content",
+            Status::ResetContent => "Reset Content",
+            Status::PartialContent => "Partial Content",
+            Status::MultiStatus => "Multi-Status",
+            Status::AlreadyReported => "Already Reported",
+            Status::ImUsed => "IM Used",
+            Status::MultipleChoices => "Multiple Choices",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::UseProxy => "Use Proxy",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::PaymentRequired => "Payment Required",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::NotAcceptable => "Not Acceptable",
+            Status::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Status::RequestTimeout => "Request Timeout",
+            Status::Conflict => "Conflict",
+            Status::Gone => "Gone",
+            Status::LengthRequired => "Length Required",
+            Status::PreconditionFailed => "Precondition Failed",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::ExpectationFailed => "Expectation Failed",
+            Status::ImATeapot => "I'm a teapot",
+            Status::MisdirectedRequest => "Misdirected Request",
+            Status::UnprocessableContent => "Unprocessable Content",
+            Status::Locked => "Locked",
+            Status::FailedDependency => "Failed Dependency",
+            Status::TooEarly => "Too Early",
+            Status::UpgradeRequired => "Upgrade Required",
+            Status::PreconditionRequired => "Precondition Required",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::BadGateway => "Bad Gateway",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::GatewayTimeout => "Gateway Timeout",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+            Status::VariantAlsoNegotiates => "Variant Also Negotiates",
+            Status::InsufficientStorage => "Insufficient Storage",
+            Status::LoopDetected => "Loop Detected",
+            Status::NotExtended => "Not Extended",
+            Status::NetworkAuthenticationRequired => "Network Authentication Required",
+        }
+    }
+
+    /// This checks if a response with this status may carry a body. Informational responses,
+    /// `204 No Content` and `304 Not Modified` never do.
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.code(), 100..=199 | 204 | 304)
+    }
+}
+
+/// This enum holds the body of a response
+pub enum Body {
+    /// There is no body at all
+    Empty,
+    /// The body is already in memory
+    Bytes(Vec<u8>),
+    /// The body is the given amount of bytes of a file, starting at its current position. They
+    /// are copied to the client while sending, which uses `sendfile`/`splice` on Linux.
+    File(File, u64),
+    /// The body is produced while sending. Without a known length, it is sent with the chunked
+    /// transfer coding or by closing the connection.
+    Stream(Box<dyn Read + Send>, Option<u64>),
+}
+
+impl Body {
+    /// This returns the length of the body, if it is known before sending
+    ///
+    /// # Returns
+    ///
+    /// Returns an `Option<u64>` that is `None` for streams of an unknown length
+    pub fn length(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File(_, length) => Some(*length),
+            Body::Stream(_, length) => *length,
+        }
+    }
+
+    /// This writes the body to the client and returns the amount of bytes written
+    fn write_to<W: Write>(self, writer: &mut W) -> io::Result<u64> {
+        match self {
+            Body::Empty => Ok(0),
+            Body::Bytes(bytes) => writer.write_all(&bytes).map(|()| bytes.len() as u64),
+            Body::File(file, length) => io::copy(&mut file.take(length), writer),
+            Body::Stream(mut stream, _) => io::copy(&mut stream, writer),
+        }
+    }
+}
+
+/// This is a response that is built up step by step and written to the client in one go
+pub struct Response {
+    status: Status,
+    headers: HeaderMap,
+    body: Body,
+    trailers: HeaderMap,
+    /// The size of the file a compressed body is made of
+    original_length: Option<u64>,
+}
+
+impl Response {
+    /// This Initializes a new `Response` with the `Server` header and an empty body
+    ///
+    /// # Parameters
+    ///
+    /// - `status`: This is the status of the response
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Response`
+    pub fn new(status: Status) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.append("Server", SERVER_NAME);
+        Response {
+            status,
+            headers,
+            body: Body::Empty,
+            trailers: HeaderMap::new(),
+            original_length: None,
+        }
+    }
+
+    /// This appends a header field, keeping all fields that already have the same name
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: This is the name of the header field
+    /// - `value`: This is the value of the header field
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Response` with the header field added
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// This sets the body of the response. The `Content-Length` is derived from it while sending.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Response` with the new body
+    pub fn body(mut self, body: Body) -> Response {
+        self.body = body;
+        self
+    }
+
+    /// This marks the body as the compressed form of a file, so the compression can be measured
+    ///
+    /// # Parameters
+    ///
+    /// - `length`: This is the size of the file
+    ///
+    /// # Returns
+    ///
+    /// Returns the marked `Response`
+    pub fn compressed_from(mut self, length: u64) -> Response {
+        self.original_length = Some(length);
+        self
+    }
+
+    /// This adds a trailer field, which is sent after a chunked body, and announces its name in
+    /// the `Trailer` header. Trailers of a response that is not chunked are dropped.
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: This is the name of the trailer field
+    /// - `value`: This is the value of the trailer field
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Response` with the trailer field added
+    pub fn trailer(mut self, name: &str, value: &str) -> Response {
+        self.headers.append("Trailer", name);
+        self.trailers.append(name, value);
+        self
+    }
+
+    /// This replaces every header field with the given name by a single new one
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.insert(name, value);
+    }
+
+    /// This returns the status of the response
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// This returns the header fields set so far
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// This returns the size of the file the body was compressed from, if it is compressed
+    pub fn original_length(&self) -> Option<u64> {
+        self.original_length
+    }
+
+    /// This splits the response into its status, header fields and body, so it can be rebuilt
+    /// with `Response::from_parts()`
+    pub fn into_parts(self) -> (Status, HeaderMap, Body) {
+        (self.status, self.headers, self.body)
+    }
+
+    /// This builds a response from its parts, without adding any header fields
+    pub fn from_parts(status: Status, headers: HeaderMap, body: Body) -> Response {
+        Response {
+            status,
+            headers,
+            body,
+            trailers: HeaderMap::new(),
+            original_length: None,
+        }
+    }
+
+    /// This checks if the client can tell where the response ends without the connection being
+    /// closed
+    pub fn is_delimited(&self) -> bool {
+        !self.status.allows_body()
+            || self.headers.contains("Content-Length")
+            || self.body.length().is_some()
+            || self.is_chunked()
+    }
+
+    /// This frames the body with the chunked transfer coding if its length is unknown. Only
+    /// HTTP/1.1 clients understand it, HTTP/1.0 clients read until the connection is closed.
+    pub fn chunk_unknown_length(&mut self) {
+        if !self.is_delimited() {
+            self.headers.insert("Transfer-Encoding", "chunked");
+        }
+    }
+
+    /// This checks if the body is sent with the chunked transfer coding
+    pub fn is_chunked(&self) -> bool {
+        self.headers
+            .get_list("Transfer-Encoding")
+            .last()
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// This serializes the response and writes it to the client. The status line and every header
+    /// line end with CRLF, and a `Content-Length` is added if the length of the body is known and
+    /// the status allows a body. A body with the `Transfer-Encoding: chunked` header is written in
+    /// chunks instead, followed by the trailer fields.
+    ///
+    /// # Parameters
+    ///
+    /// - `writer`: This is the connection the response is written to
+    /// - `send_body`: This is false if only the head is sent, e.g. for `HEAD` requests. The
+    ///   headers stay the same as if the body was sent.
+    ///
+    /// # Returns
+    ///
+    /// Returns the amount of body bytes sent, without the framing of chunks
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if writing to the client failed
+    pub fn write_to<W: Write>(self, mut writer: W, send_body: bool) -> io::Result<u64> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status.code(),
+            self.status.reason()
+        );
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let chunked = self.is_chunked();
+        if self.status.allows_body() && !chunked && !self.headers.contains("Content-Length") {
+            if let Some(length) = self.body.length() {
+                head.push_str(&format!("Content-Length: {}\r\n", length));
+            }
+        }
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())?;
+        let mut sent = 0;
+        if send_body && self.status.allows_body() {
+            if chunked {
+                let mut chunked_writer = ChunkedWriter::new(&mut writer);
+                sent = self.body.write_to(&mut chunked_writer)?;
+                chunked_writer.finish(&self.trailers)?;
+            } else {
+                sent = self.body.write_to(&mut writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This serializes a response into a string, the way the client receives it
+    fn serialize(response: Response, send_body: bool) -> String {
+        let mut output = Vec::new();
+        response.write_to(&mut output, send_body).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_codes_and_reasons_round_trip() {
+        for code in 100..600 {
+            if let Some(status) = Status::from_code(code) {
+                assert_eq!(status.code(), code);
+                assert!(!status.reason().is_empty());
+            }
+        }
+        assert_eq!(Status::from_code(404), Some(Status::NotFound));
+        assert_eq!(Status::NotFound.reason(), "Not Found");
+        assert_eq!(Status::from_code(299), None);
+    }
+
+    #[test]
+    fn test_serializes_with_crlf_framing() {
+        let response = Response::new(Status::Ok)
+            .header("Content-Type", "text/plain")
+            .body(Body::Bytes(b"hello".to_vec()));
+
+        assert_eq!(
+            serialize(response, true),
+            "HTTP/1.1 200 OK\r\nServer: Anes HTTP\r\nContent-Type: text/plain\r\n\
+             Content-Length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn test_head_keeps_the_headers_but_not_the_body() {
+        let response = Response::new(Status::NotFound).body(Body::Bytes(b"missing".to_vec()));
+
+        let output = serialize(response, false);
+
+        assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(output.ends_with("Content-Length: 7\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_no_content_has_no_length() {
+        let response = Response::new(Status::NoContent).header("Allow", "GET");
+
+        assert_eq!(
+            serialize(response, true),
+            "HTTP/1.1 204 No Content\r\nServer: Anes HTTP\r\nAllow: GET\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_set_header_replaces_fields() {
+        let mut response = Response::new(Status::Ok).header("Connection", "keep-alive");
+        response.set_header("connection", "close");
+
+        assert_eq!(response.headers().get_list("Connection"), vec!["close"]);
+        assert!(response.is_delimited());
+    }
+
+    #[test]
+    fn test_streams_are_not_delimited() {
+        let stream: Box<dyn Read + Send> = Box::new(&b"streamed"[..]);
+        let response = Response::new(Status::Ok).body(Body::Stream(stream, None));
+
+        assert!(!response.is_delimited());
+        assert!(serialize(response, true).ends_with("\r\n\r\nstreamed"));
+    }
+
+    #[test]
+    fn test_unknown_lengths_are_chunked() {
+        let stream: Box<dyn Read + Send> = Box::new(&b"streamed"[..]);
+        let mut response = Response::new(Status::Ok).body(Body::Stream(stream, None));
+        response.chunk_unknown_length();
+
+        let output = serialize(response, true);
+        assert!(response_is_chunked(&output));
+        assert!(output.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+
+        let mut response = Response::new(Status::Ok).body(Body::Bytes(b"sized".to_vec()));
+        response.chunk_unknown_length();
+        assert!(!response_is_chunked(&serialize(response, true)));
+    }
+
+    #[test]
+    fn test_trailers_follow_chunked_bodies() {
+        let stream: Box<dyn Read + Send> = Box::new(&b"streamed"[..]);
+        let mut response = Response::new(Status::Ok).body(Body::Stream(stream, None));
+        response.chunk_unknown_length();
+        let response = response.trailer("Server-Timing", "app;dur=2");
+
+        let output = serialize(response, true);
+        assert!(output.contains("\r\nTrailer: Server-Timing\r\n"));
+        assert!(output.ends_with("8\r\nstreamed\r\n0\r\nServer-Timing: app;dur=2\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_file_bodies_stop_at_their_length() {
+        let mut file = File::open("Cargo.toml").unwrap();
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(1)).unwrap();
+        let response = Response::new(Status::Ok).body(Body::File(file, 7));
+
+        assert!(serialize(response, true).ends_with("Content-Length: 7\r\n\r\npackage"));
+    }
+
+    fn response_is_chunked(output: &str) -> bool {
+        output.contains("Transfer-Encoding: chunked\r\n") && !output.contains("Content-Length")
+    }
+}
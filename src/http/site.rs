@@ -0,0 +1,425 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::parser;
+use super::response::Response;
+
+/// This is the document root used when nothing else is configured, relative to the working
+/// directory.
+pub const DEFAULT_DOCUMENT_ROOT: &str = "public";
+/// This are the index file names used when nothing else is configured. `index.*` picks the index
+/// file whose mime type the client prefers.
+pub const DEFAULT_INDEX_FILES: [&str; 1] = ["index.*"];
+
+/// This describes why the directories of a site cannot be used
+#[derive(Debug)]
+pub enum SiteError {
+    /// A directory does not exist or cannot be accessed
+    Missing {
+        setting: &'static str,
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// A path exists, but is not a directory
+    NotADirectory {
+        setting: &'static str,
+        path: PathBuf,
+    },
+    /// An index file name is empty or contains a path separator
+    InvalidIndexFile(String),
+    /// The prefix of a location is not an absolute path without dot segments
+    InvalidLocation(String),
+    /// A configured header field has an invalid name or value
+    InvalidHeader(String),
+    /// A server name is no host name, ip address or wildcard like `*.example.test`
+    InvalidServerName(String),
+}
+
+impl fmt::Display for SiteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiteError::Missing {
+                setting,
+                path,
+                error,
+            } => write!(f, "the {} {:?} cannot be used: {}", setting, path, error),
+            SiteError::NotADirectory { setting, path } => {
+                write!(f, "the {} {:?} is not a directory", setting, path)
+            }
+            SiteError::InvalidIndexFile(name) => {
+                write!(f, "the index file name {:?} is invalid", name)
+            }
+            SiteError::InvalidLocation(prefix) => write!(
+                f,
+                "the location {:?} is not an absolute path without dot segments",
+                prefix
+            ),
+            SiteError::InvalidHeader(name) => {
+                write!(f, "the header field {:?} is invalid", name)
+            }
+            SiteError::InvalidServerName(name) => {
+                write!(f, "the server name {:?} is invalid", name)
+            }
+        }
+    }
+}
+
+/// This struct holds where the files of the site are served from. Every path is absolute, so the
+/// site keeps working regardless of the working directory of the process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteConfig {
+    /// The directory request paths are mapped onto
+    pub document_root: PathBuf,
+    /// The directory holding the error pages, named after their status code, e.g. `404.html`
+    pub error_pages: PathBuf,
+    /// The names tried, in order, when a directory is requested. They may contain the wildcards
+    /// `*` and `?`.
+    pub index_files: Vec<String>,
+    /// Whether bodies may be compressed, either while sending or from precompressed siblings
+    pub compression: bool,
+    /// The header fields added to every file that is served, e.g. security headers
+    pub headers: Vec<(String, String)>,
+    /// The `Cache-Control` of every file that is served, none is sent if it is `None`
+    pub cache_control: Option<String>,
+    /// The locations with settings of their own, the one with the longest matching prefix wins
+    pub locations: Vec<Location>,
+}
+
+/// This struct holds the settings of every request path below a prefix. Every setting that is
+/// `None` is taken from the site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// The path prefix, e.g. `/assets/`, which only matches whole segments
+    pub prefix: String,
+    /// The directory the rest of the path after the prefix is looked up in
+    pub root: Option<PathBuf>,
+    /// The names tried, in order, when a directory is requested
+    pub index_files: Option<Vec<String>>,
+    /// Whether bodies may be compressed
+    pub compression: Option<bool>,
+    /// The header fields added on top of the ones of the site, replacing those of the same name
+    pub headers: Vec<(String, String)>,
+    /// The `Cache-Control` of every file that is served
+    pub cache_control: Option<String>,
+}
+
+/// This is where a request path leads to, after the location it falls into was picked
+pub struct Route<'a> {
+    /// The directory the file is looked up in
+    pub root: &'a Path,
+    /// The request path relative to the root, always starting with a `/`
+    pub path: String,
+    /// The names tried, in order, when a directory is requested
+    pub index_files: &'a [String],
+    /// Whether bodies may be compressed
+    pub compression: bool,
+    site: &'a SiteConfig,
+    location: Option<&'a Location>,
+}
+
+impl SiteConfig {
+    /// This Initializes a new `SiteConfig`, resolving its directories to absolute paths
+    ///
+    /// # Parameters
+    ///
+    /// - `document_root`: This is the directory the files are served from
+    /// - `error_pages`: This is the directory of the error pages, which is the document root if
+    ///   it is `None`
+    /// - `index_files`: This are the index file names, tried in order
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `SiteConfig`
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SiteError` if a directory does not exist or an index file name is invalid
+    pub fn new(
+        document_root: &Path,
+        error_pages: Option<&Path>,
+        index_files: Vec<String>,
+    ) -> Result<SiteConfig, SiteError> {
+        let document_root = resolve_directory("document root", document_root)?;
+        let error_pages = match error_pages {
+            Some(error_pages) => resolve_directory("error page directory", error_pages)?,
+            None => document_root.clone(),
+        };
+        check_index_files(&index_files)?;
+        let index_files = if index_files.is_empty() {
+            DEFAULT_INDEX_FILES.map(String::from).to_vec()
+        } else {
+            index_files
+        };
+
+        Ok(SiteConfig {
+            document_root,
+            error_pages,
+            index_files,
+            compression: true,
+            headers: Vec::new(),
+            cache_control: None,
+            locations: Vec::new(),
+        })
+    }
+
+    /// This picks the location a request path falls into and maps the path onto its root
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: This is the normalized request path
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Route` with the settings of the location, or those of the site if no location
+    /// matches
+    pub fn route(&self, path: &str) -> Route<'_> {
+        let location = self
+            .locations
+            .iter()
+            .filter(|location| location.matches(path))
+            .max_by_key(|location| location.prefix.len());
+
+        let (root, path) = match location {
+            Some(Location {
+                prefix,
+                root: Some(root),
+                ..
+            }) => {
+                let rest = &path[prefix.trim_end_matches('/').len()..];
+                let rest = if rest.starts_with('/') {
+                    rest.to_string()
+                } else {
+                    format!("/{}", rest)
+                };
+                (root.as_path(), rest)
+            }
+            _ => (self.document_root.as_path(), path.to_string()),
+        };
+
+        Route {
+            root,
+            path,
+            index_files: location
+                .and_then(|location| location.index_files.as_deref())
+                .unwrap_or(&self.index_files),
+            compression: location
+                .and_then(|location| location.compression)
+                .unwrap_or(self.compression),
+            site: self,
+            location,
+        }
+    }
+}
+
+impl Location {
+    /// This Initializes a new `Location` that takes every setting from the site
+    ///
+    /// # Parameters
+    ///
+    /// - `prefix`: This is the path prefix the location applies to
+    /// - `root`: This is the directory of the location, resolved to an absolute path
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Location`
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SiteError` if the prefix is not a normalized absolute path or the root does not
+    /// exist
+    pub fn new(prefix: &str, root: Option<&Path>) -> Result<Location, SiteError> {
+        let is_normalized = super::file_path::normalize(prefix).is_ok_and(|normalized| {
+            normalized == prefix || normalized.trim_end_matches('/') == prefix
+        });
+        if !is_normalized {
+            return Err(SiteError::InvalidLocation(prefix.to_string()));
+        }
+        let root = match root {
+            Some(root) => Some(resolve_directory("location root", root)?),
+            None => None,
+        };
+
+        Ok(Location {
+            prefix: prefix.to_string(),
+            root,
+            index_files: None,
+            compression: None,
+            headers: Vec::new(),
+            cache_control: None,
+        })
+    }
+
+    /// This sets the index file names of the location
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SiteError` if a name is empty or contains a path separator
+    pub fn set_index_files(&mut self, index_files: Vec<String>) -> Result<(), SiteError> {
+        check_index_files(&index_files)?;
+        self.index_files = Some(index_files);
+        Ok(())
+    }
+
+    /// This checks if a request path lies below the prefix. `/assets` matches `/assets` and
+    /// `/assets/app.js`, but not `/assets.zip`.
+    fn matches(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.prefix) {
+            Some(rest) => rest.is_empty() || self.prefix.ends_with('/') || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+impl Route<'_> {
+    /// This adds the configured header fields and the `Cache-Control` to a response for a file
+    ///
+    /// # Parameters
+    ///
+    /// - `response`: This is the response for the file, e.g. a 200 OK or 304 Not Modified
+    ///
+    /// # Returns
+    ///
+    /// Returns the `Response` with the header fields set
+    pub fn apply(&self, mut response: Response) -> Response {
+        let cache_control = self
+            .location
+            .and_then(|location| location.cache_control.as_deref())
+            .or(self.site.cache_control.as_deref());
+        if let Some(cache_control) = cache_control {
+            response.set_header("Cache-Control", cache_control);
+        }
+
+        let location_headers = self.location.map(|location| location.headers.as_slice());
+        for (name, value) in self
+            .site
+            .headers
+            .iter()
+            .chain(location_headers.unwrap_or_default())
+        {
+            response.set_header(name, value);
+        }
+        response
+    }
+}
+
+/// This function checks that a configured header field could be sent as it is
+///
+/// # Errors
+///
+/// Returns `SiteError::InvalidHeader` if the name is not a token or the value contains control
+/// characters
+pub fn check_header(name: &str, value: &str) -> Result<(), SiteError> {
+    let valid_name = !name.is_empty() && name.bytes().all(parser::is_token_char);
+    let valid_value = !value.bytes().any(|b| b.is_ascii_control() && b != b'\t');
+    if valid_name && valid_value {
+        Ok(())
+    } else {
+        Err(SiteError::InvalidHeader(name.to_string()))
+    }
+}
+
+/// This function checks that no index file name is empty or leads into another directory
+fn check_index_files(index_files: &[String]) -> Result<(), SiteError> {
+    match index_files
+        .iter()
+        .find(|name| name.is_empty() || name.contains(['/', '\\']) || *name == "..")
+    {
+        Some(name) => Err(SiteError::InvalidIndexFile(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// This function turns a directory into an absolute path with every symlink resolved
+fn resolve_directory(setting: &'static str, path: &Path) -> Result<PathBuf, SiteError> {
+    let resolved = path.canonicalize().map_err(|error| SiteError::Missing {
+        setting,
+        path: path.to_path_buf(),
+        error,
+    })?;
+    if !resolved.is_dir() {
+        return Err(SiteError::NotADirectory {
+            setting,
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolves_directories_to_absolute_paths() {
+        let site = SiteConfig::new(Path::new("public"), None, Vec::new()).unwrap();
+
+        assert!(site.document_root.is_absolute());
+        assert!(site.document_root.ends_with("public"));
+        assert_eq!(site.error_pages, site.document_root);
+        assert_eq!(site.index_files, vec!["index.*".to_string()]);
+    }
+
+    #[test]
+    fn test_rejects_missing_directories_and_bad_index_files() {
+        let error = SiteConfig::new(Path::new("does-not-exist"), None, Vec::new()).unwrap_err();
+        assert!(matches!(error, SiteError::Missing { .. }));
+        assert!(error.to_string().contains("document root"));
+
+        let error = SiteConfig::new(Path::new("Cargo.toml"), None, Vec::new()).unwrap_err();
+        assert!(matches!(error, SiteError::NotADirectory { .. }));
+
+        let error = SiteConfig::new(
+            Path::new("public"),
+            Some(Path::new("does-not-exist")),
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("error page directory"));
+
+        let error = SiteConfig::new(Path::new("public"), None, vec!["../index.html".to_string()])
+            .unwrap_err();
+        assert!(matches!(error, SiteError::InvalidIndexFile(_)));
+
+        assert!(Location::new("assets", None).is_err());
+        assert!(Location::new("/a/../b", None).is_err());
+        assert!(Location::new("/assets/", None).is_ok());
+        assert!(check_header("X Frame", "DENY").is_err());
+        assert!(check_header("X-Frame-Options", "DE\nNY").is_err());
+    }
+
+    #[test]
+    fn test_routes_to_the_longest_matching_location() {
+        let mut site = SiteConfig::new(Path::new("public"), None, Vec::new()).unwrap();
+        site.cache_control = Some("no-cache".to_string());
+        site.headers = vec![("X-Site".to_string(), "a".to_string())];
+        let mut assets = Location::new("/assets", Some(Path::new("src"))).unwrap();
+        assets.compression = Some(false);
+        assets.cache_control = Some("max-age=60".to_string());
+        assets.headers = vec![("X-Site".to_string(), "b".to_string())];
+        let mut docs = Location::new("/assets/docs/", None).unwrap();
+        docs.set_index_files(vec!["README.md".to_string()]).unwrap();
+        site.locations = vec![assets, docs];
+
+        let route = site.route("/index.html");
+        assert_eq!(route.root, site.document_root);
+        assert_eq!(route.path, "/index.html");
+        assert!(route.compression);
+
+        let route = site.route("/assets/http.rs");
+        assert!(route.root.ends_with("src"));
+        assert_eq!(route.path, "/http.rs");
+        assert!(!route.compression);
+        let response = route.apply(Response::new(super::super::response::Status::Ok));
+        assert_eq!(response.headers().get("Cache-Control"), Some("max-age=60"));
+        assert_eq!(response.headers().get_all("X-Site"), vec!["b"]);
+
+        assert_eq!(site.route("/assets").path, "/");
+        assert_eq!(site.route("/assets.zip").root, site.document_root);
+
+        let route = site.route("/assets/docs/");
+        assert_eq!(route.index_files, ["README.md"]);
+        assert_eq!(route.root, site.document_root);
+        assert_eq!(route.path, "/assets/docs/");
+    }
+}
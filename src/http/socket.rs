@@ -0,0 +1,49 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use rustls::{ServerConnection, StreamOwned};
+
+/// This is the connection requests are served over, which is either plain or encrypted
+pub trait Socket: Read + Write + Send {
+    /// This returns the TCP connection underneath, which is waited on while no request arrives
+    fn tcp(&self) -> &TcpStream;
+
+    /// This checks whether data was already received, but not read yet, which the TCP
+    /// connection does not report as readable anymore
+    fn has_pending(&mut self) -> bool;
+
+    /// This ends the connection once its last response was sent
+    fn close(&mut self);
+}
+
+impl Socket for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+
+    fn has_pending(&mut self) -> bool {
+        false
+    }
+
+    fn close(&mut self) {}
+}
+
+impl Socket for StreamOwned<ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    /// Records received along with the previous request may already hold the next one. A
+    /// connection that cannot process them is reported as pending, so reading reports the error.
+    fn has_pending(&mut self) -> bool {
+        self.conn
+            .process_new_packets()
+            .map_or(true, |state| state.plaintext_bytes_to_read() > 0)
+    }
+
+    /// This tells the client that the response was not cut off by an attacker
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,313 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::sign::CertifiedKey;
+
+use super::site::{SiteConfig, SiteError};
+
+/// This is a name a server answers to
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostPattern {
+    /// A host that has to match exactly, e.g. `example.test`
+    Exact(String),
+    /// A wildcard like `*.example.test` that matches every subdomain, but not `example.test`
+    /// itself. It is stored as the suffix, e.g. `.example.test`.
+    Wildcard(String),
+}
+
+impl HostPattern {
+    /// This parses a server name, which is a host name, an ip address or a host name whose first
+    /// label is the wildcard `*`
+    ///
+    /// # Parameters
+    ///
+    /// - `name`: This is the server name, as it was configured
+    ///
+    /// # Returns
+    ///
+    /// Returns the lowercase `HostPattern` without trailing dot
+    ///
+    /// # Errors
+    ///
+    /// Returns `SiteError::InvalidServerName` if the name is empty, contains a port or characters
+    /// that are not allowed in host names, or has a wildcard anywhere but the first label
+    pub fn parse(name: &str) -> Result<HostPattern, SiteError> {
+        let invalid = || SiteError::InvalidServerName(name.to_string());
+        let lowercase = name.to_ascii_lowercase();
+        let lowercase = lowercase.strip_suffix('.').unwrap_or(&lowercase);
+
+        let (pattern, host) = match lowercase.strip_prefix("*.") {
+            Some(suffix) => (HostPattern::Wildcard(format!(".{}", suffix)), suffix),
+            None => (HostPattern::Exact(lowercase.to_string()), lowercase),
+        };
+        let valid = match host.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')) {
+            Some(ip) => ip.parse::<std::net::Ipv6Addr>().is_ok(),
+            None => {
+                !host.is_empty()
+                    && host.split('.').all(|label| {
+                        !label.is_empty()
+                            && label
+                                .bytes()
+                                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+                    })
+            }
+        };
+        if !valid || (matches!(pattern, HostPattern::Wildcard(_)) && host.starts_with('[')) {
+            return Err(invalid());
+        }
+        Ok(pattern)
+    }
+
+    /// This function checks if a normalized host matches the pattern
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => name == host,
+            HostPattern::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+        }
+    }
+}
+
+/// This struct holds a site together with the names it is served under
+#[derive(Debug, Clone)]
+pub struct VirtualHost {
+    /// The names the site answers to, it only answers as the default server if there are none
+    pub names: Vec<HostPattern>,
+    /// The site the files are served from
+    pub site: SiteConfig,
+    /// The certificate presented to HTTPS clients that name the site through SNI
+    pub certificate: Option<Arc<CertifiedKey>>,
+}
+
+/// This struct holds every site of the server and picks the one a request is sent to by its host
+#[derive(Debug, Clone)]
+pub struct VirtualHosts {
+    hosts: Vec<VirtualHost>,
+    default: usize,
+}
+
+impl VirtualHosts {
+    /// This Initializes new `VirtualHosts`
+    ///
+    /// # Parameters
+    ///
+    /// - `hosts`: This are the sites with their names, which must not be empty
+    /// - `default`: This is the index of the site that answers requests whose host matches none
+    ///   of the names, or that name no host at all
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `VirtualHosts`
+    pub fn new(hosts: Vec<VirtualHost>, default: usize) -> VirtualHosts {
+        assert!(default < hosts.len(), "the default server does not exist");
+        VirtualHosts { hosts, default }
+    }
+
+    /// This function picks the site a request is sent to. A name that matches exactly wins over
+    /// wildcards, of which the longest one wins. Requests that match no name go to the default
+    /// server.
+    ///
+    /// # Parameters
+    ///
+    /// - `host`: This is the normalized host of the request, if it names one
+    ///
+    /// # Returns
+    ///
+    /// Returns the `SiteConfig` of the picked site
+    pub fn select(&self, host: Option<&str>) -> &SiteConfig {
+        &self.select_host(host).site
+    }
+
+    /// This function picks the certificate presented to a client, which is the one of the site
+    /// the client names through SNI, or else the one of the default server
+    ///
+    /// # Parameters
+    ///
+    /// - `server_name`: This is the lowercase server name the client sent, if it sent one
+    ///
+    /// # Returns
+    ///
+    /// Returns the certificate, or `None` if neither site has one
+    pub fn certificate(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        self.select_host(server_name)
+            .certificate
+            .as_ref()
+            .or(self.hosts[self.default].certificate.as_ref())
+            .cloned()
+    }
+
+    /// This function checks if any site has a certificate
+    pub fn has_certificate(&self) -> bool {
+        self.hosts.iter().any(|host| host.certificate.is_some())
+    }
+
+    /// This function picks the virtual host a request is sent to, as described for `select()`
+    fn select_host(&self, host: Option<&str>) -> &VirtualHost {
+        let Some(host) = host else {
+            return &self.hosts[self.default];
+        };
+        let patterns = || {
+            self.hosts
+                .iter()
+                .flat_map(|virtual_host| {
+                    virtual_host
+                        .names
+                        .iter()
+                        .map(move |name| (name, virtual_host))
+                })
+                .filter(|(name, _)| name.matches(host))
+        };
+
+        let exact = patterns().find(|(name, _)| matches!(name, HostPattern::Exact(_)));
+        let wildcard = || {
+            patterns()
+                .filter_map(|(name, virtual_host)| match name {
+                    HostPattern::Wildcard(suffix) => Some((suffix.len(), virtual_host)),
+                    HostPattern::Exact(_) => None,
+                })
+                // The first of equally long wildcards wins, like the first of equal names
+                .rev()
+                .max_by_key(|(length, _)| *length)
+        };
+        match exact {
+            Some((_, virtual_host)) => virtual_host,
+            None => match wildcard() {
+                Some((_, virtual_host)) => virtual_host,
+                None => &self.hosts[self.default],
+            },
+        }
+    }
+
+    /// This function returns the site answering requests that match no name
+    pub fn default_site(&self) -> &SiteConfig {
+        &self.hosts[self.default].site
+    }
+
+    /// This function returns every site, in the order they were configured
+    pub fn sites(&self) -> impl Iterator<Item = &SiteConfig> {
+        self.hosts.iter().map(|virtual_host| &virtual_host.site)
+    }
+
+    /// This function returns every directory files are served from, the document roots of the
+    /// sites and the roots of their locations, each once
+    pub fn roots(&self) -> Vec<&Path> {
+        let mut roots: Vec<&Path> = Vec::new();
+        for site in self.sites() {
+            let locations = site
+                .locations
+                .iter()
+                .filter_map(|location| location.root.as_deref());
+            for root in std::iter::once(site.document_root.as_path()).chain(locations) {
+                if !roots.contains(&root) {
+                    roots.push(root);
+                }
+            }
+        }
+        roots
+    }
+}
+
+impl From<SiteConfig> for VirtualHosts {
+    /// This serves a single site under every host
+    fn from(site: SiteConfig) -> VirtualHosts {
+        VirtualHosts::new(
+            vec![VirtualHost {
+                names: Vec::new(),
+                site,
+                certificate: None,
+            }],
+            0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Location;
+    use std::path::{Path, PathBuf};
+
+    fn site(root: &str) -> SiteConfig {
+        let mut site = SiteConfig::new(Path::new("public"), None, Vec::new()).unwrap();
+        site.document_root = PathBuf::from(root);
+        site
+    }
+
+    fn host(names: &[&str], root: &str) -> VirtualHost {
+        VirtualHost {
+            names: names
+                .iter()
+                .map(|name| HostPattern::parse(name).unwrap())
+                .collect(),
+            site: site(root),
+            certificate: None,
+        }
+    }
+
+    #[test]
+    fn test_parses_server_names() {
+        assert_eq!(
+            HostPattern::parse("Example.Test.").unwrap(),
+            HostPattern::Exact("example.test".to_string())
+        );
+        assert_eq!(
+            HostPattern::parse("*.example.test").unwrap(),
+            HostPattern::Wildcard(".example.test".to_string())
+        );
+        assert!(HostPattern::parse("[::1]").is_ok());
+        assert!(HostPattern::parse("127.0.0.1").is_ok());
+        for name in [
+            "",
+            "*",
+            "a.*.test",
+            "example.test:80",
+            "a..test",
+            "*.[::1]",
+            "a b",
+        ] {
+            assert!(HostPattern::parse(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_selects_the_site_of_the_host() {
+        let hosts = VirtualHosts::new(
+            vec![
+                host(&["a.test", "*.a.test"], "/a"),
+                host(&["*.test"], "/test"),
+                host(&["www.b.test"], "/b"),
+                host(&["*.b.test"], "/any-b"),
+            ],
+            2,
+        );
+        let root = |host| hosts.select(host).document_root.to_str().unwrap();
+
+        assert_eq!(root(Some("a.test")), "/a");
+        assert_eq!(root(Some("www.a.test")), "/a");
+        assert_eq!(root(Some("deep.www.a.test")), "/a");
+        assert_eq!(root(Some("c.test")), "/test");
+        assert_eq!(root(Some("www.b.test")), "/b");
+        assert_eq!(root(Some("cdn.b.test")), "/any-b");
+        assert_eq!(root(Some("test")), "/b");
+        assert_eq!(root(Some("other.example")), "/b");
+        assert_eq!(root(None), "/b");
+    }
+
+    #[test]
+    fn test_lists_every_root_once() {
+        let location = |prefix, root: Option<&str>| {
+            let mut location = Location::new(prefix, None).unwrap();
+            location.root = root.map(PathBuf::from);
+            location
+        };
+        let mut first = host(&["a.test"], "/a");
+        first.site.locations = vec![location("/assets", Some("/assets")), location("/api", None)];
+        let mut second = host(&["b.test"], "/b");
+        second.site.locations = vec![location("/assets", Some("/assets"))];
+        let hosts = VirtualHosts::new(vec![first, second, host(&["c.test"], "/a")], 0);
+
+        assert_eq!(
+            hosts.roots(),
+            vec![Path::new("/a"), Path::new("/assets"), Path::new("/b")]
+        );
+    }
+}
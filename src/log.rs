@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use tracing_subscriber::filter::{Directive, EnvFilter, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+/// This is the handle the filter of the running subscriber is replaced through on a reload.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// This struct holds the filter deciding which messages are printed. It is a comma separated list
+/// of directives in the syntax of `RUST_LOG`, each either a level like `debug` that applies to
+/// every message, or a level for the messages of a module like `anes_http::tcp=debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter(String);
+
+impl Default for LogFilter {
+    fn default() -> LogFilter {
+        LogFilter("info".to_string())
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<LogFilter, String> {
+        let mut directives = Vec::new();
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let directive = if directive.eq_ignore_ascii_case("warning") {
+                "warn"
+            } else {
+                directive
+            };
+            // A bare word would be taken for a module, so a misspelled level would hide everything
+            if !directive.contains('=') && directive.parse::<LevelFilter>().is_err() {
+                return Err(format!(
+                    "{:?} is no log level, use error, warn, info, debug, trace or off, or \
+                     module=level",
+                    directive
+                ));
+            }
+            if let Err(e) = directive.parse::<Directive>() {
+                return Err(format!("{:?} is no log filter: {}", directive, e));
+            }
+            directives.push(directive);
+        }
+        if directives.is_empty() {
+            return Err("the log filter is empty".to_string());
+        }
+        Ok(LogFilter(directives.join(",")))
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl LogFilter {
+    /// This function builds the filter the subscriber uses
+    fn env_filter(&self) -> EnvFilter {
+        EnvFilter::builder().parse_lossy(&self.0)
+    }
+}
+
+/// This enum holds the formats messages are printed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One line per message for humans, with the fields of the connection and request
+    #[default]
+    Compact,
+    /// One JSON object per message, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "{:?} is no log format, use compact or json",
+                format
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogFormat::Compact => "compact",
+            LogFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// This function starts printing messages to stderr for the whole process. Calling it again has
+/// no effect.
+///
+/// # Parameters
+///
+/// - `filter`: This decides which messages are printed
+/// - `format`: This is the format messages are printed in
+pub fn init(filter: &LogFilter, format: LogFormat) {
+    let (filter_layer, handle) = reload::Layer::new(filter.env_filter());
+    let output = match format {
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(io::stderr().is_terminal())
+            .with_writer(io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_list(true)
+            .with_current_span(false)
+            .with_writer(io::stderr)
+            .boxed(),
+    };
+    if tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(output)
+        .try_init()
+        .is_ok()
+    {
+        let _ = FILTER.set(handle);
+    }
+}
+
+/// This function replaces the filter deciding which messages are printed, for the whole process
+pub fn set_filter(filter: &LogFilter) {
+    if let Some(handle) = FILTER.get() {
+        if let Err(e) = handle.reload(filter.env_filter()) {
+            warning!("The log filter cannot be changed: {}", e);
+        }
+    }
+}
+
+pub(crate) use tracing::{debug, error, info, trace, warn as warning};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_levels_and_directives() {
+        assert_eq!("WARNING".parse::<LogFilter>().unwrap().to_string(), "warn");
+        assert_eq!(
+            "info, anes_http::tcp=debug"
+                .parse::<LogFilter>()
+                .unwrap()
+                .to_string(),
+            "info,anes_http::tcp=debug"
+        );
+        assert_eq!(LogFilter::default().to_string(), "info");
+        assert!("verbose".parse::<LogFilter>().is_err());
+        assert!("anes_http=loud".parse::<LogFilter>().is_err());
+        assert!(" , ".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn test_parses_formats() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("compact".parse::<LogFormat>(), Ok(LogFormat::Compact));
+        assert!("pretty".parse::<LogFormat>().is_err());
+        assert_eq!(LogFormat::Json.to_string(), "json");
+    }
+}
@@ -1,6 +1,15 @@
-use std::env;
+use std::process;
+use std::sync::Arc;
+use clap::Parser;
 use dotenv::dotenv;
 
+mod access_log;
+mod cli;
+mod config;
+mod log;
+mod metrics;
+mod reload;
+mod tls;
 mod utils;
 mod tcp;
 mod http;
@@ -8,9 +17,85 @@ mod http;
 fn main() {
     dotenv().ok();
 
+    let cli = cli::Cli::parse();
+    let check_config = cli.check_config;
+    let settings = match cli::Settings::resolve(cli.clone()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("anes-http: {}", e);
+            process::exit(1);
+        }
+    };
+    if check_config {
+        println!("The configuration is valid");
+        return;
+    }
+    log::init(&settings.log_level, settings.log_format);
+    let mut inherited = tcp::Inherited::from_env();
+
     utils::greet_user();
   
-    println!("The server will run on {}", env::var("TCP_ADDRESS").unwrap());
-    let listener = tcp::spawn_tcp_server("127.0.0.1:7878");
-    tcp::handle_incoming_connections(listener, &http::request_gate);
+    for site in settings.hosts.sites() {
+        log::info!("Serving files from {:?}", site.document_root);
+    }
+    if settings.precompress {
+        http::precompress(&settings.hosts);
+    }
+    let listeners = settings
+        .listeners
+        .iter()
+        .enumerate()
+        .map(|(index, listener)| {
+            log::info!("The server will run on {}", listener.address);
+            let tcp_listener = inherited
+                .take(&listener.address)
+                .unwrap_or_else(|| tcp::spawn_tcp_server(&listener.address, settings.bind_retries));
+            (tcp_listener, index)
+        })
+        .collect::<Vec<_>>();
+
+    let current = Arc::new(reload::Current::new(reload::Snapshot {
+        hosts: Arc::clone(&settings.hosts),
+        services: settings.listeners.iter().map(|listener| listener.service.clone()).collect(),
+        metrics_path: settings.metrics_path.clone(),
+        limits: settings.limits.clone(),
+    }));
+    let reloader = Arc::new(reload::Reloader::new(cli, &settings, Arc::clone(&current)));
+    access_log::set(settings.access_log);
+    if let Err(e) = access_log::reopen_on_signal() {
+        log::warning!("Reopening the access log on SIGUSR1 is not available: {}", e);
+    }
+    if let Err(e) = reload::reload_on_hangup(Arc::clone(&reloader)) {
+        log::warning!("Reloading on SIGHUP is not available: {}", e);
+    }
+
+    let shutdown = Arc::new(tcp::Shutdown::new(settings.shutdown_timeout));
+    if let Some(interval) = settings.watch {
+        reload::reload_on_change(reloader, interval, Arc::clone(&shutdown));
+    }
+    if let Err(e) = tcp::stop_on_signal(Arc::clone(&shutdown)) {
+        log::warning!("Stopping gracefully on SIGINT and SIGTERM is not available: {}", e);
+    }
+    let handoff = listeners.iter().map(|(listener, _)| listener.try_clone()).collect();
+    match handoff {
+        Ok(handoff) => {
+            if let Err(e) = tcp::upgrade_on_signal(handoff, Arc::clone(&shutdown)) {
+                log::warning!("Upgrading on SIGUSR2 is not available: {}", e);
+            }
+        }
+        Err(e) => log::warning!("Upgrading on SIGUSR2 is not available: {}", e),
+    }
+    inherited.finish();
+
+    let gate = move |turn: tcp::Turn<_>, index: &usize, connection: &tcp::Connection| {
+        // An idle connection keeps the snapshot it was accepted with
+        let (turn, snapshot) = turn.carrying(|| current.load());
+        let service = &snapshot.services[*index];
+        let metrics_path = snapshot.metrics_path.as_deref();
+        let (limits, hosts) = (&snapshot.limits, &snapshot.hosts);
+        let idle = http::request_gate(turn, limits, hosts, service, metrics_path, connection);
+        idle.map(|session| (session, snapshot))
+    };
+    tcp::handle_incoming_connections(listeners, settings.workers, &shutdown, gate);
+    log::info!("The server stopped");
 }
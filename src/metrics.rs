@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// This is the path the metrics are served at, if no other is configured.
+pub const DEFAULT_METRICS_PATH: &str = "/metrics";
+
+/// This is the content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// This is the most buckets a histogram can have, besides the one of every observation.
+const MAX_BUCKETS: usize = 16;
+
+/// This are the upper bounds of the buckets of the request latency, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// This are the upper bounds of the buckets of the response size, in bytes.
+const SIZE_BUCKETS: &[f64] = &[
+    128.0, 1024.0, 8192.0, 65536.0, 524288.0, 4194304.0, 33554432.0,
+];
+
+/// This are the upper bounds of the buckets of the compression ratio, the size of a compressed
+/// body divided by the size of the file.
+const RATIO_BUCKETS: &[f64] = &[0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0];
+
+/// This holds the metrics of the whole process.
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics::new());
+
+/// This is the amount of open connections, which is kept apart from the other metrics, as it
+/// changes twice for every connection.
+static OPEN_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// This struct holds the metrics that are updated once per response
+#[derive(Debug)]
+struct Metrics {
+    /// The responses, counted by request method and status
+    requests: BTreeMap<(String, u16), u64>,
+    /// The time from receiving the head of a request to sending the response
+    latency: Histogram,
+    /// The body bytes of every response
+    response_size: Histogram,
+    /// How much the bodies of compressed responses were compressed
+    compression_ratio: Histogram,
+    /// The compressed responses that were served from a precompressed file
+    precompressed_hits: u64,
+    /// The compressed responses that had to be compressed while sending
+    precompressed_misses: u64,
+    /// The connections accepted since the server started
+    connections: u64,
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            requests: BTreeMap::new(),
+            latency: Histogram::new(LATENCY_BUCKETS),
+            response_size: Histogram::new(SIZE_BUCKETS),
+            compression_ratio: Histogram::new(RATIO_BUCKETS),
+            precompressed_hits: 0,
+            precompressed_misses: 0,
+            connections: 0,
+        }
+    }
+
+    /// This writes the metrics in the Prometheus text format
+    ///
+    /// # Parameters
+    ///
+    /// - `open_connections`: This is the amount of connections that are open right now
+    fn render(&self, open_connections: u64) -> String {
+        let mut text = String::new();
+        header(
+            &mut text,
+            "anes_http_requests_total",
+            "counter",
+            "Responses sent, by request method and status.",
+        );
+        for ((method, status), count) in &self.requests {
+            let _ = writeln!(
+                text,
+                "anes_http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, count
+            );
+        }
+        self.latency.render(
+            &mut text,
+            "anes_http_request_duration_seconds",
+            "Time from receiving the head of a request to sending the response.",
+        );
+        self.response_size.render(
+            &mut text,
+            "anes_http_response_size_bytes",
+            "Body bytes sent per response.",
+        );
+        self.compression_ratio.render(
+            &mut text,
+            "anes_http_compression_ratio",
+            "Size of compressed bodies relative to the files they were made of.",
+        );
+        header(
+            &mut text,
+            "anes_http_precompressed_total",
+            "counter",
+            "Compressed responses, served from a precompressed file (hit) or compressed while \
+             sending (miss).",
+        );
+        let _ = writeln!(
+            text,
+            "anes_http_precompressed_total{{result=\"hit\"}} {}",
+            self.precompressed_hits
+        );
+        let _ = writeln!(
+            text,
+            "anes_http_precompressed_total{{result=\"miss\"}} {}",
+            self.precompressed_misses
+        );
+        header(
+            &mut text,
+            "anes_http_connections_total",
+            "counter",
+            "Connections accepted since the server started.",
+        );
+        let _ = writeln!(text, "anes_http_connections_total {}", self.connections);
+        header(
+            &mut text,
+            "anes_http_open_connections",
+            "gauge",
+            "Connections that are open right now.",
+        );
+        let _ = writeln!(text, "anes_http_open_connections {}", open_connections);
+        text
+    }
+}
+
+/// This writes the `HELP` and `TYPE` lines of a metric
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+}
+
+/// This struct counts observations in buckets with fixed upper bounds
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// The observations per bucket, each counted only in the first bucket it fits in
+    counts: [u64; MAX_BUCKETS],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    const fn new(bounds: &'static [f64]) -> Histogram {
+        assert!(bounds.len() <= MAX_BUCKETS);
+        Histogram {
+            bounds,
+            counts: [0; MAX_BUCKETS],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// This writes the histogram with cumulative buckets, as Prometheus expects them
+    fn render(&self, text: &mut String, name: &str, help: &str) {
+        header(text, name, "histogram", help);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(text, "{}_sum {}", name, self.sum);
+        let _ = writeln!(text, "{}_count {}", name, self.count);
+    }
+}
+
+/// This function checks a path the metrics can be served at
+///
+/// # Errors
+///
+/// Returns a description of the problem if the path does not start with a slash
+pub fn check_path(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err(format!("the metrics path {:?} has to start with /", path));
+    }
+    Ok(())
+}
+
+fn lock() -> MutexGuard<'static, Metrics> {
+    match METRICS.lock() {
+        Ok(metrics) => metrics,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// This function records a response that was sent
+///
+/// # Parameters
+///
+/// - `method`: This is the method of the request, or `None` if it could not be parsed, which is
+///   counted as `-`
+/// - `status`: This is the status code of the response
+/// - `bytes`: This is the amount of body bytes sent
+/// - `duration`: This is how long answering the request took
+pub fn response(method: Option<&str>, status: u16, bytes: u64, duration: Duration) {
+    let mut metrics = lock();
+    let method = method.unwrap_or("-").to_string();
+    *metrics.requests.entry((method, status)).or_insert(0) += 1;
+    metrics.latency.observe(duration.as_secs_f64());
+    metrics.response_size.observe(bytes as f64);
+}
+
+/// This function records a body that was compressed
+///
+/// # Parameters
+///
+/// - `original`: This is the size of the file
+/// - `compressed`: This is the size of the compressed body that was sent
+pub fn compressed(original: u64, compressed: u64) {
+    if original > 0 {
+        lock()
+            .compression_ratio
+            .observe(compressed as f64 / original as f64);
+    }
+}
+
+/// This function records whether a compressed response was served from a precompressed file
+pub fn precompressed(hit: bool) {
+    let mut metrics = lock();
+    if hit {
+        metrics.precompressed_hits += 1;
+    } else {
+        metrics.precompressed_misses += 1;
+    }
+}
+
+/// This counts a connection as open until it is dropped
+#[derive(Debug)]
+pub struct OpenConnection(());
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// This function records a new connection, which counts as open until the returned
+/// `OpenConnection` is dropped
+pub fn connection_opened() -> OpenConnection {
+    lock().connections += 1;
+    OPEN_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    OpenConnection(())
+}
+
+/// This function returns the metrics of the process in the Prometheus text format
+pub fn render() -> String {
+    lock().render(OPEN_CONNECTIONS.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_counters_by_label() {
+        let mut metrics = Metrics::new();
+        *metrics
+            .requests
+            .entry(("GET".to_string(), 200))
+            .or_insert(0) += 2;
+        *metrics
+            .requests
+            .entry(("HEAD".to_string(), 404))
+            .or_insert(0) += 1;
+        metrics.precompressed_hits = 3;
+        metrics.connections = 4;
+
+        let text = metrics.render(1);
+        assert!(text.contains("# TYPE anes_http_requests_total counter\n"));
+        assert!(text.contains("anes_http_requests_total{method=\"GET\",status=\"200\"} 2\n"));
+        assert!(text.contains("anes_http_requests_total{method=\"HEAD\",status=\"404\"} 1\n"));
+        assert!(text.contains("anes_http_precompressed_total{result=\"hit\"} 3\n"));
+        assert!(text.contains("anes_http_precompressed_total{result=\"miss\"} 0\n"));
+        assert!(text.contains("anes_http_connections_total 4\n"));
+        assert!(text.contains("anes_http_open_connections 1\n"));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[1.0, 10.0]);
+        histogram.observe(0.5);
+        histogram.observe(1.0);
+        histogram.observe(5.0);
+        histogram.observe(50.0);
+
+        let mut text = String::new();
+        histogram.render(&mut text, "size", "The size.");
+        assert_eq!(
+            text,
+            "# HELP size The size.\n# TYPE size histogram\nsize_bucket{le=\"1\"} 2\n\
+             size_bucket{le=\"10\"} 3\nsize_bucket{le=\"+Inf\"} 4\nsize_sum 56.5\nsize_count 4\n"
+        );
+    }
+
+    #[test]
+    fn test_open_connections_are_counted_until_dropped() {
+        let connection = connection_opened();
+        assert!(OPEN_CONNECTIONS.load(Ordering::Relaxed) >= 1);
+        assert!(render().contains("# TYPE anes_http_open_connections gauge\n"));
+        drop(connection);
+    }
+}
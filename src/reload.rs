@@ -0,0 +1,401 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+
+use crate::access_log;
+use crate::cli::{Cli, Settings, SettingsError};
+use crate::http::{self, RequestLimits, Service, VirtualHosts};
+use crate::log::{self, LogFormat};
+use crate::tcp::Shutdown;
+
+/// This is the size up to which the content of a watched file is compared, not only its metadata.
+/// Configuration files and certificates are far smaller.
+const HASHED_LENGTH: u64 = 1024 * 1024;
+
+/// This holds everything a connection is served with that can change while the server runs
+#[derive(Debug)]
+pub struct Snapshot {
+    /// The sites, picked by the host of each request
+    pub hosts: Arc<VirtualHosts>,
+    /// How the connections of every listener are answered, in the order of the listeners
+    pub services: Vec<Service>,
+    /// The path the listeners serving files also serve the metrics at, if they do
+    pub metrics_path: Option<String>,
+    /// The limits every request and connection have to stay within
+    pub limits: RequestLimits,
+}
+
+/// This holds the current `Snapshot`. Every connection takes the one that is current when it is
+/// accepted and keeps it until it is closed, so a reload never changes the settings of a
+/// connection halfway through.
+#[derive(Debug)]
+pub struct Current {
+    snapshot: RwLock<Arc<Snapshot>>,
+}
+
+impl Current {
+    /// This Initializes a new `Current` holding the given snapshot
+    pub fn new(snapshot: Snapshot) -> Current {
+        Current {
+            snapshot: RwLock::new(Arc::new(snapshot)),
+        }
+    }
+
+    /// This function returns the snapshot new connections are served with
+    pub fn load(&self) -> Arc<Snapshot> {
+        match self.snapshot.read() {
+            Ok(snapshot) => Arc::clone(&snapshot),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// This function replaces the snapshot for every connection accepted from now on
+    fn store(&self, snapshot: Snapshot) {
+        let snapshot = Arc::new(snapshot);
+        match self.snapshot.write() {
+            Ok(mut current) => *current = snapshot,
+            Err(poisoned) => *poisoned.into_inner() = snapshot,
+        }
+    }
+}
+
+/// This re-reads the settings from the command line and the configuration file the server was
+/// started with, and swaps them into the running server if they are valid
+pub struct Reloader {
+    cli: Cli,
+    current: Arc<Current>,
+    addresses: Vec<String>,
+    workers: usize,
+    bind_retries: u8,
+    shutdown_timeout: Duration,
+    log_format: LogFormat,
+    /// The files the settings were read from, which is also held while reloading, so reloads
+    /// triggered by a signal and by the watcher never run at the same time
+    files: Mutex<Vec<PathBuf>>,
+}
+
+impl Reloader {
+    /// This Initializes a new `Reloader`
+    ///
+    /// # Parameters
+    ///
+    /// - `cli`: This is the command line the server was started with
+    /// - `settings`: This are the settings the server was started with
+    /// - `current`: This is where the reloaded settings are swapped into
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Reloader`
+    pub fn new(cli: Cli, settings: &Settings, current: Arc<Current>) -> Reloader {
+        Reloader {
+            cli,
+            current,
+            addresses: addresses(settings),
+            workers: settings.workers,
+            bind_retries: settings.bind_retries,
+            shutdown_timeout: settings.shutdown_timeout,
+            log_format: settings.log_format,
+            files: Mutex::new(settings.files.clone()),
+        }
+    }
+
+    /// This function reloads the settings. The listeners cannot change without a restart, so
+    /// settings that change them are rejected as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SettingsError` if the new settings are invalid, in which case the running
+    /// server keeps the settings it has
+    pub fn reload(&self) -> Result<(), SettingsError> {
+        let mut files = match self.files.lock() {
+            Ok(files) => files,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let settings = Settings::resolve(self.cli.clone())?;
+        if addresses(&settings) != self.addresses {
+            return Err(SettingsError::Invalid(
+                "the listeners changed, which needs a restart".to_string(),
+            ));
+        }
+        if settings.workers != self.workers
+            || settings.bind_retries != self.bind_retries
+            || settings.shutdown_timeout != self.shutdown_timeout
+            || settings.log_format != self.log_format
+        {
+            log::warning!(
+                "The amount of workers, bind retries, the shutdown timeout and the log format \
+                 only change after a restart"
+            );
+        }
+
+        if settings.precompress {
+            http::precompress(&settings.hosts);
+        }
+        log::set_filter(&settings.log_level);
+        access_log::set(settings.access_log);
+        *files = settings.files;
+        self.current.store(Snapshot {
+            hosts: settings.hosts,
+            services: settings
+                .listeners
+                .into_iter()
+                .map(|listener| listener.service)
+                .collect(),
+            metrics_path: settings.metrics_path,
+            limits: settings.limits,
+        });
+        Ok(())
+    }
+
+    /// This function reloads the settings and logs the outcome
+    fn reload_and_log(&self, reason: &str) {
+        log::info!("Reloading the configuration, as {}", reason);
+        match self.reload() {
+            Ok(()) => log::info!("The configuration was reloaded"),
+            Err(e) => log::error!("Keeping the running configuration: {}", e),
+        }
+    }
+
+    /// This function returns the files the current settings were read from
+    fn files(&self) -> Vec<PathBuf> {
+        match self.files.lock() {
+            Ok(files) => files.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+/// This function returns the addresses of the listeners, together with the kind of their service
+fn addresses(settings: &Settings) -> Vec<String> {
+    settings
+        .listeners
+        .iter()
+        .map(|listener| {
+            let kind = match listener.service {
+                Service::Http => "http",
+                Service::Https(_) => "https",
+                Service::RedirectToHttps(_) => "redirect",
+                Service::Metrics(_) => "metrics",
+            };
+            format!("{} {}", kind, listener.address)
+        })
+        .collect()
+}
+
+/// This function reloads the settings whenever the process receives SIGHUP
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the signal handler cannot be registered
+pub fn reload_on_hangup(reloader: Arc<Reloader>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            reloader.reload_and_log("SIGHUP was received");
+        }
+    });
+    Ok(())
+}
+
+/// This function reloads the settings whenever the configuration file or a certificate changes,
+/// until the server stops. Files are compared by their modification time, size, inode and, if
+/// they are small, their content, so a file replaced within the same second by one of the same
+/// size is noticed as well. Files that were swapped out while being written are picked up on the
+/// next check.
+///
+/// # Parameters
+///
+/// - `reloader`: This reloads the settings
+/// - `interval`: This is how often the files are checked
+/// - `shutdown`: This ends the watching once the server stops
+///
+/// # Returns
+///
+/// Returns the handle of the thread watching the files
+pub fn reload_on_change(
+    reloader: Arc<Reloader>,
+    interval: Duration,
+    shutdown: Arc<Shutdown>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut seen = fingerprint(&reloader.files());
+        while !shutdown.wait_timeout(interval) {
+            let files = reloader.files();
+            let now = fingerprint(&files);
+            if now != seen {
+                reloader.reload_and_log("a watched file changed");
+                // The files may have changed with the settings
+                seen = fingerprint(&reloader.files());
+            }
+        }
+    })
+}
+
+/// This identifies the state of a watched file
+#[derive(Debug, PartialEq)]
+struct Fingerprint {
+    modified: SystemTime,
+    length: u64,
+    inode: u64,
+    /// The hash of the content, if the file is small enough to be read on every check
+    hash: Option<u64>,
+}
+
+/// This function returns the fingerprint of every file, if it exists
+fn fingerprint(files: &[PathBuf]) -> Vec<Option<Fingerprint>> {
+    files
+        .iter()
+        .map(|path| {
+            let metadata = fs::metadata(path).ok()?;
+            let hash = if metadata.len() <= HASHED_LENGTH {
+                let mut hasher = DefaultHasher::new();
+                fs::read(path).ok()?.hash(&mut hasher);
+                Some(hasher.finish())
+            } else {
+                None
+            };
+            Some(Fingerprint {
+                modified: metadata.modified().ok()?,
+                length: metadata.len(),
+                inode: metadata.ino(),
+                hash,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::path::Path;
+
+    /// This writes a configuration serving the given directory and returns the reloader of a
+    /// server started with it
+    fn start(directory: &Path, root: &str) -> (Arc<Reloader>, Arc<Current>) {
+        let config = directory.join("anes-http.toml");
+        write_config(&config, root, "127.0.0.1:0");
+        let cli = Cli::try_parse_from(["anes-http", "-c", config.to_str().unwrap()]).unwrap();
+        let settings = Settings::resolve(cli.clone()).unwrap();
+        let current = Arc::new(Current::new(Snapshot {
+            hosts: Arc::clone(&settings.hosts),
+            services: Vec::new(),
+            metrics_path: None,
+            limits: RequestLimits::default(),
+        }));
+        let reloader = Arc::new(Reloader::new(cli, &settings, Arc::clone(&current)));
+        (reloader, current)
+    }
+
+    fn write_config(config: &Path, root: &str, address: &str) {
+        fs::write(
+            config,
+            format!(
+                "[[listen]]\naddress = \"{}\"\n\n[[server]]\nroot = \"{}\"\n",
+                address, root
+            ),
+        )
+        .unwrap();
+    }
+
+    fn root(current: &Current) -> PathBuf {
+        current.load().hosts.default_site().document_root.clone()
+    }
+
+    #[test]
+    fn test_reloads_valid_settings_and_keeps_the_old_ones_otherwise() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-reload-{}", std::process::id()));
+        fs::create_dir_all(directory.join("a")).unwrap();
+        fs::create_dir_all(directory.join("b")).unwrap();
+        let config = directory.join("anes-http.toml");
+        let (reloader, current) = start(&directory, "a");
+        assert!(root(&current).ends_with("a"));
+
+        write_config(&config, "b", "127.0.0.1:0");
+        reloader.reload().unwrap();
+        assert!(root(&current).ends_with("b"));
+
+        write_config(&config, "missing", "127.0.0.1:0");
+        assert!(matches!(reloader.reload(), Err(SettingsError::Config(_))));
+        fs::write(&config, "[[server]\n").unwrap();
+        assert!(reloader.reload().is_err());
+        write_config(&config, "a", "127.0.0.1:1");
+        assert!(reloader
+            .reload()
+            .unwrap_err()
+            .to_string()
+            .contains("restart"));
+        assert!(root(&current).ends_with("b"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_reloads_when_a_watched_file_changes() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-watch-{}", std::process::id()));
+        fs::create_dir_all(directory.join("a")).unwrap();
+        fs::create_dir_all(directory.join("b")).unwrap();
+        let (reloader, current) = start(&directory, "a");
+        let shutdown = Arc::new(Shutdown::new(Duration::ZERO));
+        let watcher = reload_on_change(reloader, Duration::from_millis(20), Arc::clone(&shutdown));
+
+        thread::sleep(Duration::from_millis(50));
+        write_config(&directory.join("anes-http.toml"), "b", "127.0.0.1:0");
+        for _ in 0..100 {
+            if root(&current).ends_with("b") {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(root(&current).ends_with("b"));
+
+        shutdown.stop();
+        watcher.join().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_notices_same_sized_replacements() {
+        let directory =
+            std::env::temp_dir().join(format!("anes-http-fingerprint-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (file, replacement) = (directory.join("cert.pem"), directory.join("new.pem"));
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let write = |path: &Path, content: &str| {
+            fs::write(path, content).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let files = [file.clone()];
+
+        write(&file, "first");
+        let seen = fingerprint(&files);
+        assert_eq!(fingerprint(&files), seen);
+
+        write(&file, "other");
+        let changed = fingerprint(&files);
+        assert_ne!(changed, seen);
+
+        // Swapping in another file with the same content changes the inode only
+        write(&replacement, "other");
+        fs::rename(&replacement, &file).unwrap();
+        assert_ne!(fingerprint(&files), changed);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
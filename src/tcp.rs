@@ -1,17 +1,45 @@
+use std::io::{self, ErrorKind};
 use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-/// This is the amount of reconnects that will be attempted by the TCP Binder before panicing.
-const RECONNECT_TRIES: u8 = 5;
+use crate::log;
+use idle::{Poller, Serve};
+use thread_pool::ThreadPool;
 
-/// Attempts to spawn a TCP-Server to port 7878. It retries 5 times, after which the function
-/// panics.
+mod handoff;
+mod idle;
+mod shutdown;
+mod thread_pool;
+
+pub use handoff::{upgrade_on_signal, Inherited};
+pub use idle::{Idle, Turn};
+pub use shutdown::{stop_on_signal, Connection, Shutdown, SHUTDOWN_TIMEOUT};
+
+/// This is the amount of reconnects that will be attempted by the TCP Binder before panicing, if
+/// nothing else is configured.
+pub const RECONNECT_TRIES: u8 = 5;
+
+/// This is how long the accept loop waits for a connection before checking if the server stops.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// This is how long the accept loop waits after the first failed accept.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// This is the longest the accept loop waits after repeatedly failed accepts.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Attempts to spawn a TCP-Server to the given address. It retries `max_tries` times, after which
+/// the function panics.
 /// 
 /// # Parameters
 ///
 /// - `tries`: This is the current try, which the function is on. Normally `0` would be passed, as
-/// the function handles the incrementing recursively.
+///   the function handles the incrementing recursively.
+/// - `max_tries`: This is the amount of retries before giving up
+/// - `tcp_address`: This is the address the server binds to
 ///
 /// # Returns
 ///
@@ -20,18 +48,18 @@ const RECONNECT_TRIES: u8 = 5;
 ///
 /// # Errors
 ///
-/// In case the function fails to bind to port 7878 five times it panics and also prints out the
-/// reason for not being able to.
-fn internal_spawn_tcp_server(tries: u8, tcp_address: &str) -> TcpListener {
+/// In case the function fails to bind to the address `max_tries` times it panics and also prints
+/// out the reason for not being able to.
+fn internal_spawn_tcp_server(tries: u8, max_tries: u8, tcp_address: &str) -> TcpListener {
     match TcpListener::bind(tcp_address) {
         Ok(listener) => {
             listener
         },
-        Err(e) if tries < RECONNECT_TRIES => {
-            eprintln!("Failed to bind to port. Reason: {}", e);
-            println!("Trying {} more times", RECONNECT_TRIES - tries);
+        Err(e) if tries < max_tries => {
+            log::warning!("Failed to bind to port. Reason: {}", e);
+            log::info!("Trying {} more times", max_tries - tries);
             thread::sleep(Duration::from_secs(1));
-            internal_spawn_tcp_server(tries + 1, tcp_address)
+            internal_spawn_tcp_server(tries + 1, max_tries, tcp_address)
         },
         Err(e) => {
             panic!("Couldn't bind to port! Reason: {}", e);
@@ -39,8 +67,13 @@ fn internal_spawn_tcp_server(tries: u8, tcp_address: &str) -> TcpListener {
     }
 }
 
-/// Attempts to spawn a TCP-Server to port 7878. It retries 5 times, after which the function
-/// panics.
+/// Attempts to spawn a TCP-Server to the given address. It retries `max_tries` times, after which
+/// the function panics.
+///
+/// # Parameters
+///
+/// - `tcp_address`: This is the address the server binds to, e.g. `127.0.0.1:7878`
+/// - `max_tries`: This is the amount of retries before giving up, usually `RECONNECT_TRIES`
 ///
 /// # Returns
 ///
@@ -49,37 +82,205 @@ fn internal_spawn_tcp_server(tries: u8, tcp_address: &str) -> TcpListener {
 /// 
 /// # Errors
 ///
-/// In case the function fails to bind to port 7878 five times it panics and also prints out the
-/// reason for not being able to.
-pub fn spawn_tcp_server(tcp_address: &str) -> TcpListener {
-    internal_spawn_tcp_server(0, tcp_address)
+/// In case the function fails to bind to the address `max_tries` times it panics and also prints
+/// out the reason for not being able to.
+pub fn spawn_tcp_server(tcp_address: &str, max_tries: u8) -> TcpListener {
+    internal_spawn_tcp_server(0, max_tries, tcp_address)
+}
+
+/// This returns the amount of workers used when nothing else is configured. It is the amount of
+/// available CPU cores, or `4` if that cannot be determined.
+pub fn default_worker_count() -> usize {
+    thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(4)
+}
+
+/// This function handles the traffic that comes into the TcpServer and hands every incoming
+/// connection to a bounded pool of worker threads. When every worker is busy and the queue of the
+/// pool is full, no further connections are accepted until a worker frees up. Every listener is
+/// accepted on from its own thread, while all of them share the same pool.
+///
+/// A connection that waits for its next request is handed back by the gate and waited on by a
+/// single thread instead, so the workers only serve requests. Once something arrives on it or its
+/// deadline runs out, it is handed to the pool again.
+///
+/// Once the `Shutdown` is stopped, no new connections are accepted and the function returns as
+/// soon as the open connections are closed, or their time to finish ran out. The listeners are
+/// left untouched, as they may be shared with the process that takes over.
+///
+/// # Parameters
+///
+/// - `listeners`: This are the `TcpListener` objects, each with the value handed to the gate
+///   together with its connections, e.g. how they are served. Ideally the listeners are spawned
+///   from the `spawn_tcp_server()` function.
+/// - `workers`: This is the amount of worker threads that serve requests concurrently.
+/// - `shutdown`: This stops the server and keeps track of the open connections.
+/// - `http_gate`: This is the function that handles the actual business logic of every incoming
+///   connection. The functions parameters should be the `Turn` the connection gets, the value of
+///   the listener it came from and the `Connection` it reports whether it is idle through. It
+///   returns the connection if it waits for its next request.
+pub fn handle_incoming_connections<T, S, F>(
+    listeners: Vec<(TcpListener, T)>,
+    workers: usize,
+    shutdown: &Arc<Shutdown>,
+    http_gate: F,
+) where
+    T: Send + Sync + 'static,
+    S: Idle,
+    F: Fn(Turn<S>, &T, &Connection) -> Option<S> + Send + Sync + 'static,
+{
+    let pool = Arc::new(ThreadPool::new(workers));
+    let poller = Arc::new(Poller::new().expect("Failed to create the poller of idle connections"));
+    let serve: Serve<S, T> = {
+        let poller = Arc::clone(&poller);
+        Arc::new(move |turn, value, connection| {
+            if let Some(idle) = http_gate(turn, &value, &connection) {
+                poller.park(idle, value, connection);
+            }
+        })
+    };
+    let waiting = poller.spawn(Arc::clone(&pool), Arc::clone(&serve));
+
+    for (listener, value) in listeners {
+        let (pool, serve) = (Arc::clone(&pool), Arc::clone(&serve));
+        let shutdown = Arc::clone(shutdown);
+        let value = Arc::new(value);
+        thread::spawn(move || {
+            accept_connections(&listener, &shutdown, |stream| {
+                let connection = shutdown.track(&stream);
+                let (serve, value) = (Arc::clone(&serve), Arc::clone(&value));
+                pool.execute(move || serve(Turn::Accepted(stream), value, connection));
+            })
+        });
+    }
+
+    shutdown.wait();
+    match shutdown.drain() {
+        0 => log::info!("Every connection was closed"),
+        cut_off => log::warning!("{} connections did not finish in time and were cut off", cut_off),
+    }
+    poller.close();
+    let _ = waiting.join();
+}
+
+/// This function accepts connections from a listener until the server stops. The listener is
+/// waited on with a timeout instead of blocking in `accept()`, so the loop notices the stop
+/// without connecting to itself, which could hand the connection to another process sharing the
+/// listener.
+///
+/// # Parameters
+///
+/// - `listener`: This is the listener, which is switched to non-blocking mode
+/// - `shutdown`: This tells when to stop accepting
+/// - `handle`: This is called with every accepted connection
+fn accept_connections(
+    listener: &TcpListener,
+    shutdown: &Shutdown,
+    mut handle: impl FnMut(TcpStream),
+) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        log::error!("The listener cannot be stopped gracefully: {}", e);
+    }
+
+    let mut backoff = ACCEPT_BACKOFF;
+    while !shutdown.is_stopping() {
+        let accepted = match readable(listener, ACCEPT_POLL_INTERVAL) {
+            Ok(true) => listener.accept(),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                backoff = ACCEPT_BACKOFF;
+                // Some systems hand the non-blocking mode of the listener down to its connections
+                if let Err(e) = stream.set_nonblocking(false) {
+                    log::warning!("Failed to make the connection blocking: {}", e);
+                    continue;
+                }
+                handle(stream);
+            }
+            // Another thread or process sharing the listener was faster
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => backoff = accept_failed(e, backoff),
+        }
+    }
+}
+
+/// This function waits until a connection can be accepted from the listener
+///
+/// # Returns
+///
+/// Returns `true` if a connection is pending, and `false` if the timeout ran out first
+///
+/// # Errors
+///
+/// Returns the `io::Error` the listener cannot be waited on with
+fn readable(listener: &TcpListener, timeout: Duration) -> io::Result<bool> {
+    let mut descriptor = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+    // SAFETY: The descriptor points to exactly one valid `pollfd`
+    match unsafe { libc::poll(&mut descriptor, 1, timeout) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        0 => Ok(false),
+        _ => Ok(true),
+    }
 }
 
-/// This function handles the traffic that comes into the TcpServer and spawns a new thread for
-/// every incoming connection.
+/// This function handles a failed accept. Connections the client aborted before they were
+/// accepted are skipped, while all other errors, like running out of file descriptors, are
+/// retried after waiting, so the loop does not spin while the error persists.
 ///
 /// # Parameters
 ///
-/// - `listener`: This is a `TcpListener` object. Ideally this is spawned from the
-/// `spawn_tcp_server()` function.
+/// - `error`: This is the error the accept failed with
+/// - `backoff`: This is how long to wait before accepting again
+///
+/// # Returns
 ///
-/// - `resolved`: This is the function that handles the actual business logic of every incoming
-/// connection. The functions parameters should be a simple `TcpStream` object.
-pub fn handle_incoming_connections(listener: TcpListener, http_gate: &dyn Fn(TcpStream)) {
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        println!("Connection established!");
-        http_gate(stream);
+/// Returns how long to wait after the next failure, which doubles up to `MAX_ACCEPT_BACKOFF`
+fn accept_failed(error: io::Error, backoff: Duration) -> Duration {
+    match error.kind() {
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::Interrupted => {
+            log::debug!("A connection was aborted before it was accepted: {}", error);
+            backoff
+        }
+        _ => {
+            log::error!("Failed to accept a connection: {}. Retrying in {:?}", error, backoff);
+            thread::sleep(backoff);
+            (backoff * 2).min(MAX_ACCEPT_BACKOFF)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Instant;
+
+    /// This is a connection that never waits for another request
+    struct Answered(TcpStream);
+
+    impl Idle for Answered {
+        fn socket(&self) -> &TcpStream {
+            &self.0
+        }
+
+        fn deadline(&self) -> Instant {
+            Instant::now()
+        }
+    }
 
     #[test]
     fn test_spawn_server() {
-        let listener = internal_spawn_tcp_server(0, "127.0.0.1:0");
+        let listener = internal_spawn_tcp_server(0, RECONNECT_TRIES, "127.0.0.1:0");
         assert!(listener.local_addr().is_ok(), "Listener should have a valid local address");
     }
 
@@ -92,9 +293,48 @@ mod tests {
         let _port = _guard.local_addr().expect("Failed to get the local address").port();
 
         let result = std::panic::catch_unwind(|| {
-            internal_spawn_tcp_server(5, ("127.0.0.1:".to_owned() + &_port.to_string()).as_str());
+            internal_spawn_tcp_server(5, RECONNECT_TRIES, ("127.0.0.1:".to_owned() + &_port.to_string()).as_str());
         });
 
         assert!(result.is_err(), "The binding should have paniced, as it cannot bind to the port but it didn't");
     }
+
+    #[test]
+    fn test_stops_accepting_and_lets_requests_finish() {
+        let listener = internal_spawn_tcp_server(0, RECONNECT_TRIES, "127.0.0.1:0");
+        let port = listener.local_addr().unwrap().port();
+        let shutdown = Arc::new(Shutdown::new(Duration::from_secs(5)));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let stopping = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            handle_incoming_connections(vec![(listener, ())], 2, &stopping, |turn, _, _| {
+                if let Turn::Accepted(mut stream) = turn {
+                    thread::sleep(Duration::from_millis(300));
+                    let _ = std::io::Write::write_all(&mut stream, b"done");
+                }
+                None::<Answered>
+            });
+            sender.send(()).unwrap();
+        });
+
+        let mut in_flight = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        shutdown.stop();
+
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut in_flight, &mut response).unwrap();
+        assert_eq!(response, "done", "The in-flight connection was cut off");
+        assert!(receiver.recv_timeout(Duration::from_secs(2)).is_ok(), "The server did not stop");
+    }
+
+    #[test]
+    fn test_failed_accepts_back_off() {
+        let aborted = io::Error::from(ErrorKind::ConnectionAborted);
+        assert_eq!(accept_failed(aborted, ACCEPT_BACKOFF), ACCEPT_BACKOFF);
+
+        let exhausted = io::Error::other("Too many open files");
+        assert_eq!(accept_failed(exhausted, ACCEPT_BACKOFF), ACCEPT_BACKOFF * 2);
+        let exhausted = io::Error::other("Too many open files");
+        assert_eq!(accept_failed(exhausted, MAX_ACCEPT_BACKOFF), MAX_ACCEPT_BACKOFF);
+    }
 }
@@ -0,0 +1,392 @@
+use std::env;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{parent_id, CommandExt};
+use std::process::{self, Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use signal_hook::consts::SIGUSR2;
+use signal_hook::iterator::Signals;
+
+use super::Shutdown;
+use crate::log;
+
+/// This is the first file descriptor listeners are passed in, right after stdin, stdout and
+/// stderr.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// This names the server that started this one during an upgrade, which is stopped once this one
+/// took over the listeners.
+const UPGRADE_FROM: &str = "ANES_HTTP_UPGRADE_FROM";
+
+/// This struct holds the listeners passed to the process, either through socket activation as
+/// described in `sd_listen_fds(3)`, or by the server this one replaces during an upgrade.
+#[derive(Debug)]
+pub struct Inherited {
+    listeners: Vec<TcpListener>,
+    /// The process id of the server this one replaces, if it was started by an upgrade
+    upgraded_from: Option<u32>,
+}
+
+impl Inherited {
+    /// This takes over the listeners passed through `LISTEN_FDS`. The variables are removed, so
+    /// they are not passed on to other processes. As that is not thread safe, this has to be
+    /// called before any thread is spawned.
+    ///
+    /// # Returns
+    ///
+    /// It returns the inherited listeners, which are empty if the process was started normally
+    pub fn from_env() -> Inherited {
+        let count = listen_fds(
+            env::var("LISTEN_PID").ok().as_deref(),
+            env::var("LISTEN_FDS").ok().as_deref(),
+            process::id(),
+        );
+        let upgraded_from = env::var(UPGRADE_FROM).ok().and_then(|pid| pid.parse().ok());
+        for variable in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES", UPGRADE_FROM] {
+            env::remove_var(variable);
+        }
+
+        let listeners = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .filter_map(|fd| {
+                // SAFETY: This only reads the flags of the descriptor
+                if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    log::warning!("The inherited file descriptor {} is not open", fd);
+                    return None;
+                }
+                // SAFETY: The descriptors were passed to this process, which now owns them
+                let listener = unsafe { TcpListener::from_raw_fd(fd) };
+                match inherited_listener(&listener) {
+                    Ok(address) => {
+                        log::debug!("Inherited the listener on {}", address);
+                        Some(listener)
+                    }
+                    Err(e) => {
+                        log::warning!("The inherited file descriptor {} cannot be used: {}", fd, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Inherited {
+            listeners,
+            upgraded_from,
+        }
+    }
+
+    /// This function takes the inherited listener bound to an address, so it does not have to be
+    /// bound again
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: This is the address of a configured listener, with a port
+    ///
+    /// # Returns
+    ///
+    /// Returns the `TcpListener`, or `None` if none of the inherited ones is bound to the address
+    pub fn take(&mut self, address: &str) -> Option<TcpListener> {
+        let addresses: Vec<SocketAddr> = address.to_socket_addrs().ok()?.collect();
+        let position = self.listeners.iter().position(|listener| {
+            listener
+                .local_addr()
+                .is_ok_and(|local| addresses.contains(&local))
+        })?;
+        Some(self.listeners.remove(position))
+    }
+
+    /// This function closes the inherited listeners that are no longer configured and, if this
+    /// server was started by an upgrade, stops the server it replaces, now that it took over
+    pub fn finish(self) {
+        for listener in self.listeners {
+            if let Ok(address) = listener.local_addr() {
+                log::warning!(
+                    "Closing the inherited listener on {}, as it is not configured",
+                    address
+                );
+            }
+        }
+
+        let Some(pid) = self.upgraded_from else {
+            return;
+        };
+        // The old server may already be gone, and its process id taken by another process
+        if pid != parent_id() {
+            log::warning!("The server this one replaces is gone");
+            return;
+        }
+        log::info!("Took over the listeners, stopping the old server {}", pid);
+        // SAFETY: This only sends a signal
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+            log::error!(
+                "Failed to stop the old server: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// This function returns the amount of listeners passed through socket activation
+///
+/// # Parameters
+///
+/// - `pid`: This is the value of `LISTEN_PID`, the process the listeners are meant for
+/// - `fds`: This is the value of `LISTEN_FDS`, the amount of listeners
+/// - `own_pid`: This is the id of the current process
+///
+/// # Returns
+///
+/// Returns the amount, which is `0` if the listeners are meant for another process
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> RawFd {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return 0;
+    }
+    fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+/// This function checks that an inherited file descriptor is a listening TCP socket, and keeps
+/// it from being passed on to other programs
+///
+/// # Returns
+///
+/// Returns the address the listener is bound to
+fn inherited_listener(listener: &TcpListener) -> io::Result<SocketAddr> {
+    let fd = listener.as_raw_fd();
+    let mut listening: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: The option is written to a `c_int` of the given length
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut listening as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if listening == 0 {
+        return Err(io::Error::other("the socket does not listen"));
+    }
+    // SAFETY: This only sets a flag of the descriptor
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    listener.local_addr()
+}
+
+/// This function upgrades the server whenever the process receives SIGUSR2. The binary is
+/// started again with the same arguments and the listeners, and once it took them over, it stops
+/// this server, which finishes its open connections. If the new server fails to start, this one
+/// keeps running.
+///
+/// The new server is a child of this one and keeps running under its own process id. A
+/// supervisor tracking the main process id, like a systemd service, takes the exit of this
+/// server for the end of the service and stops the new one with it. Under such a supervisor the
+/// service has to be restarted through it instead, which socket activation keeps the listeners
+/// open for.
+///
+/// # Parameters
+///
+/// - `listeners`: This are the listeners passed to the new server
+/// - `shutdown`: This tells if the server is stopping, in which case it is not upgraded
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the signal handler cannot be registered
+pub fn upgrade_on_signal(listeners: Vec<TcpListener>, shutdown: Arc<Shutdown>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGUSR2])?;
+    let upgrading = Arc::new(AtomicBool::new(false));
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if shutdown.is_stopping() {
+                continue;
+            }
+            if upgrading.swap(true, Ordering::SeqCst) {
+                log::warning!("An upgrade is already running");
+                continue;
+            }
+            match spawn_upgrade(&listeners) {
+                Ok(child) => {
+                    log::info!("Upgrading, the new server runs as {}", child.id());
+                    let (shutdown, upgrading) = (Arc::clone(&shutdown), Arc::clone(&upgrading));
+                    thread::spawn(move || wait_for_upgrade(child, &shutdown, &upgrading));
+                }
+                Err(e) => {
+                    log::error!("Failed to start the new server: {}", e);
+                    upgrading.store(false, Ordering::SeqCst);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// This function starts the new server with the same arguments and the listeners
+fn spawn_upgrade(listeners: &[TcpListener]) -> io::Result<Child> {
+    let mut args = env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::other("the path of the binary is unknown"))?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(UPGRADE_FROM, process::id().to_string());
+    spawn_with_listeners(command, listeners)
+}
+
+/// This function starts a process with the listeners in the descriptors right after stderr, the
+/// way socket activation passes them
+///
+/// # Parameters
+///
+/// - `command`: This is the process to start
+/// - `listeners`: This are the listeners passed to it
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the listeners cannot be passed on or the process cannot be started
+fn spawn_with_listeners(mut command: Command, listeners: &[TcpListener]) -> io::Result<Child> {
+    let count = listeners.len() as RawFd;
+
+    // The listeners are first moved above the descriptors they are passed in, so none of them is
+    // overwritten before it was passed on. The copies are closed once the new server started.
+    let moved = listeners
+        .iter()
+        .map(|listener| {
+            // SAFETY: This only duplicates the descriptor, the copy is not passed on by itself
+            match unsafe {
+                libc::fcntl(
+                    listener.as_raw_fd(),
+                    libc::F_DUPFD_CLOEXEC,
+                    LISTEN_FDS_START + count,
+                )
+            } {
+                -1 => Err(io::Error::last_os_error()),
+                // SAFETY: The copy is owned by nothing else
+                fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+            }
+        })
+        .collect::<io::Result<Vec<OwnedFd>>>()?;
+    let fds: Vec<RawFd> = moved.iter().map(AsRawFd::as_raw_fd).collect();
+
+    command
+        .env("LISTEN_FDS", count.to_string())
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDNAMES");
+    // SAFETY: Only `dup2`, which is async-signal-safe, runs between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for (target, fd) in (LISTEN_FDS_START..).zip(&fds) {
+                if libc::dup2(*fd, target) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// This function waits for the new server to take over. It is only reported if it exits before
+/// that, as this server keeps running then and can be upgraded again.
+fn wait_for_upgrade(mut child: Child, shutdown: &Shutdown, upgrading: &AtomicBool) {
+    let status = child.wait();
+    if !shutdown.is_stopping() {
+        match status {
+            Ok(status) => log::error!("The new server exited with {} before it took over", status),
+            Err(e) => log::error!("Failed to wait for the new server: {}", e),
+        }
+    }
+    upgrading.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    /// This names the address the listener is passed to the test process for
+    const TEST_ADDRESS: &str = "ANES_HTTP_TEST_INHERITED_ADDRESS";
+
+    #[test]
+    fn test_passes_the_listeners_to_a_new_process() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut command = Command::new(env::current_exe().unwrap());
+        command
+            .args([
+                "tcp::handoff::tests::serve_the_inherited_listener",
+                "--exact",
+                "--ignored",
+            ])
+            .env(TEST_ADDRESS, address.to_string())
+            .stdout(Stdio::null());
+        let mut child = spawn_with_listeners(command, &[listener]).unwrap();
+
+        // Only the copy of the new process accepts connections now
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).unwrap();
+        assert_eq!(answer, "inherited");
+        assert!(child.wait().unwrap().success());
+    }
+
+    /// This is run as the new process of `test_passes_the_listeners_to_a_new_process`, taking
+    /// over the listener the way the server does on start
+    #[test]
+    #[ignore = "runs as the process the listeners are passed to"]
+    fn serve_the_inherited_listener() {
+        let Ok(address) = env::var(TEST_ADDRESS) else {
+            return;
+        };
+        let mut inherited = Inherited::from_env();
+        let listener = inherited
+            .take(&address)
+            .expect("The listener was not passed on");
+        inherited.finish();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"inherited").unwrap();
+    }
+
+    #[test]
+    fn test_counts_the_listeners_meant_for_this_process() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        assert_eq!(listen_fds(None, Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("41"), Some("2"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("two"), 42), 0);
+    }
+
+    #[test]
+    fn test_takes_the_listener_of_an_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(inherited_listener(&listener).is_ok());
+        let mut inherited = Inherited {
+            listeners: vec![listener],
+            upgraded_from: None,
+        };
+
+        assert!(inherited.take("127.0.0.1:1").is_none());
+        assert!(inherited.take(&format!("127.0.0.1:{}", port)).is_some());
+        assert!(inherited.take(&format!("127.0.0.1:{}", port)).is_none());
+        inherited.finish();
+    }
+
+    #[test]
+    fn test_rejects_sockets_that_do_not_listen() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // SAFETY: The connected socket is only borrowed, the listener made of it is forgotten
+        let connected = unsafe { TcpListener::from_raw_fd(client.as_raw_fd()) };
+        assert!(inherited_listener(&connected).is_err());
+        std::mem::forget(connected);
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::iter;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::shutdown::Connection;
+use super::thread_pool::ThreadPool;
+use crate::log;
+
+/// This is how long the poller waits before trying again after waiting on the connections failed.
+const POLL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// This is a connection that waits for its next request. Instead of blocking a worker, it is
+/// waited on by the `Poller` until the client sends something or its deadline runs out.
+pub trait Idle: Send + 'static {
+    /// This returns the socket that becomes readable once the next request arrives
+    fn socket(&self) -> &TcpStream;
+
+    /// This returns the point in time the connection is given up at, if nothing arrived by then
+    fn deadline(&self) -> Instant;
+}
+
+/// An idle connection can carry whatever it is served with alongside it, e.g. the settings it
+/// was accepted with
+impl<S: Idle, C: Send + 'static> Idle for (S, C) {
+    fn socket(&self) -> &TcpStream {
+        self.0.socket()
+    }
+
+    fn deadline(&self) -> Instant {
+        self.0.deadline()
+    }
+}
+
+/// This is why a connection is handed to the gate
+#[derive(Debug)]
+pub enum Turn<S> {
+    /// The connection was just accepted
+    Accepted(TcpStream),
+    /// Something arrived on the idle connection, which is either the next request or the client
+    /// closing it
+    Readable(S),
+    /// Nothing arrived on the idle connection before its deadline
+    Expired(S),
+}
+
+impl<S, C> Turn<(S, C)> {
+    /// This splits off what an idle connection carries, or creates it for a connection that was
+    /// just accepted
+    ///
+    /// # Parameters
+    ///
+    /// - `create`: This creates what a new connection carries while it is idle
+    ///
+    /// # Returns
+    ///
+    /// Returns the turn of the connection itself, together with what it carries
+    pub fn carrying(self, create: impl FnOnce() -> C) -> (Turn<S>, C) {
+        match self {
+            Turn::Accepted(stream) => (Turn::Accepted(stream), create()),
+            Turn::Readable((idle, carried)) => (Turn::Readable(idle), carried),
+            Turn::Expired((idle, carried)) => (Turn::Expired(idle), carried),
+        }
+    }
+}
+
+/// This is the function a connection is served with on every turn, together with the value of
+/// the listener it came from and its `Connection`
+pub(super) type Serve<S, T> = Arc<dyn Fn(Turn<S>, Arc<T>, Connection) + Send + Sync>;
+
+/// This is an idle connection, together with everything it is served with once it gets its turn
+struct Parked<S, T> {
+    idle: S,
+    value: Arc<T>,
+    connection: Connection,
+}
+
+struct State<S, T> {
+    closed: bool,
+    parked: Vec<Parked<S, T>>,
+}
+
+/// This waits on the idle connections of every listener from a single thread, and hands each of
+/// them back to the pool once it became readable or expired. This way the workers only serve
+/// requests, and a client keeping its connection open does not keep others from being served.
+pub(super) struct Poller<S, T> {
+    state: Mutex<State<S, T>>,
+    /// Writing to this wakes the thread, so it starts waiting on the newly parked connections
+    waker: UnixStream,
+    /// This is waited on together with the idle connections
+    woken: UnixStream,
+}
+
+impl<S: Idle, T: Send + Sync + 'static> Poller<S, T> {
+    /// This Initializes a new `Poller` without any connections
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Poller`
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the socket pair the thread is woken through cannot be created
+    pub(super) fn new() -> io::Result<Poller<S, T>> {
+        let (waker, woken) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        woken.set_nonblocking(true)?;
+        Ok(Poller {
+            state: Mutex::new(State {
+                closed: false,
+                parked: Vec::new(),
+            }),
+            waker,
+            woken,
+        })
+    }
+
+    /// This function spawns the thread that waits on the idle connections until the `Poller` is
+    /// closed
+    ///
+    /// # Parameters
+    ///
+    /// - `pool`: This is the pool the connections are served on once they get their turn
+    /// - `serve`: This is the function the connections are served with
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle of the thread, which finishes once the `Poller` is closed
+    pub(super) fn spawn(
+        self: &Arc<Self>,
+        pool: Arc<ThreadPool>,
+        serve: Serve<S, T>,
+    ) -> JoinHandle<()> {
+        let poller = Arc::clone(self);
+        thread::Builder::new()
+            .name("anes-http-poller".to_string())
+            .spawn(move || poller.run(&pool, &serve))
+            .expect("Failed to spawn the poller thread")
+    }
+
+    /// This function hands an idle connection to the `Poller`. Once it is closed, the connection
+    /// is closed as well.
+    ///
+    /// # Parameters
+    ///
+    /// - `idle`: This is the connection waiting for its next request
+    /// - `value`: This is the value of the listener the connection came from
+    /// - `connection`: This is where the connection reports its state to the `Shutdown`
+    pub(super) fn park(&self, idle: S, value: Arc<T>, connection: Connection) {
+        let mut state = self.lock();
+        if state.closed {
+            return;
+        }
+        state.parked.push(Parked {
+            idle,
+            value,
+            connection,
+        });
+        drop(state);
+        self.wake();
+    }
+
+    /// This function closes every parked connection and stops the thread
+    pub(super) fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        let parked = std::mem::take(&mut state.parked);
+        drop(state);
+        drop(parked);
+        self.wake();
+    }
+
+    /// This function waits on the parked connections until the `Poller` is closed
+    fn run(&self, pool: &ThreadPool, serve: &Serve<S, T>) {
+        loop {
+            let (mut descriptors, timeout) = {
+                let state = self.lock();
+                if state.closed {
+                    return;
+                }
+                let sockets = state
+                    .parked
+                    .iter()
+                    .map(|parked| parked.idle.socket().as_raw_fd());
+                let descriptors = iter::once(self.woken.as_raw_fd())
+                    .chain(sockets)
+                    .map(|fd| libc::pollfd {
+                        fd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    })
+                    .collect::<Vec<_>>();
+                let deadline = state
+                    .parked
+                    .iter()
+                    .map(|parked| parked.idle.deadline())
+                    .min();
+                let timeout =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                (descriptors, timeout)
+            };
+
+            if let Err(e) = poll(&mut descriptors, timeout) {
+                log::error!("Failed to wait on the idle connections: {}", e);
+                thread::sleep(POLL_BACKOFF);
+            }
+            let mut drained = [0; 64];
+            while matches!((&self.woken).read(&mut drained), Ok(read) if read > 0) {}
+
+            for (turn, value, connection) in self.take_turns(&descriptors) {
+                let serve = Arc::clone(serve);
+                pool.execute(move || serve(turn, value, connection));
+            }
+        }
+    }
+
+    /// This function takes the connections that became readable or expired out of the parked
+    /// ones. Connections parked after the descriptors were collected are only checked for their
+    /// deadline.
+    ///
+    /// # Parameters
+    ///
+    /// - `descriptors`: This are the descriptors that were waited on, starting with the one the
+    ///   thread is woken through, followed by the parked connections in order
+    ///
+    /// # Returns
+    ///
+    /// Returns the turns of the connections, which are served outside of the lock, as handing
+    /// them to the pool may block until a worker frees up
+    fn take_turns(&self, descriptors: &[libc::pollfd]) -> Vec<(Turn<S>, Arc<T>, Connection)> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let mut turns = Vec::new();
+        for (index, parked) in std::mem::take(&mut state.parked).into_iter().enumerate() {
+            let readable = descriptors
+                .get(index + 1)
+                .is_some_and(|polled| polled.revents != 0);
+            let turn = match parked.idle {
+                idle if readable => Turn::Readable(idle),
+                idle if idle.deadline() <= now => Turn::Expired(idle),
+                idle => {
+                    state.parked.push(Parked { idle, ..parked });
+                    continue;
+                }
+            };
+            turns.push((turn, parked.value, parked.connection));
+        }
+        turns
+    }
+
+    /// This function wakes the thread, so it starts over waiting on the parked connections
+    fn wake(&self) {
+        // A full socket means the thread is woken anyway
+        if let Err(e) = (&self.waker).write(&[1]) {
+            if e.kind() != ErrorKind::WouldBlock {
+                log::error!("Failed to wake the poller: {}", e);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<S, T>> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// This function waits until one of the descriptors is readable, closed or failed
+///
+/// # Parameters
+///
+/// - `descriptors`: This are the descriptors, whose `revents` are set to what happened on them
+/// - `timeout`: This is how long to wait at most, or `None` to wait without limit
+///
+/// # Errors
+///
+/// Returns the `io::Error` the descriptors cannot be waited on with
+fn poll(descriptors: &mut [libc::pollfd], timeout: Option<Duration>) -> io::Result<()> {
+    // The timeout is rounded up, so the thread does not wake right before a deadline
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout
+            .as_millis()
+            .saturating_add(1)
+            .try_into()
+            .unwrap_or(libc::c_int::MAX)
+    });
+    // SAFETY: The pointer and length describe the valid `pollfd`s of the slice
+    let polled = unsafe {
+        libc::poll(
+            descriptors.as_mut_ptr(),
+            descriptors.len() as libc::nfds_t,
+            timeout,
+        )
+    };
+    match polled {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == ErrorKind::Interrupted => Ok(()),
+            e => Err(e),
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::Shutdown;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    struct Waiting(TcpStream, Instant);
+
+    impl Idle for Waiting {
+        fn socket(&self) -> &TcpStream {
+            &self.0
+        }
+
+        fn deadline(&self) -> Instant {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_hands_back_readable_and_expired_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Arc::new(Shutdown::new(Duration::from_secs(1)));
+        let poller = Arc::new(Poller::<Waiting, &str>::new().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let serve: Serve<Waiting, &str> = Arc::new(move |turn, value, _| {
+            let turn = match turn {
+                Turn::Accepted(_) => "accepted",
+                Turn::Readable(_) => "readable",
+                Turn::Expired(_) => "expired",
+            };
+            sender.lock().unwrap().send((*value, turn)).unwrap();
+        });
+        let waiting = poller.spawn(Arc::new(ThreadPool::new(1)), serve);
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let connection = shutdown.track(&stream);
+        let far = Instant::now() + Duration::from_secs(30);
+        poller.park(Waiting(stream, far), Arc::new("talking"), connection);
+        let _silent = TcpStream::connect(address).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let connection = shutdown.track(&stream);
+        let soon = Instant::now() + Duration::from_millis(200);
+        poller.park(Waiting(stream, soon), Arc::new("silent"), connection);
+
+        client.write_all(b"GET").unwrap();
+        let turn = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(turn, ("talking", "readable"));
+        let turn = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(turn, ("silent", "expired"));
+
+        poller.close();
+        waiting.join().unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown as Direction, TcpStream};
+use std::process;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::log;
+
+/// This is how long in-flight requests may take to finish during a shutdown, if nothing else is
+/// configured.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// This coordinates stopping the server. Once it is stopped, no new connections are accepted,
+/// idle keep-alive connections are closed, and the connections that are serving a request get
+/// until the timeout to finish it before they are cut off.
+#[derive(Debug)]
+pub struct Shutdown {
+    timeout: Duration,
+    state: Mutex<State>,
+    /// This is notified when the server is stopped and whenever a connection is closed
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    stopping: bool,
+    next_id: u64,
+    connections: HashMap<u64, Tracked>,
+}
+
+/// This is an open connection, as seen by the `Shutdown`
+#[derive(Debug)]
+struct Tracked {
+    /// A handle to the socket, used to close it. It is `None` if the socket could not be cloned,
+    /// in which case the connection is waited for, but cannot be cut off.
+    stream: Option<TcpStream>,
+    /// Whether the connection waits for the next request instead of serving one
+    idle: bool,
+}
+
+impl Shutdown {
+    /// This Initializes a new `Shutdown`
+    ///
+    /// # Parameters
+    ///
+    /// - `timeout`: This is how long in-flight requests may take to finish once the server stops
+    ///
+    /// # Returns
+    ///
+    /// It returns the newly created `Shutdown`
+    pub fn new(timeout: Duration) -> Shutdown {
+        Shutdown {
+            timeout,
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// This function stops the server. Idle connections are closed right away, all others are
+    /// closed once they answered their current request.
+    pub fn stop(&self) {
+        let mut state = self.lock();
+        if state.stopping {
+            return;
+        }
+        state.stopping = true;
+        for tracked in state.connections.values().filter(|tracked| tracked.idle) {
+            close(tracked);
+        }
+        self.changed.notify_all();
+    }
+
+    /// This function checks if the server is stopping
+    pub fn is_stopping(&self) -> bool {
+        self.lock().stopping
+    }
+
+    /// This function starts keeping track of a new connection until the returned `Connection` is
+    /// dropped. The connection counts as serving a request until it reports otherwise.
+    pub fn track(self: &Arc<Self>, stream: &TcpStream) -> Connection {
+        let stream = match stream.try_clone() {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                log::warning!("The connection cannot be closed on shutdown: {}", e);
+                None
+            }
+        };
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.connections.insert(
+            id,
+            Tracked {
+                stream,
+                idle: false,
+            },
+        );
+        Connection {
+            shutdown: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// This function blocks until the server is stopped
+    pub fn wait(&self) {
+        let mut state = self.lock();
+        while !state.stopping {
+            state = match self.changed.wait(state) {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
+    }
+
+    /// This function blocks until the server is stopped, but no longer than the given duration
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the server is stopping
+    pub fn wait_timeout(&self, duration: Duration) -> bool {
+        let state = self.lock();
+        let (state, _) = match self
+            .changed
+            .wait_timeout_while(state, duration, |state| !state.stopping)
+        {
+            Ok(waited) => waited,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        state.stopping
+    }
+
+    /// This function waits for the open connections to close, but no longer than the timeout,
+    /// after which the remaining ones are cut off
+    ///
+    /// # Returns
+    ///
+    /// Returns the amount of connections that were cut off
+    pub fn drain(&self) -> usize {
+        let deadline = Instant::now() + self.timeout;
+        let mut state = self.lock();
+        while !state.connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = match self.changed.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+        for tracked in state.connections.values() {
+            close(tracked);
+        }
+        state.connections.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// This function closes the socket of a connection, which ends every read and write on it
+fn close(tracked: &Tracked) {
+    if let Some(stream) = &tracked.stream {
+        let _ = stream.shutdown(Direction::Both);
+    }
+}
+
+/// This is the handle a connection reports its state to the `Shutdown` through. The connection
+/// is no longer waited for once it is dropped.
+#[derive(Debug)]
+pub struct Connection {
+    shutdown: Arc<Shutdown>,
+    id: u64,
+}
+
+impl Connection {
+    /// This function reports that the connection waits for the next request, so it can be
+    /// closed right away when the server stops
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the server is stopping, in which case the connection has to be closed
+    pub fn idle(&self) -> bool {
+        let mut state = self.shutdown.lock();
+        if state.stopping {
+            return false;
+        }
+        if let Some(tracked) = state.connections.get_mut(&self.id) {
+            tracked.idle = true;
+        }
+        true
+    }
+
+    /// This function reports that the next request started arriving, so it is answered before
+    /// the connection is closed
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the connection was closed while it was idle
+    pub fn busy(&self) -> bool {
+        let mut state = self.shutdown.lock();
+        let stopping = state.stopping;
+        match state.connections.get_mut(&self.id) {
+            Some(tracked) if stopping && tracked.idle => false,
+            Some(tracked) => {
+                tracked.idle = false;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// This function checks if the server is stopping, in which case no further requests should
+    /// be read from the connection
+    pub fn is_stopping(&self) -> bool {
+        self.shutdown.is_stopping()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut state = self.shutdown.lock();
+        state.connections.remove(&self.id);
+        self.shutdown.changed.notify_all();
+    }
+}
+
+/// This function stops the server when the process receives SIGINT or SIGTERM. A second signal
+/// exits right away, without waiting for the open connections.
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the signal handler cannot be registered
+pub fn stop_on_signal(shutdown: Arc<Shutdown>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if shutdown.is_stopping() {
+                log::warning!("Exiting without waiting for the open connections");
+                process::exit(1);
+            }
+            log::info!(
+                "Shutting down, open connections get {:?} to finish",
+                shutdown.timeout
+            );
+            shutdown.stop();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// This returns both ends of a new connection
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn test_stop_closes_idle_connections_only() {
+        let shutdown = Arc::new(Shutdown::new(Duration::from_secs(5)));
+        let (mut idle_client, idle_server) = connection();
+        let (_busy_client, busy_server) = connection();
+        let idle = shutdown.track(&idle_server);
+        let busy = shutdown.track(&busy_server);
+        assert!(idle.idle());
+
+        shutdown.stop();
+        assert_eq!(idle_client.read(&mut [0; 1]).unwrap(), 0);
+        assert!(!idle.busy());
+        assert!(busy.busy());
+        assert!(busy.is_stopping());
+        assert!(!busy.idle());
+
+        drop(idle);
+        drop(busy);
+        assert_eq!(shutdown.drain(), 0);
+    }
+
+    #[test]
+    fn test_drain_waits_for_connections_until_the_timeout() {
+        let shutdown = Arc::new(Shutdown::new(Duration::from_millis(200)));
+        let (_client, server) = connection();
+        let finishing = shutdown.track(&server);
+        let (mut stuck_client, stuck_server) = connection();
+        let _stuck = shutdown.track(&stuck_server);
+        shutdown.stop();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(finishing);
+        });
+        let started = Instant::now();
+        assert_eq!(shutdown.drain(), 1);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(stuck_client.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_wait_timeout_returns_once_stopped() {
+        let shutdown = Arc::new(Shutdown::new(Duration::ZERO));
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+
+        let stopping = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopping.stop();
+        });
+        let started = Instant::now();
+        assert!(shutdown.wait_timeout(Duration::from_secs(10)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
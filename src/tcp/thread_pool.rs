@@ -0,0 +1,173 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::log;
+
+/// This is the amount of queued jobs allowed per worker before `execute()` starts blocking.
+const QUEUE_SLOTS_PER_WORKER: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// This is a bounded pool of worker threads. Jobs are handed to the workers through a bounded
+/// queue, so when every worker is busy and the queue is full, the caller is blocked until a slot
+/// frees up. This is how the accept loop gets backpressure instead of spawning without limit.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    sender: Option<SyncSender<Job>>,
+}
+
+impl ThreadPool {
+    /// This creates a new `ThreadPool` with a fixed amount of workers
+    ///
+    /// # Parameters
+    ///
+    /// - `size`: This is the amount of worker threads that will be spawned
+    ///
+    /// # Returns
+    ///
+    /// Returns the newly created `ThreadPool`
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `size` is zero, as a pool without workers would never run a job.
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0, "A thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::sync_channel(size * QUEUE_SLOTS_PER_WORKER);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver)))
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// This hands a job to the pool. If the queue is full, this blocks until a worker picks up a
+    /// queued job.
+    ///
+    /// # Parameters
+    ///
+    /// - `job`: This is the closure that will be run on one of the workers
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            if sender.send(Box::new(job)).is_err() {
+                log::error!("All workers of the thread pool are gone, dropping the job");
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Dropping the sender closes the queue, after which every worker finishes its current job
+    /// and exits. The workers are joined so no job is cut off halfway.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// This is a single worker thread of the `ThreadPool`
+struct Worker {
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// This spawns the worker thread, which keeps pulling jobs from the shared queue until it is
+    /// closed. A panicking job is caught, so it only takes down its own connection and not the
+    /// worker.
+    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Worker {
+        let handle = thread::Builder::new()
+            .name(format!("anes-http-worker-{}", id))
+            .spawn(move || loop {
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+
+                match job {
+                    Ok(job) => {
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            log::error!("Worker {} caught a panicking job", id);
+                        }
+                    }
+                    Err(_) => break,
+                }
+            })
+            .expect("Failed to spawn worker thread");
+
+        Worker {
+            handle: Some(handle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_pool_runs_every_job() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4);
+
+        for _ in 0..32 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 32, "Not every job was run");
+    }
+
+    #[test]
+    fn test_slow_job_does_not_block_others() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| thread::sleep(Duration::from_secs(2)));
+        pool.execute(move || sender.send(()).unwrap());
+
+        assert!(
+            receiver.recv_timeout(Duration::from_millis(500)).is_ok(),
+            "The second job was stalled by the slow one"
+        );
+    }
+
+    #[test]
+    fn test_panicking_job_keeps_worker_alive() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("Job failed on purpose"));
+        pool.execute(move || sender.send(()).unwrap());
+
+        assert!(
+            receiver.recv_timeout(Duration::from_secs(1)).is_ok(),
+            "The worker died together with the panicking job"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_empty_pool_panics() {
+        ThreadPool::new(0);
+    }
+}
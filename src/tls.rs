@@ -0,0 +1,319 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned, SupportedCipherSuite};
+use rustls::{SupportedProtocolVersion, ALL_VERSIONS};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+use crate::http::VirtualHosts;
+
+/// This is the only application protocol the server speaks, which is offered through ALPN if
+/// nothing else is configured.
+pub const HTTP_1_1: &str = "http/1.1";
+
+/// This describes why TLS cannot be set up
+#[derive(Debug)]
+pub enum TlsError {
+    /// A PEM file cannot be read or holds nothing of the expected kind
+    Pem {
+        path: PathBuf,
+        error: rustls_pki_types::pem::Error,
+    },
+    /// The certificate chain and the private key cannot be used together
+    Key { path: PathBuf, error: rustls::Error },
+    /// The protocol version is not `1.2` or `1.3`
+    InvalidVersion(String),
+    /// The cipher suite is unknown to rustls
+    InvalidCipherSuite(String),
+    /// The application protocol is not one the server speaks
+    InvalidProtocol(String),
+    /// The protocol versions and cipher suites do not fit together
+    Config(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Pem { path, error } => {
+                write!(f, "the PEM file {:?} cannot be used: {}", path, error)
+            }
+            TlsError::Key { path, error } => {
+                write!(f, "the private key {:?} cannot be used: {}", path, error)
+            }
+            TlsError::InvalidVersion(version) => {
+                write!(f, "{:?} is no TLS version, use 1.2 or 1.3", version)
+            }
+            TlsError::InvalidCipherSuite(suite) => {
+                write!(f, "the cipher suite {:?} is not supported", suite)
+            }
+            TlsError::InvalidProtocol(protocol) => write!(
+                f,
+                "the application protocol {:?} is not supported, use {}",
+                protocol, HTTP_1_1
+            ),
+            TlsError::Config(error) => write!(f, "TLS cannot be set up: {}", error),
+        }
+    }
+}
+
+/// This struct holds the protocol settings every HTTPS listener shares
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    /// The protocol versions that may be negotiated
+    pub versions: Vec<&'static SupportedProtocolVersion>,
+    /// The cipher suites that may be negotiated, in the order of preference
+    pub cipher_suites: Vec<SupportedCipherSuite>,
+    /// The application protocols offered through ALPN
+    pub protocols: Vec<String>,
+}
+
+impl Default for TlsSettings {
+    fn default() -> TlsSettings {
+        TlsSettings {
+            versions: ALL_VERSIONS.to_vec(),
+            cipher_suites: ring::DEFAULT_CIPHER_SUITES.to_vec(),
+            protocols: vec![HTTP_1_1.to_string()],
+        }
+    }
+}
+
+/// This function parses a TLS protocol version
+///
+/// # Parameters
+///
+/// - `version`: This is the version, either `1.2` or `1.3`, optionally prefixed with `TLS`
+///
+/// # Errors
+///
+/// Returns `TlsError::InvalidVersion` for every other version
+pub fn parse_version(version: &str) -> Result<&'static SupportedProtocolVersion, TlsError> {
+    let number = version.trim_start_matches(|c: char| c.is_ascii_alphabetic() || c == ' ');
+    match number {
+        "1.2" => Ok(&rustls::version::TLS12),
+        "1.3" => Ok(&rustls::version::TLS13),
+        _ => Err(TlsError::InvalidVersion(version.to_string())),
+    }
+}
+
+/// This function looks up a cipher suite by its IANA name, e.g. `TLS13_AES_128_GCM_SHA256`
+///
+/// # Errors
+///
+/// Returns `TlsError::InvalidCipherSuite` if rustls does not implement the cipher suite
+pub fn parse_cipher_suite(name: &str) -> Result<SupportedCipherSuite, TlsError> {
+    ring::ALL_CIPHER_SUITES
+        .iter()
+        .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| TlsError::InvalidCipherSuite(name.to_string()))
+}
+
+/// This function checks an application protocol offered through ALPN
+///
+/// # Errors
+///
+/// Returns `TlsError::InvalidProtocol` for protocols other than HTTP/1.x, which the server cannot
+/// speak
+pub fn parse_protocol(protocol: &str) -> Result<String, TlsError> {
+    match protocol {
+        HTTP_1_1 | "http/1.0" => Ok(protocol.to_string()),
+        _ => Err(TlsError::InvalidProtocol(protocol.to_string())),
+    }
+}
+
+/// This function loads a certificate chain and its private key from PEM files
+///
+/// # Parameters
+///
+/// - `certificate`: This is the PEM file holding the certificate, followed by its intermediates
+/// - `private_key`: This is the PEM file holding the private key in PKCS#1, PKCS#8 or SEC1 form
+///
+/// # Returns
+///
+/// Returns the `CertifiedKey` that is presented to clients
+///
+/// # Errors
+///
+/// Returns a `TlsError` if a file cannot be read, holds no certificate or key, or the key does
+/// not belong to the certificate
+pub fn load_certificate(
+    certificate: &Path,
+    private_key: &Path,
+) -> Result<Arc<CertifiedKey>, TlsError> {
+    let pem_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |error| TlsError::Pem { path, error }
+    };
+    let chain = CertificateDer::pem_file_iter(certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(pem_error(certificate))?;
+    if chain.is_empty() {
+        return Err(pem_error(certificate)(
+            rustls_pki_types::pem::Error::NoItemsFound,
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(private_key).map_err(pem_error(private_key))?;
+
+    let key_error = |error| TlsError::Key {
+        path: private_key.to_path_buf(),
+        error,
+    };
+    let signing_key = ring::sign::any_supported_type(&key).map_err(key_error)?;
+    let certified = CertifiedKey::new(chain, signing_key);
+    certified.keys_match().map_err(key_error)?;
+    Ok(Arc::new(certified))
+}
+
+/// This picks the certificate of the site the client names through SNI, the same way the `Host`
+/// header picks the site. Clients that name no site, or one without a certificate, get the one of
+/// the default server.
+#[derive(Debug)]
+struct CertificateResolver {
+    hosts: Arc<VirtualHosts>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name().map(str::to_ascii_lowercase);
+        self.hosts.certificate(server_name.as_deref())
+    }
+}
+
+/// This function builds the configuration of the HTTPS listeners
+///
+/// # Parameters
+///
+/// - `settings`: This are the protocol versions, cipher suites and application protocols
+/// - `hosts`: This are the sites, whose certificates are picked through SNI
+///
+/// # Returns
+///
+/// Returns the `ServerConfig` every TLS connection is accepted with
+///
+/// # Errors
+///
+/// Returns `TlsError::Config` if none of the cipher suites can be used with the protocol versions
+pub fn server_config(
+    settings: &TlsSettings,
+    hosts: Arc<VirtualHosts>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = CryptoProvider {
+        cipher_suites: settings.cipher_suites.clone(),
+        ..ring::default_provider()
+    };
+    let mut config = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&settings.versions)
+        .map_err(TlsError::Config)?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertificateResolver { hosts }));
+    config.alpn_protocols = settings
+        .protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    Ok(Arc::new(config))
+}
+
+/// This function performs the TLS handshake on a new connection
+///
+/// # Parameters
+///
+/// - `stream`: This is the connection, whose read timeout also limits every read of the handshake
+/// - `config`: This is the configuration built by `server_config()`
+/// - `timeout`: This is how long the whole handshake may take, however slowly the client sends it
+///
+/// # Returns
+///
+/// Returns the encrypted stream, which can be read from and written to like the connection
+///
+/// # Errors
+///
+/// Returns an `io::Error` if the client does not speak TLS, accepts none of the settings,
+/// closes the connection during the handshake or does not finish it in time
+pub fn accept(
+    mut stream: TcpStream,
+    config: &Arc<ServerConfig>,
+    timeout: Duration,
+) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+    let mut connection = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+    let deadline = Instant::now() + timeout;
+    let read_timeout = stream.read_timeout()?;
+    while connection.is_handshaking() {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::new(ErrorKind::TimedOut, "the handshake took too long"))?;
+        // No read may wait past the deadline
+        stream.set_read_timeout(Some(read_timeout.map_or(remaining, |t| t.min(remaining))))?;
+        connection.complete_io(&mut stream)?;
+    }
+    stream.set_read_timeout(read_timeout)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_protocol_settings() {
+        assert_eq!(parse_version("1.3").unwrap(), &rustls::version::TLS13);
+        assert_eq!(parse_version("TLSv1.2").unwrap(), &rustls::version::TLS12);
+        assert!(parse_version("1.1").is_err());
+
+        let suite = parse_cipher_suite("tls13_aes_128_gcm_sha256").unwrap();
+        assert_eq!(suite.version(), &rustls::version::TLS13);
+        assert!(parse_cipher_suite("TLS_RSA_WITH_RC4_128_MD5").is_err());
+
+        assert!(parse_protocol("http/1.1").is_ok());
+        assert!(parse_protocol("h2").is_err());
+    }
+
+    #[test]
+    fn test_rejects_settings_that_do_not_fit_together() {
+        let settings = TlsSettings {
+            versions: vec![&rustls::version::TLS12],
+            cipher_suites: vec![parse_cipher_suite("TLS13_AES_128_GCM_SHA256").unwrap()],
+            ..TlsSettings::default()
+        };
+        let hosts = Arc::new(VirtualHosts::from(
+            crate::http::SiteConfig::new(Path::new("public"), None, Vec::new()).unwrap(),
+        ));
+        assert!(matches!(
+            server_config(&settings, hosts),
+            Err(TlsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_loads_certificates() {
+        let directory = std::env::temp_dir().join(format!("anes-http-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(vec!["other".to_string()]).unwrap();
+        let (certificate, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+        std::fs::write(&certificate, generated.cert.pem()).unwrap();
+        std::fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+
+        assert!(load_certificate(&certificate, &key).is_ok());
+        assert!(matches!(
+            load_certificate(&key, &key),
+            Err(TlsError::Pem { .. })
+        ));
+        std::fs::write(&key, other.key_pair.serialize_pem()).unwrap();
+        assert!(matches!(
+            load_certificate(&certificate, &key),
+            Err(TlsError::Key { .. })
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
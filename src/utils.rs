@@ -1,6 +1,7 @@
-/// Prints a greeting using ASCII-Art to the console. Print this at the start of your program.
+/// Prints a greeting using ASCII-Art to the console. Print this at the start of your program. It
+/// goes to stderr, as stdout may carry the access log, which has to stay one entry per line.
 pub fn greet_user() {
-    println!(r#"
+    eprintln!(r#"
     __    __  __                                            
    / /_  / /_/ /_____        ________  ______   _____  _____
   / __ \/ __/ __/ __ \______/ ___/ _ \/ ___/ | / / _ \/ ___/